@@ -0,0 +1,193 @@
+//! Runs the mock tunnel server and plays a JSON script against whichever
+//! Cyberdriver connects to it, printing each response as a JSON line.
+//!
+//! ```text
+//! cargo run --features mock-tunnel --bin mock-tunnel -- \
+//!   --listen 127.0.0.1:8765 --secret sk_test --script script.json
+//! ```
+//!
+//! A script is a list of steps, run in order:
+//!
+//! ```json
+//! [
+//!   { "reject_handshakes": { "count": 1, "status": 403 } },
+//!   { "request": { "method": "GET", "path": "/computer/display/dimensions" } },
+//!   { "concurrent": [{ "path": "/computer/display/screenshot" },
+//!                    { "path": "/computer/display/dimensions" }] },
+//!   { "request": { "method": "POST", "path": "/computer/input/keyboard/type",
+//!                  "headers": { "X-Idempotency-Key": "k1" }, "json": { "text": "hi" } } },
+//!   "disconnect",
+//!   { "sleep_ms": 500 },
+//!   { "close_policy": "revoked" },
+//!   { "close": { "code": 1000, "reason": "bye" } }
+//! ]
+//! ```
+//!
+//! `--accept-encoding zstd,gzip` lets the client compress responses; they
+//! are printed decompressed.
+//!
+//! Steps that need a client wait for one to connect; after `disconnect`,
+//! `close_policy` or `close` the next such step waits for it to reconnect.
+
+use std::{collections::HashMap, time::Duration};
+
+use cyberdriver_lib::mock_tunnel::{
+  MockRequest, MockResponse, MockTunnelConnection, MockTunnelOptions, MockTunnelServer,
+};
+use serde::Deserialize;
+
+type BoxError = Box<dyn std::error::Error>;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Step {
+  RejectHandshakes { count: usize, status: u16 },
+  Request(ScriptRequest),
+  Concurrent(Vec<ScriptRequest>),
+  SleepMs(u64),
+  Disconnect,
+  ClosePolicy(String),
+  Close { code: u16, reason: String },
+}
+
+/// Without a `method`, requests with a `json` body are POSTs and others GETs.
+#[derive(Deserialize)]
+struct ScriptRequest {
+  method: Option<String>,
+  path: String,
+  query: Option<String>,
+  #[serde(default)]
+  headers: HashMap<String, String>,
+  body: Option<String>,
+  json: Option<serde_json::Value>,
+}
+
+impl ScriptRequest {
+  fn into_request(self) -> MockRequest {
+    let mut request = match self.json {
+      Some(json) => MockRequest::post_json(&self.path, &json),
+      None => MockRequest::get(&self.path),
+    };
+    if let Some(method) = self.method {
+      request.method = method.to_uppercase();
+    }
+    request.query = self.query;
+    request.headers.extend(self.headers);
+    if let Some(body) = self.body {
+      request.body = body.into_bytes();
+    }
+    request
+  }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), BoxError> {
+  let mut listen = "127.0.0.1:8765".to_string();
+  let mut options = MockTunnelOptions::default();
+  let mut script = None;
+  let mut args = std::env::args().skip(1);
+  while let Some(arg) = args.next() {
+    let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+    match arg.as_str() {
+      "--listen" => listen = value()?,
+      "--secret" => options.secret = Some(value()?),
+      "--script" => script = Some(value()?),
+      "--allow-missing-piglet-headers" => options.require_piglet_headers = false,
+      "--accept-encoding" => {
+        let encodings = value()?;
+        options.content_encodings =
+          encodings.split(',').map(|name| name.trim().to_string()).collect();
+      }
+      _ => return Err(format!("Unknown argument '{arg}'").into()),
+    }
+  }
+  let steps: Vec<Step> = match script {
+    Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+    None => vec![Step::Request(ScriptRequest {
+      method: None,
+      path: "/computer/display/dimensions".to_string(),
+      query: None,
+      headers: HashMap::new(),
+      body: None,
+      json: None,
+    })],
+  };
+
+  let mut server = MockTunnelServer::bind(&listen, options).await?;
+  eprintln!("Mock tunnel listening on ws://{}/tunnel/ws", server.local_addr());
+  let mut connection: Option<MockTunnelConnection> = None;
+  for step in steps {
+    match step {
+      Step::RejectHandshakes { count, status } => {
+        server.reject_next_handshakes(count, http::StatusCode::from_u16(status)?).await;
+      }
+      Step::SleepMs(ms) => tokio::time::sleep(Duration::from_millis(ms)).await,
+      Step::Request(request) => {
+        let client = connected(&mut server, &mut connection).await?;
+        print_response(&client.request(&request.into_request()).await?);
+      }
+      Step::Concurrent(requests) => {
+        let client = connected(&mut server, &mut connection).await?;
+        let mut ids = Vec::new();
+        for request in requests {
+          ids.push(client.send_request(&request.into_request()).await?);
+        }
+        for id in ids {
+          print_response(&client.response_for(&id).await?);
+        }
+      }
+      Step::Disconnect => {
+        connected(&mut server, &mut connection).await?;
+        if let Some(client) = connection.take() {
+          client.disconnect();
+        }
+      }
+      Step::ClosePolicy(reason) => {
+        connected(&mut server, &mut connection).await?;
+        if let Some(client) = connection.take() {
+          client.close_policy(&reason).await?;
+        }
+      }
+      Step::Close { code, reason } => {
+        connected(&mut server, &mut connection).await?;
+        if let Some(client) = connection.take() {
+          client.close(code.into(), &reason).await?;
+        }
+      }
+    }
+  }
+  Ok(())
+}
+
+/// The current client, waiting for one to connect if there is none.
+async fn connected<'a>(
+  server: &mut MockTunnelServer,
+  connection: &'a mut Option<MockTunnelConnection>,
+) -> Result<&'a mut MockTunnelConnection, BoxError> {
+  if connection.is_none() {
+    let client = server.accept().await.ok_or("Mock tunnel server stopped")?;
+    let version = client
+      .headers()
+      .get("x-piglet-version")
+      .and_then(|value| value.to_str().ok())
+      .unwrap_or("unknown");
+    eprintln!("Client connected (version {version})");
+    *connection = Some(client);
+  }
+  connection.as_mut().ok_or_else(|| "No client connected".into())
+}
+
+fn print_response(response: &MockResponse) {
+  let body = match response.json() {
+    Ok(json) => json,
+    Err(_) => serde_json::Value::String(response.text()),
+  };
+  let line = serde_json::json!({
+    "requestId": response.request_id,
+    "status": response.status,
+    "headers": response.headers,
+    "contentEncoding": response.content_encoding,
+    "body": body,
+  });
+  println!("{line}");
+}
@@ -112,3 +112,20 @@ pub async fn get_recent_logs(lines: Option<usize>) -> Result<String, String> {
   crate::cyberdriver::read_recent_logs(max_lines)
     .map_err(|err| format!("{err:?}"))
 }
+
+#[tauri::command]
+pub async fn set_remote_control_frozen(app: AppHandle, frozen: bool) -> Result<(), String> {
+  app
+    .state::<Mutex<CyberdriverRuntime>>()
+    .lock()
+    .await
+    .set_remote_control_frozen(frozen)
+    .await;
+  Ok(())
+}
+
+#[tauri::command]
+pub async fn answer_shell_approval(id: String, approved: bool) -> Result<(), String> {
+  crate::cyberdriver::answer_shell_approval(&id, approved);
+  Ok(())
+}
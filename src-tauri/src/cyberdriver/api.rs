@@ -1,24 +1,25 @@
-use std::{path::PathBuf, time::{Duration, Instant}};
+use std::time::{Duration, Instant};
 
 use axum::{
-  extract::{Query, State},
-  http::StatusCode,
+  extract::{DefaultBodyLimit, Query, Request, State},
+  http::{header, Method, StatusCode},
+  middleware::{self, Next},
   response::{IntoResponse, Response},
   routing::{get, post},
   Json, Router,
 };
-use base64::Engine;
 use image::GenericImageView;
-use enigo::{Button, Enigo, Settings};
+use enigo::{Enigo, Settings};
 use serde::Deserialize;
 use tokio::sync::Mutex;
 use tauri::AppHandle;
-use crate::error::CyberdriverError;
 
 use super::{
   config::{Config, ConnectionInfo},
-  diagnostics, input, keepalive::KeepAliveManager, logger::DebugLogger, update,
-  CyberdriverSettings,
+  diagnostics, emergency_stop::EmergencyStop, fs, fs_archive, fs_list, fs_text, fs_watch,
+  input, keepalive::KeepAliveManager, logger::DebugLogger,
+  presence::{LocalPresence, YieldMode}, processes::{self, ProcessJobs}, proxy::ProxySettings, shell,
+  shell_session::ShellSessions, shell_stream, system_processes, update, CyberdriverSettings,
 };
 
 #[derive(Clone)]
@@ -29,6 +30,11 @@ pub struct ApiState {
   pub debug_logger: DebugLogger,
   pub connection_info: std::sync::Arc<Mutex<ConnectionInfo>>,
   pub enigo: std::sync::Arc<Mutex<Enigo>>,
+  pub emergency_stop: EmergencyStop,
+  pub presence: LocalPresence,
+  pub uploads: fs::UploadSessions,
+  pub shell_sessions: std::sync::Arc<ShellSessions>,
+  pub jobs: std::sync::Arc<ProcessJobs>,
   pub app_handle: AppHandle,
 }
 
@@ -40,41 +46,93 @@ impl ApiState {
     settings: std::sync::Arc<Mutex<CyberdriverSettings>>,
     debug_logger: DebugLogger,
     connection_info: std::sync::Arc<Mutex<ConnectionInfo>>,
+    emergency_stop: EmergencyStop,
+    presence: LocalPresence,
   ) -> Self {
-    Self {
+    let state = Self {
       app_handle,
       config,
       keepalive,
       settings,
       debug_logger,
       connection_info,
+      emergency_stop,
+      presence,
+      uploads: Default::default(),
+      shell_sessions: ShellSessions::new(),
+      jobs: ProcessJobs::new(),
       enigo: std::sync::Arc::new(Mutex::new(Enigo::new(&Settings::default()).unwrap())),
+    };
+    tauri::async_runtime::spawn(Self::stop_on_freeze(
+      state.emergency_stop.subscribe(),
+      std::sync::Arc::downgrade(&state.shell_sessions),
+      std::sync::Arc::downgrade(&state.jobs),
+      state.debug_logger.clone(),
+    ));
+    state
+  }
+
+  /// Ends shell sessions and kills running jobs each time the emergency stop
+  /// freezes remote control, until the state they belong to is dropped.
+  async fn stop_on_freeze(
+    mut frozen: tokio::sync::watch::Receiver<bool>,
+    shell_sessions: std::sync::Weak<ShellSessions>,
+    jobs: std::sync::Weak<ProcessJobs>,
+    debug_logger: DebugLogger,
+  ) {
+    while frozen.changed().await.is_ok() {
+      if !*frozen.borrow_and_update() {
+        continue;
+      }
+      let (Some(shell_sessions), Some(jobs)) = (shell_sessions.upgrade(), jobs.upgrade()) else {
+        return;
+      };
+      let sessions = shell_sessions.destroy_all().await;
+      let killed = jobs.kill_running("KILL (emergency stop)").await;
+      debug_logger.log(
+        "CONTROL",
+        "Stopped remote processes",
+        &[("shell_sessions", sessions.to_string()), ("running_jobs", killed.to_string())],
+      );
     }
   }
+
+  /// Ends what remote requests left running: shell sessions, background
+  /// jobs and unfinished uploads.
+  pub async fn shutdown(&self) {
+    let sessions = self.shell_sessions.destroy_all().await;
+    let jobs = self.jobs.kill_all("KILL (shutdown)").await;
+    fs::discard_uploads(&self.uploads).await;
+    self.debug_logger.log(
+      "RUNTIME",
+      "Local API state released",
+      &[("shell_sessions", sessions.to_string()), ("running_jobs", jobs.to_string())],
+    );
+  }
 }
 
 #[derive(Debug)]
-struct ApiError {
+pub(super) struct ApiError {
   status: StatusCode,
   message: String,
 }
 
 impl ApiError {
-  fn bad_request(message: &str) -> Self {
+  pub(super) fn bad_request(message: &str) -> Self {
     Self {
       status: StatusCode::BAD_REQUEST,
       message: message.to_string(),
     }
   }
 
-  fn internal(message: &str) -> Self {
+  pub(super) fn internal(message: &str) -> Self {
     Self {
       status: StatusCode::INTERNAL_SERVER_ERROR,
       message: message.to_string(),
     }
   }
 
-  fn status(status: StatusCode, message: &str) -> Self {
+  pub(super) fn status(status: StatusCode, message: &str) -> Self {
     Self {
       status,
       message: message.to_string(),
@@ -88,7 +146,7 @@ impl IntoResponse for ApiError {
   }
 }
 
-type ApiResult<T> = std::result::Result<T, ApiError>;
+pub(super) type ApiResult<T> = std::result::Result<T, ApiError>;
 
 pub fn router(state: ApiState) -> Router {
   Router::new()
@@ -102,21 +160,141 @@ pub fn router(state: ApiState) -> Router {
     .route("/computer/input/mouse/drag", post(post_mouse_drag))
     .route("/computer/input/mouse/scroll", post(post_mouse_scroll))
     .route("/computer/copy_to_clipboard", post(post_copy_to_clipboard))
-    .route("/computer/fs/list", get(get_fs_list))
-    .route("/computer/fs/read", get(get_fs_read))
-    .route("/computer/fs/write", post(post_fs_write))
-    .route("/computer/shell/powershell/simple", post(post_powershell_simple))
-    .route("/computer/shell/powershell/test", post(post_powershell_test))
-    .route("/computer/shell/powershell/exec", post(post_powershell_exec))
-    .route("/computer/shell/powershell/session", post(post_powershell_session))
+    .route("/computer/fs/list", get(fs_list::get_fs_list))
+    .route("/computer/fs/read", get(fs::get_fs_read))
+    .route("/computer/fs/read/raw", get(fs::get_fs_read_raw))
+    .route("/computer/fs/read/text", get(fs_text::get_fs_read_text))
+    .route("/computer/fs/hash", get(fs::get_fs_hash))
+    .route("/computer/fs/write", post(fs::post_fs_write))
+    .route("/computer/fs/write/raw", post(fs::post_fs_write_raw))
+    .route("/computer/fs/upload", post(fs::post_fs_upload_start))
+    .route(
+      "/computer/fs/upload/:id",
+      get(fs::get_fs_upload).delete(fs::delete_fs_upload),
+    )
+    .route("/computer/fs/upload/:id/chunk", post(fs::post_fs_upload_chunk))
+    .route("/computer/fs/upload/:id/chunk/raw", post(fs::post_fs_upload_chunk_raw))
+    .route("/computer/fs/upload/:id/complete", post(fs::post_fs_upload_complete))
+    .route("/computer/fs/stat", get(fs::get_fs_stat))
+    .route("/computer/fs/mkdir", post(fs::post_fs_mkdir))
+    .route("/computer/fs/move", post(fs::post_fs_move))
+    .route("/computer/fs/copy", post(fs::post_fs_copy))
+    .route("/computer/fs/delete", post(fs::post_fs_delete))
+    .route("/computer/fs/glob", get(fs::get_fs_glob))
+    .route("/computer/fs/search", get(fs_text::get_fs_search))
+    .route("/computer/fs/archive", post(fs_archive::post_fs_archive))
+    .route("/computer/fs/extract", post(fs_archive::post_fs_extract))
+    .route("/computer/fs/wait", post(fs_watch::post_fs_wait))
+    .route("/computer/shell/powershell/simple", post(shell::post_powershell_simple))
+    .route("/computer/shell/powershell/test", post(shell::post_powershell_test))
+    .route("/computer/shell/powershell/exec", post(shell::post_powershell_exec))
+    .route(
+      "/computer/shell/powershell/exec/stream",
+      post(shell_stream::post_powershell_exec_stream),
+    )
+    .route("/computer/shell/powershell/session", post(shell::post_powershell_session))
+    .route("/computer/shell/sessions", get(shell::get_shell_sessions))
+    .route("/computer/processes", get(processes::get_processes))
+    .route("/computer/processes/start", post(processes::post_process_start))
+    .route(
+      "/computer/processes/:id",
+      get(processes::get_process).delete(processes::delete_process),
+    )
+    .route("/computer/processes/:id/output", get(processes::get_process_output))
+    .route("/computer/processes/:id/stdin", post(processes::post_process_stdin))
+    .route("/computer/processes/:id/signal", post(processes::post_process_signal))
+    .route("/computer/processes/:id/kill", post(processes::post_process_kill))
+    .route("/computer/processes/:id/wait", post(processes::post_process_wait))
+    .route("/computer/system/processes", get(system_processes::get_system_processes))
+    .route(
+      "/computer/system/processes/:pid/kill",
+      post(system_processes::post_system_process_kill),
+    )
     .route("/internal/diagnostics", get(get_diagnostics))
     .route("/internal/update", post(post_update))
     .route("/internal/keepalive/remote/activity", post(post_keepalive_activity))
     .route("/internal/keepalive/remote/enable", post(post_keepalive_enable))
     .route("/internal/keepalive/remote/disable", post(post_keepalive_disable))
+    .layer(DefaultBodyLimit::max(fs::MAX_REQUEST_BODY_BYTES))
+    .layer(middleware::from_fn_with_state(state.clone(), local_user_guard))
+    .layer(middleware::from_fn_with_state(state.clone(), emergency_stop_guard))
     .with_state(state)
 }
 
+async fn emergency_stop_guard(
+  State(state): State<ApiState>,
+  request: Request,
+  next: Next,
+) -> Response {
+  if state.emergency_stop.is_frozen() && EmergencyStop::is_locked_path(request.uri().path()) {
+    state.debug_logger.log(
+      "CONTROL",
+      "Rejected while frozen",
+      &[("path", request.uri().path().to_string())],
+    );
+    return ApiError::status(
+      StatusCode::LOCKED,
+      "Remote control is frozen by the local user (emergency stop)",
+    )
+    .into_response();
+  }
+  next.run(request).await
+}
+
+/// Endpoints that synthesize input and therefore compete with the local user.
+const SYNTHETIC_INPUT_PREFIXES: &[&str] = &["/computer/input/", "/computer/copy_to_clipboard"];
+
+async fn local_user_guard(
+  State(state): State<ApiState>,
+  request: Request,
+  next: Next,
+) -> Response {
+  let path = request.uri().path();
+  if request.method() != Method::POST
+    || !SYNTHETIC_INPUT_PREFIXES.iter().any(|prefix| path.starts_with(prefix))
+  {
+    return next.run(request).await;
+  }
+  if state.presence.should_yield() {
+    let path = path.to_string();
+    let idle = match state.presence.mode() {
+      YieldMode::Queue => state.presence.wait_until_idle().await,
+      YieldMode::Reject => false,
+    };
+    if state.emergency_stop.is_frozen() && EmergencyStop::is_locked_path(&path) {
+      state.debug_logger.log("CONTROL", "Rejected while frozen", &[("path", path)]);
+      return ApiError::status(
+        StatusCode::LOCKED,
+        "Remote control is frozen by the local user (emergency stop)",
+      )
+      .into_response();
+    }
+    if !idle {
+      let retry_after = state
+        .presence
+        .remaining_active()
+        .map(|remaining| remaining.as_secs_f64().ceil() as u64)
+        .unwrap_or(0)
+        .max(1);
+      state.debug_logger.log(
+        "CONTROL",
+        "Yielded to local user",
+        &[("path", path), ("retry_after", retry_after.to_string())],
+      );
+      return (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        Json(serde_json::json!({
+          "error": "Local user is active; remote input paused",
+          "retry_after": retry_after,
+        })),
+      )
+        .into_response();
+    }
+  }
+  next.run(request).await
+}
+
 #[derive(Deserialize)]
 struct ScreenshotQuery {
   width: Option<u32>,
@@ -267,7 +445,8 @@ async fn post_keyboard_type(
     return Err(ApiError::bad_request("Missing 'text' field"));
   }
   let settings = state.settings.lock().await.clone();
-  input::type_text(&state.enigo, &payload.text, settings.experimental_space)
+  let cancel = state.emergency_stop.input_token();
+  input::type_text(&state.enigo, &payload.text, settings.experimental_space, &cancel)
     .await
     .map_err(|err| ApiError::internal(&err.to_string()))?;
   Ok(Json(serde_json::json!({})))
@@ -286,7 +465,14 @@ async fn post_keyboard_key(
     &[("sequence", payload.text.clone())],
   );
   let settings = state.settings.lock().await.clone();
-  input::execute_xdo_sequence(&state.app_handle, &state.enigo, &payload.text, settings.experimental_space)
+  let cancel = state.emergency_stop.input_token();
+  input::execute_xdo_sequence(
+    &state.app_handle,
+    &state.enigo,
+    &payload.text,
+    settings.experimental_space,
+    &cancel,
+  )
     .await
     .map_err(|err| ApiError::internal(&err.to_string()))?;
   Ok(Json(serde_json::json!({})))
@@ -308,7 +494,14 @@ async fn post_copy_to_clipboard(
     }
   }).await;
 
-  input::execute_xdo_sequence(&state.app_handle, &state.enigo, "ctrl+c", settings.experimental_space)
+  let cancel = state.emergency_stop.input_token();
+  input::execute_xdo_sequence(
+    &state.app_handle,
+    &state.enigo,
+    "ctrl+c",
+    settings.experimental_space,
+    &cancel,
+  )
     .await
     .map_err(|err| ApiError::internal(&err.to_string()))?;
 
@@ -364,18 +557,15 @@ struct MouseClickPayload {
   button: Option<String>,
   down: Option<bool>,
   clicks: Option<u8>,
+  duration: Option<f64>,
 }
 
 async fn post_mouse_click(
   State(state): State<ApiState>,
   Json(payload): Json<MouseClickPayload>,
 ) -> ApiResult<Json<serde_json::Value>> {
-  let button = match payload.button.as_deref().unwrap_or("left") {
-    "left" => Button::Left,
-    "right" => Button::Right,
-    "middle" => Button::Middle,
-    _ => return Err(ApiError::bad_request("Invalid button")),
-  };
+  let button = input::parse_button(payload.button.as_deref().unwrap_or("left"))
+    .ok_or_else(|| ApiError::bad_request("Invalid button"))?;
   state.debug_logger.log(
     "INPUT",
     "Mouse click",
@@ -397,9 +587,33 @@ async fn post_mouse_click(
           .map(|v| v.to_string())
           .unwrap_or_else(|| "default".into()),
       ),
+      (
+        "duration",
+        payload
+          .duration
+          .map(|v| v.to_string())
+          .unwrap_or_else(|| "none".into()),
+      ),
     ],
   );
-  if let Some(down) = payload.down {
+  if let Some(duration) = payload.duration {
+    if !(duration > 0.0 && duration <= input::MAX_INPUT_DURATION.as_secs_f64()) {
+      return Err(ApiError::bad_request(&format!(
+        "duration must be between 0 and {} seconds",
+        input::MAX_INPUT_DURATION.as_secs()
+      )));
+    }
+    input::mouse_hold(
+      &state.enigo,
+      payload.x,
+      payload.y,
+      button,
+      Duration::from_secs_f64(duration),
+      &state.emergency_stop.input_token(),
+    )
+    .await
+    .map_err(|err| ApiError::internal(&err.to_string()))?;
+  } else if let Some(down) = payload.down {
     input::mouse_click(&state.enigo, payload.x, payload.y, button, down, !down, 0)
     .await
     .map_err(|err| ApiError::internal(&err.to_string()))?;
@@ -433,12 +647,8 @@ async fn post_mouse_drag(
   State(state): State<ApiState>,
   Json(payload): Json<MouseDragPayload>,
 ) -> ApiResult<Json<serde_json::Value>> {
-  let button = match payload.button.as_deref().unwrap_or("left") {
-    "left" => Button::Left,
-    "right" => Button::Right,
-    "middle" => Button::Middle,
-    _ => return Err(ApiError::bad_request("Invalid button")),
-  };
+  let button = input::parse_button(payload.button.as_deref().unwrap_or("left"))
+    .ok_or_else(|| ApiError::bad_request("Invalid button"))?;
   let end_x = payload
     .to_x
     .or(payload.x)
@@ -457,12 +667,11 @@ async fn post_mouse_drag(
     .ok_or_else(|| ApiError::bad_request("Missing or invalid start coordinates"))?;
   input::mouse_drag(
     &state.enigo,
-    start_x,
-    start_y,
-    end_x,
-    end_y,
+    (start_x, start_y),
+    (end_x, end_y),
     button,
     payload.duration,
+    &state.emergency_stop.input_token(),
   )
     .await
     .map_err(|err| ApiError::internal(&err.to_string()))?;
@@ -475,8 +684,18 @@ struct MouseScrollPayload {
   amount: i32,
   x: Option<i32>,
   y: Option<i32>,
+  unit: Option<String>,
+  smooth: Option<bool>,
+  until_stable: Option<bool>,
+  max_steps: Option<u32>,
+  settle_ms: Option<u64>,
 }
 
+const DEFAULT_SCROLL_MAX_STEPS: u32 = 50;
+const MAX_SCROLL_MAX_STEPS: u32 = 500;
+const DEFAULT_SCROLL_SETTLE_MS: u64 = 250;
+const MAX_SCROLL_SETTLE_MS: u64 = 2000;
+
 async fn post_mouse_scroll(
   State(state): State<ApiState>,
   Json(payload): Json<MouseScrollPayload>,
@@ -484,241 +703,61 @@ async fn post_mouse_scroll(
   if payload.amount < 0 {
     return Err(ApiError::bad_request("'amount' must be non-negative"));
   }
-  input::mouse_scroll(
-    &state.enigo,
-    payload.direction.to_lowercase().as_str(),
-    payload.amount,
-    payload.x,
-    payload.y,
-  )
-  .await
-  .map_err(|err| ApiError::internal(&err.to_string()))?;
-  Ok(Json(serde_json::json!({})))
-}
-
-#[derive(Deserialize)]
-struct FsListQuery {
-  path: Option<String>,
-}
-
-async fn get_fs_list(
-  Query(query): Query<FsListQuery>,
-) -> ApiResult<Json<serde_json::Value>> {
-  let path = query.path.unwrap_or_else(|| ".".to_string());
-  let safe_path = PathBuf::from(path).expand_dir();
-  if !safe_path.exists() {
-    return Err(ApiError::status(StatusCode::NOT_FOUND, "Directory not found"));
-  }
-  if !safe_path.is_dir() {
-    return Err(ApiError::bad_request("Path is not a directory"));
-  }
-  let mut entries = Vec::new();
-  for item in std::fs::read_dir(&safe_path).map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to list directory"))? {
-    if let Ok(item) = item {
-      let path = item.path();
-      let name = item.file_name().to_string_lossy().to_string();
-      let meta = item.metadata();
-      let is_dir = meta.as_ref().map(|m| m.is_dir()).unwrap_or(false);
-      let size = meta.as_ref().ok().and_then(|m| if m.is_file() { Some(m.len()) } else { None });
-      let modified = meta.ok().and_then(|m| m.modified().ok()).and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok()).map(|d| d.as_secs_f64());
-      entries.push(serde_json::json!({
-        "name": name,
-        "path": path.to_string_lossy(),
-        "is_dir": is_dir,
-        "size": size,
-        "modified": modified,
-      }));
-    }
-  }
-  entries.sort_by(|a, b| {
-    let a_dir = a.get("is_dir").and_then(|v| v.as_bool()).unwrap_or(false);
-    let b_dir = b.get("is_dir").and_then(|v| v.as_bool()).unwrap_or(false);
-    a_dir
-      .cmp(&b_dir)
-      .reverse()
-      .then_with(|| a.get("name").unwrap().as_str().cmp(&b.get("name").unwrap().as_str()))
-  });
-  Ok(Json(serde_json::json!({ "path": safe_path.to_string_lossy(), "entries": entries })))
-}
-
-#[derive(Deserialize)]
-struct FsReadQuery {
-  path: String,
-}
-
-async fn get_fs_read(
-  Query(query): Query<FsReadQuery>,
-) -> ApiResult<Json<serde_json::Value>> {
-  let safe_path = PathBuf::from(query.path).expand_dir();
-  if !safe_path.exists() {
-    return Err(ApiError::status(StatusCode::NOT_FOUND, "File not found"));
-  }
-  if !safe_path.is_file() {
-    return Err(ApiError::bad_request("Path is not a file"));
-  }
-  let meta = safe_path.metadata().map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to read file"))?;
-  if meta.len() > 100 * 1024 * 1024 {
-    return Err(ApiError::status(StatusCode::PAYLOAD_TOO_LARGE, "File too large (>100MB)"));
-  }
-  let content = tokio::fs::read(&safe_path)
-    .await
-    .map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to read file"))?;
-  Ok(Json(serde_json::json!({
-    "path": safe_path.to_string_lossy(),
-    "content": base64::engine::general_purpose::STANDARD.encode(content),
-    "size": meta.len(),
-  })))
-}
-
-#[derive(Deserialize)]
-struct FsWritePayload {
-  path: String,
-  content: String,
-  mode: Option<String>,
-}
-
-async fn post_fs_write(
-  Json(payload): Json<FsWritePayload>,
-) -> ApiResult<Json<serde_json::Value>> {
-  if payload.path.is_empty() {
-    return Err(ApiError::bad_request("Missing 'path' field"));
-  }
-  if payload.content.is_empty() {
-    return Err(ApiError::bad_request("Missing 'content' field"));
-  }
-  let file_data = base64::engine::general_purpose::STANDARD
-    .decode(payload.content)
-    .map_err(|_| ApiError::bad_request("Invalid base64 content"))?;
-  let mut safe_path = PathBuf::from(payload.path).expand_dir();
-  if safe_path.parent().map(|p| p == std::path::Path::new(".")).unwrap_or(false) {
-    safe_path = dirs::home_dir()
-      .unwrap_or_else(|| PathBuf::from("."))
-      .join("CyberdeskTransfers")
-      .join(safe_path.file_name().unwrap());
-  }
-  if let Some(parent) = safe_path.parent() {
-    let _ = tokio::fs::create_dir_all(parent).await;
-  }
-  let write_mode = payload.mode.unwrap_or_else(|| "write".to_string());
-  if write_mode == "append" {
-    use tokio::io::AsyncWriteExt;
-    let mut file = tokio::fs::OpenOptions::new()
-      .create(true)
-      .append(true)
-      .open(&safe_path)
-      .await
-      .map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to write file"))?;
-    file
-      .write_all(&file_data)
-      .await
-      .map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to write file"))?;
-  } else {
-    tokio::fs::write(&safe_path, file_data)
+  let unit = match payload.unit.as_deref() {
+    Some(unit) => input::ScrollUnit::from_str(unit)
+      .ok_or_else(|| ApiError::bad_request("'unit' must be 'notch' or 'pixel'"))?,
+    None => input::ScrollUnit::Notch,
+  };
+  let direction = payload.direction.to_lowercase();
+  let step = input::ScrollStep {
+    direction: direction.as_str(),
+    amount: payload.amount,
+    unit,
+    smooth: payload.smooth.unwrap_or(false),
+    x: payload.x,
+    y: payload.y,
+  };
+  let cancel = state.emergency_stop.input_token();
+  if !payload.until_stable.unwrap_or(false) {
+    input::mouse_scroll(&state.enigo, &step, &cancel)
       .await
-      .map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to write file"))?;
+      .map_err(|err| ApiError::internal(&err.to_string()))?;
+    return Ok(Json(serde_json::json!({})));
   }
-  Ok(Json(serde_json::json!({})))
-}
-
-async fn post_powershell_simple() -> ApiResult<Json<serde_json::Value>> {
-  let output = if cfg!(windows) {
-    std::process::Command::new("powershell")
-      .args(["-NoProfile", "-Command", "Write-Output 'Hello World'"])
-      .output()
-  } else {
-    std::process::Command::new("/bin/sh")
-      .args(["-c", "printf 'Hello World'"])
-      .output()
-  }
-  .map_err(|err| ApiError::internal(&err.to_string()))?;
-  Ok(Json(serde_json::json!({
-    "returncode": output.status.code().unwrap_or(0),
-    "stdout": truncate_output(String::from_utf8_lossy(&output.stdout).to_string()),
-    "stderr": truncate_output(String::from_utf8_lossy(&output.stderr).to_string()),
-  })))
-}
 
-async fn post_powershell_test() -> ApiResult<Json<serde_json::Value>> {
-  let output = if cfg!(windows) {
-    std::process::Command::new("powershell")
-      .args(["-NoLogo", "-NoProfile", "-NonInteractive", "-ExecutionPolicy", "Bypass", "-Command"])
-      .arg("Write-Output \"Hello from PowerShell\"")
-      .output()
-  } else {
-    std::process::Command::new("/bin/sh")
-      .args(["-c", "printf 'Hello from shell'"])
-      .output()
+  if payload.amount == 0 {
+    return Err(ApiError::bad_request("'amount' must be positive when 'until_stable' is set"));
   }
-  .map_err(|err| ApiError::internal(&err.to_string()))?;
+  let settle_ms = payload
+    .settle_ms
+    .unwrap_or(DEFAULT_SCROLL_SETTLE_MS)
+    .min(MAX_SCROLL_SETTLE_MS);
+  // Every step waits `settle_ms`, and all steps have to fit in the input time limit.
+  let fitting_steps = (input::MAX_INPUT_DURATION.as_millis() as u64 / settle_ms.max(1)).max(1);
+  let max_steps = payload
+    .max_steps
+    .unwrap_or(DEFAULT_SCROLL_MAX_STEPS)
+    .clamp(1, MAX_SCROLL_MAX_STEPS)
+    .min(fitting_steps.min(u32::MAX as u64) as u32);
+  let settle = Duration::from_millis(settle_ms);
+  let result = input::scroll_until_stable(&state.enigo, &step, max_steps, settle, &cancel)
+    .await
+    .map_err(|err| ApiError::internal(&err.to_string()))?;
+  state.debug_logger.log(
+    "INPUT",
+    "Scroll until stable",
+    &[
+      ("direction", direction.clone()),
+      ("steps", result.steps.to_string()),
+      ("stable", result.stable.to_string()),
+    ],
+  );
   Ok(Json(serde_json::json!({
-    "returncode": output.status.code().unwrap_or(0),
-    "stdout": truncate_output(String::from_utf8_lossy(&output.stdout).to_string()),
-    "stderr": truncate_output(String::from_utf8_lossy(&output.stderr).to_string()),
+    "steps": result.steps,
+    "stable": result.stable,
   })))
 }
 
-#[derive(Deserialize)]
-struct PowerShellExecPayload {
-  command: String,
-  #[allow(dead_code)]
-  same_session: Option<bool>,
-  working_directory: Option<String>,
-  session_id: Option<String>,
-  timeout: Option<f64>,
-}
-
-async fn post_powershell_exec(
-  Json(payload): Json<PowerShellExecPayload>,
-) -> ApiResult<Json<serde_json::Value>> {
-  if payload.command.is_empty() {
-    return Err(ApiError::bad_request("Missing 'command' field"));
-  }
-  let timeout = payload.timeout.unwrap_or(30.0);
-  let working_directory = payload.working_directory.clone();
-  let command = payload.command.clone();
-  let session_id = payload.session_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
-  let result: std::result::Result<CommandResult, CyberdriverError> =
-    tokio::task::spawn_blocking(move || {
-      execute_shell_command(&command, working_directory.as_deref(), timeout)
-    })
-    .await
-    .unwrap_or_else(|err| Err(CyberdriverError::RuntimeError(err.to_string())));
-
-  match result {
-    Ok(result) => Ok(Json(serde_json::json!({
-      "stdout": truncate_output(result.stdout),
-      "stderr": truncate_output(result.stderr),
-      "exit_code": result.exit_code,
-      "session_id": session_id,
-      "timeout_reached": result.timeout_reached,
-    }))),
-    Err(err) => Err(ApiError::internal(&err.to_string())),
-  }
-}
-
-#[derive(Deserialize)]
-struct PowerShellSessionPayload {
-  action: String,
-  #[allow(dead_code)]
-  session_id: Option<String>,
-}
-
-async fn post_powershell_session(
-  Json(payload): Json<PowerShellSessionPayload>,
-) -> ApiResult<Json<serde_json::Value>> {
-  if payload.action != "create" && payload.action != "destroy" {
-    return Err(ApiError::bad_request("Invalid action. Must be 'create' or 'destroy'"));
-  }
-  if payload.action == "create" {
-    Ok(Json(serde_json::json!({
-      "session_id": uuid::Uuid::new_v4().to_string(),
-      "message": "Session ID generated (sessions are stateless)"
-    })))
-  } else {
-    Ok(Json(serde_json::json!({ "message": "Session destroyed (no-op in stateless mode)" })))
-  }
-}
-
 async fn get_diagnostics() -> ApiResult<Json<serde_json::Value>> {
   Ok(Json(diagnostics::collect()))
 }
@@ -760,9 +799,12 @@ async fn post_update(
   State(state): State<ApiState>,
   Json(payload): Json<update::UpdateRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-  let response = update::handle_update(payload, &state.connection_info, &state.config.version)
-    .await
+  let proxy = ProxySettings::from_settings(&*state.settings.lock().await)
     .map_err(|err| ApiError::internal(&err.to_string()))?;
+  let response =
+    update::handle_update(payload, &state.connection_info, &state.config.version, &proxy)
+      .await
+      .map_err(|err| ApiError::internal(&err.to_string()))?;
   let app = state.app_handle.clone();
   tauri::async_runtime::spawn(async move {
     tokio::time::sleep(Duration::from_secs(2)).await;
@@ -771,62 +813,6 @@ async fn post_update(
   Ok(Json(serde_json::to_value(response).unwrap_or_else(|_| serde_json::json!({}))))
 }
 
-#[derive(Debug)]
-struct CommandResult {
-  stdout: String,
-  stderr: String,
-  exit_code: i32,
-  timeout_reached: bool,
-}
-
-fn execute_shell_command(
-  command: &str,
-  working_dir: Option<&str>,
-  timeout: f64,
-) -> std::result::Result<CommandResult, CyberdriverError> {
-  let mut cmd = if cfg!(windows) {
-    let mut cmd = std::process::Command::new("powershell");
-    cmd.args(["-NoLogo", "-NoProfile", "-NonInteractive", "-ExecutionPolicy", "Bypass"])
-      .arg("-Command")
-      .arg(command);
-    cmd
-  } else {
-    let mut cmd = std::process::Command::new("/bin/sh");
-    cmd.args(["-c", command]);
-    cmd
-  };
-  if let Some(dir) = working_dir {
-    cmd.current_dir(dir);
-  }
-  let child = cmd
-    .stdout(std::process::Stdio::piped())
-    .stderr(std::process::Stdio::piped())
-    .spawn()
-    .map_err(|err| CyberdriverError::RuntimeError(err.to_string()))?;
-
-  let (tx, rx) = std::sync::mpsc::channel();
-  std::thread::spawn(move || {
-    let output = child.wait_with_output();
-    let _ = tx.send(output);
-  });
-  match rx.recv_timeout(Duration::from_secs_f64(timeout.max(1.0))) {
-    Ok(Ok(output)) => Ok(CommandResult {
-      stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-      stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-      exit_code: output.status.code().unwrap_or(-1),
-      timeout_reached: false,
-    }),
-    _ => Ok(CommandResult {
-      stdout: String::new(),
-      stderr: format!(
-        "Command timeout reached after {timeout} seconds. Process continues in background."
-      ),
-      exit_code: 0,
-      timeout_reached: true,
-    }),
-  }
-}
-
 struct ScreenshotMetrics {
   capture_ms: f64,
   resize_ms: f64,
@@ -935,6 +921,12 @@ struct CaptureImageResult {
   backend: ScreenshotBackend,
 }
 
+/// The whole screen, captured from the same monitor and backend as
+/// `/computer/display/screenshot`.
+pub(super) fn capture_display() -> std::result::Result<image::DynamicImage, String> {
+  capture_backend_image(select_backend(), None).map(|capture| capture.image)
+}
+
 fn capture_backend_image(
   backend: ScreenshotBackend,
   target: Option<(u32, u32)>,
@@ -1112,30 +1104,3 @@ fn get_logical_dimensions() -> Option<(u32, u32)> {
   let height = monitor.height().ok()?;
   Some((width, height))
 }
-
-trait ExpandPath {
-  fn expand_dir(self) -> PathBuf;
-}
-
-impl ExpandPath for PathBuf {
-  fn expand_dir(self) -> PathBuf {
-    if let Some(str_path) = self.to_str() {
-      if str_path.starts_with("~") {
-        if let Some(home) = dirs::home_dir() {
-          return PathBuf::from(str_path.replacen("~", home.to_string_lossy().as_ref(), 1));
-        }
-      }
-    }
-    self
-  }
-}
-
-fn truncate_output(output: String) -> String {
-  let max = 15_000;
-  if output.len() <= max {
-    return output;
-  }
-  let head = &output[..max / 2];
-  let tail = &output[output.len() - max / 2..];
-  format!("{head}\n... (truncated) ...\n{tail}")
-}
@@ -0,0 +1,157 @@
+use std::sync::{Arc, PoisonError};
+
+use tauri::{AppHandle, Emitter};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, ShortcutState};
+use tauri_plugin_notification::NotificationExt;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{CyberdriverError, Result};
+
+use super::{input, logger::DebugLogger};
+
+pub const DEFAULT_EMERGENCY_STOP_SHORTCUT: &str = "CommandOrControl+Shift+F12";
+
+/// Path prefixes that are rejected with 423 Locked while remote control is frozen.
+pub const LOCKED_PATH_PREFIXES: &[&str] = &[
+  "/computer/input/",
+  "/computer/copy_to_clipboard",
+  "/computer/fs/",
+  "/computer/shell/",
+  "/computer/processes",
+  "/computer/system/processes/",
+];
+
+/// Local kill switch for remote control. Freezing rejects input, fs, shell and
+/// process endpoints, cancels input already running, releases anything the
+/// remote side may be holding down and tells the person at the machine what
+/// happened. Shell sessions and jobs are stopped by whoever
+/// [subscribes](Self::subscribe) to the frozen state.
+#[derive(Clone)]
+pub struct EmergencyStop {
+  app: AppHandle,
+  frozen: Arc<watch::Sender<bool>>,
+  /// Cancelled when remote control is frozen, and replaced on resume.
+  input: Arc<std::sync::Mutex<CancellationToken>>,
+  shortcut: Arc<std::sync::Mutex<Option<String>>>,
+  debug_logger: DebugLogger,
+}
+
+impl EmergencyStop {
+  pub fn new(app: AppHandle, debug_logger: DebugLogger) -> Self {
+    Self {
+      app,
+      frozen: Arc::new(watch::Sender::new(false)),
+      input: Arc::new(std::sync::Mutex::new(CancellationToken::new())),
+      shortcut: Arc::new(std::sync::Mutex::new(None)),
+      debug_logger,
+    }
+  }
+
+  pub fn is_frozen(&self) -> bool {
+    *self.frozen.borrow()
+  }
+
+  /// Follows the frozen state.
+  pub fn subscribe(&self) -> watch::Receiver<bool> {
+    self.frozen.subscribe()
+  }
+
+  /// A token that is cancelled when remote control is frozen, for input to
+  /// check while it runs. It is already cancelled while frozen.
+  pub fn input_token(&self) -> CancellationToken {
+    self.input.lock().unwrap_or_else(PoisonError::into_inner).clone()
+  }
+
+  pub fn is_locked_path(path: &str) -> bool {
+    LOCKED_PATH_PREFIXES
+      .iter()
+      .any(|prefix| path.starts_with(prefix))
+  }
+
+  pub async fn set_frozen(&self, frozen: bool) {
+    {
+      let mut input = self.input.lock().unwrap_or_else(PoisonError::into_inner);
+      if self.frozen.send_replace(frozen) == frozen {
+        return;
+      }
+      if frozen {
+        input.cancel();
+      } else {
+        *input = CancellationToken::new();
+      }
+    }
+    if frozen {
+      self.debug_logger.info("CONTROL", "Remote control frozen");
+      if let Err(err) = tokio::task::spawn_blocking(input::release_all)
+        .await
+        .unwrap_or_else(|err| Err(CyberdriverError::RuntimeError(err.to_string())))
+      {
+        self.debug_logger.log(
+          "CONTROL",
+          "Failed to release held input",
+          &[("error", err.to_string())],
+        );
+      }
+      self.notify(
+        "Remote control stopped",
+        "Cyberdriver is rejecting remote input. Press the shortcut again or use Resume to continue.",
+      );
+    } else {
+      self.debug_logger.info("CONTROL", "Remote control resumed");
+      self.notify("Remote control resumed", "Cyberdriver is accepting remote input again.");
+    }
+    let _ = self.app.emit("remote-control-frozen", frozen);
+  }
+
+  pub async fn toggle(&self) {
+    self.set_frozen(!self.is_frozen()).await;
+  }
+
+  /// Registers `shortcut` as the global emergency-stop hotkey, replacing any
+  /// previously registered one. An empty string disables the hotkey.
+  pub fn register_shortcut(&self, shortcut: &str) -> Result<()> {
+    let shortcut = shortcut.trim();
+    let mut current = self.shortcut.lock()?;
+    if current.as_deref() == Some(shortcut) {
+      return Ok(());
+    }
+    let manager = self.app.global_shortcut();
+    if let Some(previous) = current.take() {
+      let _ = manager.unregister(previous.as_str());
+    }
+    if shortcut.is_empty() {
+      return Ok(());
+    }
+    let stop = self.clone();
+    manager
+      .on_shortcut(shortcut, move |_app, _shortcut, event| {
+        if event.state == ShortcutState::Pressed {
+          let stop = stop.clone();
+          tauri::async_runtime::spawn(async move {
+            stop.toggle().await;
+          });
+        }
+      })
+      .map_err(|err| {
+        CyberdriverError::RuntimeError(format!("Failed to register shortcut `{shortcut}`: {err}"))
+      })?;
+    *current = Some(shortcut.to_string());
+    self.debug_logger.log(
+      "CONTROL",
+      "Emergency stop shortcut registered",
+      &[("shortcut", shortcut.to_string())],
+    );
+    Ok(())
+  }
+
+  fn notify(&self, title: &str, body: &str) {
+    let _ = self
+      .app
+      .notification()
+      .builder()
+      .title(title)
+      .body(body)
+      .show();
+  }
+}
@@ -0,0 +1,1171 @@
+use std::{
+  collections::HashMap,
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::{Duration, Instant},
+};
+
+use axum::{
+  body::{Body, Bytes},
+  extract::{Path as UrlPath, Query, State},
+  http::{header, StatusCode},
+  response::{IntoResponse, Response},
+  Json,
+};
+use base64::Engine;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::{
+  io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+  sync::Mutex,
+};
+use tokio_util::io::ReaderStream;
+
+use super::{
+  api::{ApiError, ApiResult, ApiState},
+  fs_policy::FsPolicy,
+};
+
+/// Largest file returned in one piece by `/computer/fs/read` without an explicit range.
+const MAX_INLINE_READ_BYTES: u64 = 100 * 1024 * 1024;
+/// Largest single ranged read or upload chunk.
+const MAX_CHUNK_BYTES: u64 = 32 * 1024 * 1024;
+/// Request body limit for the local API; fits a base64-encoded maximum chunk.
+pub(super) const MAX_REQUEST_BODY_BYTES: usize = 48 * 1024 * 1024;
+/// Uploads that receive nothing for this long are discarded.
+const UPLOAD_SESSION_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+const HASH_BUFFER_BYTES: usize = 256 * 1024;
+/// Largest `/computer/fs/read/raw` range that is buffered to send its SHA-256 as a header.
+const MAX_HASHED_RAW_READ_BYTES: u64 = 8 * 1024 * 1024;
+
+pub(super) type UploadSessions = Arc<Mutex<HashMap<String, Arc<Mutex<UploadSession>>>>>;
+
+pub(super) struct UploadSession {
+  path: PathBuf,
+  temp_path: PathBuf,
+  received: u64,
+  expected_size: Option<u64>,
+  expected_sha256: Option<String>,
+  hasher: Sha256,
+  updated: Instant,
+  finished: bool,
+}
+
+#[derive(Deserialize)]
+pub(super) struct FsReadQuery {
+  path: String,
+  offset: Option<u64>,
+  length: Option<u64>,
+}
+
+/// A validated byte range within an existing file.
+struct ReadRange {
+  path: PathBuf,
+  file_size: u64,
+  offset: u64,
+  length: u64,
+}
+
+impl ReadRange {
+  fn resolve(policy: &FsPolicy, query: FsReadQuery, max_length: u64) -> ApiResult<Self> {
+    let path = policy.readable(query.path)?;
+    if !path.exists() {
+      return Err(ApiError::status(StatusCode::NOT_FOUND, "File not found"));
+    }
+    if !path.is_file() {
+      return Err(ApiError::bad_request("Path is not a file"));
+    }
+    let file_size = path
+      .metadata()
+      .map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to read file"))?
+      .len();
+    let offset = query.offset.unwrap_or(0);
+    if offset > file_size {
+      return Err(ApiError::status(
+        StatusCode::RANGE_NOT_SATISFIABLE,
+        &format!("offset {offset} is beyond the end of the file ({file_size} bytes)"),
+      ));
+    }
+    let remaining = file_size - offset;
+    let length = match query.length {
+      Some(length) if length > MAX_CHUNK_BYTES => {
+        return Err(ApiError::bad_request(&format!(
+          "length must be at most {MAX_CHUNK_BYTES} bytes"
+        )));
+      }
+      Some(length) => length.min(remaining),
+      None if remaining > max_length => {
+        return Err(ApiError::status(
+          StatusCode::PAYLOAD_TOO_LARGE,
+          "File too large (>100MB); use offset and length to read it in chunks",
+        ));
+      }
+      None => remaining,
+    };
+    Ok(Self {
+      path,
+      file_size,
+      offset,
+      length,
+    })
+  }
+
+  async fn open(&self) -> ApiResult<tokio::io::Take<tokio::fs::File>> {
+    let mut file = tokio::fs::File::open(&self.path)
+      .await
+      .map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to read file"))?;
+    file
+      .seek(std::io::SeekFrom::Start(self.offset))
+      .await
+      .map_err(|err| ApiError::internal(&err.to_string()))?;
+    Ok(file.take(self.length))
+  }
+}
+
+pub(super) async fn get_fs_read(
+  State(state): State<ApiState>,
+  Query(query): Query<FsReadQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let policy = FsPolicy::load(&state).await;
+  let range = ReadRange::resolve(&policy, query, MAX_INLINE_READ_BYTES)?;
+  let mut content = Vec::with_capacity(range.length as usize);
+  range
+    .open()
+    .await?
+    .read_to_end(&mut content)
+    .await
+    .map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to read file"))?;
+  Ok(Json(serde_json::json!({
+    "path": range.path.to_string_lossy(),
+    "sha256": sha256_hex(&content),
+    "offset": range.offset,
+    "length": content.len(),
+    "eof": range.offset + content.len() as u64 >= range.file_size,
+    "content": base64::engine::general_purpose::STANDARD.encode(content),
+    "size": range.file_size,
+  })))
+}
+
+/// Same as `/computer/fs/read` but streams the bytes as the response body.
+/// Range details are sent as headers. Ranges up to `MAX_HASHED_RAW_READ_BYTES`
+/// are read once into memory and also carry their SHA-256; larger ones are
+/// streamed without it, so use `/computer/fs/hash` to verify them.
+pub(super) async fn get_fs_read_raw(
+  State(state): State<ApiState>,
+  Query(query): Query<FsReadQuery>,
+) -> ApiResult<Response> {
+  let policy = FsPolicy::load(&state).await;
+  let range = ReadRange::resolve(&policy, query, u64::MAX)?;
+  let (sha256, body) = if range.length <= MAX_HASHED_RAW_READ_BYTES {
+    let mut content = Vec::with_capacity(range.length as usize);
+    range
+      .open()
+      .await?
+      .read_to_end(&mut content)
+      .await
+      .map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to read file"))?;
+    (Some(sha256_hex(&content)), Body::from(content))
+  } else {
+    (None, Body::from_stream(ReaderStream::new(range.open().await?)))
+  };
+  Ok(
+    (
+      [
+        (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+        (header::CONTENT_LENGTH, range.length.to_string()),
+        (header::HeaderName::from_static("x-file-size"), range.file_size.to_string()),
+        (header::HeaderName::from_static("x-range-offset"), range.offset.to_string()),
+      ],
+      sha256.map(|sha256| [(header::HeaderName::from_static("x-content-sha256"), sha256)]),
+      body,
+    )
+      .into_response(),
+  )
+}
+
+/// Streams a file (or a range of it) through SHA-256 without buffering it.
+pub(super) async fn get_fs_hash(
+  State(state): State<ApiState>,
+  Query(query): Query<FsReadQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let policy = FsPolicy::load(&state).await;
+  let range = ReadRange::resolve(&policy, query, u64::MAX)?;
+  let sha256 = hash_reader(range.open().await?).await?;
+  Ok(Json(serde_json::json!({
+    "path": range.path.to_string_lossy(),
+    "offset": range.offset,
+    "length": range.length,
+    "size": range.file_size,
+    "sha256": sha256,
+  })))
+}
+
+#[derive(Deserialize)]
+pub(super) struct FsWritePayload {
+  path: String,
+  content: String,
+  mode: Option<String>,
+  offset: Option<u64>,
+  sha256: Option<String>,
+}
+
+pub(super) async fn post_fs_write(
+  State(state): State<ApiState>,
+  Json(payload): Json<FsWritePayload>,
+) -> ApiResult<Json<serde_json::Value>> {
+  if payload.path.is_empty() {
+    return Err(ApiError::bad_request("Missing 'path' field"));
+  }
+  if payload.content.is_empty() {
+    return Err(ApiError::bad_request("Missing 'content' field"));
+  }
+  let file_data = base64::engine::general_purpose::STANDARD
+    .decode(payload.content)
+    .map_err(|_| ApiError::bad_request("Invalid base64 content"))?;
+  let options = WriteOptions {
+    mode: payload.mode,
+    offset: payload.offset,
+    sha256: payload.sha256,
+  };
+  let path = FsPolicy::load(&state).await.writable(resolve_write_path(&payload.path))?;
+  write_file(path, &file_data, options).await
+}
+
+#[derive(Deserialize)]
+pub(super) struct FsWriteRawQuery {
+  path: String,
+  mode: Option<String>,
+  offset: Option<u64>,
+  sha256: Option<String>,
+}
+
+/// Same as `/computer/fs/write` but takes the file bytes as the request body.
+pub(super) async fn post_fs_write_raw(
+  State(state): State<ApiState>,
+  Query(query): Query<FsWriteRawQuery>,
+  body: Bytes,
+) -> ApiResult<Json<serde_json::Value>> {
+  if query.path.is_empty() {
+    return Err(ApiError::bad_request("Missing 'path' parameter"));
+  }
+  let options = WriteOptions {
+    mode: query.mode,
+    offset: query.offset,
+    sha256: query.sha256,
+  };
+  let path = FsPolicy::load(&state).await.writable(resolve_write_path(&query.path))?;
+  write_file(path, &body, options).await
+}
+
+struct WriteOptions {
+  mode: Option<String>,
+  offset: Option<u64>,
+  sha256: Option<String>,
+}
+
+async fn write_file(
+  safe_path: PathBuf,
+  file_data: &[u8],
+  options: WriteOptions,
+) -> ApiResult<Json<serde_json::Value>> {
+  let sha256 = sha256_hex(file_data);
+  verify_sha256(options.sha256.as_deref(), &sha256)?;
+  if let Some(parent) = safe_path.parent() {
+    let _ = tokio::fs::create_dir_all(parent).await;
+  }
+  let write_mode = options.mode.unwrap_or_else(|| "write".to_string());
+  let permission_denied =
+    |_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to write file");
+  if let Some(offset) = options.offset {
+    let mut file = tokio::fs::OpenOptions::new()
+      .create(true)
+      .truncate(false)
+      .write(true)
+      .open(&safe_path)
+      .await
+      .map_err(permission_denied)?;
+    file
+      .seek(std::io::SeekFrom::Start(offset))
+      .await
+      .map_err(|err| ApiError::internal(&err.to_string()))?;
+    file.write_all(file_data).await.map_err(permission_denied)?;
+  } else if write_mode == "append" {
+    let mut file = tokio::fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&safe_path)
+      .await
+      .map_err(permission_denied)?;
+    file.write_all(file_data).await.map_err(permission_denied)?;
+  } else {
+    tokio::fs::write(&safe_path, file_data)
+      .await
+      .map_err(permission_denied)?;
+  }
+  Ok(Json(serde_json::json!({
+    "path": safe_path.to_string_lossy(),
+    "size": file_data.len(),
+    "sha256": sha256,
+  })))
+}
+
+#[derive(Deserialize)]
+pub(super) struct UploadStartPayload {
+  path: String,
+  size: Option<u64>,
+  sha256: Option<String>,
+  #[serde(default)]
+  overwrite: bool,
+}
+
+/// Starts a resumable upload. Chunks are written to a temporary file next to
+/// the destination, which is only renamed into place by `complete`.
+pub(super) async fn post_fs_upload_start(
+  State(state): State<ApiState>,
+  Json(payload): Json<UploadStartPayload>,
+) -> ApiResult<Json<serde_json::Value>> {
+  if payload.path.is_empty() {
+    return Err(ApiError::bad_request("Missing 'path' field"));
+  }
+  let path = FsPolicy::load(&state)
+    .await
+    .writable(resolve_write_path(&payload.path))?;
+  if path.exists() && !payload.overwrite {
+    return Err(ApiError::status(
+      StatusCode::CONFLICT,
+      "File already exists; pass overwrite=true to replace it",
+    ));
+  }
+  if let Some(parent) = path.parent() {
+    tokio::fs::create_dir_all(parent)
+      .await
+      .map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to create directory"))?;
+  }
+  purge_stale_uploads(&state.uploads).await;
+
+  let upload_id = uuid::Uuid::new_v4().to_string();
+  let file_name = path
+    .file_name()
+    .map(|name| name.to_string_lossy().to_string())
+    .unwrap_or_default();
+  let temp_path = path.with_file_name(format!(".{file_name}.{upload_id}.part"));
+  tokio::fs::File::create(&temp_path)
+    .await
+    .map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to write file"))?;
+
+  let session = UploadSession {
+    path: path.clone(),
+    temp_path,
+    received: 0,
+    expected_size: payload.size,
+    expected_sha256: payload.sha256.map(|hash| hash.to_lowercase()),
+    hasher: Sha256::new(),
+    updated: Instant::now(),
+    finished: false,
+  };
+  state
+    .uploads
+    .lock()
+    .await
+    .insert(upload_id.clone(), Arc::new(Mutex::new(session)));
+  Ok(Json(serde_json::json!({
+    "upload_id": upload_id,
+    "path": path.to_string_lossy(),
+    "offset": 0,
+    "max_chunk_size": MAX_CHUNK_BYTES,
+  })))
+}
+
+pub(super) async fn get_fs_upload(
+  State(state): State<ApiState>,
+  UrlPath(upload_id): UrlPath<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let session = find_upload(&state.uploads, &upload_id).await?;
+  let session = session.lock().await;
+  Ok(Json(serde_json::json!({
+    "upload_id": upload_id,
+    "path": session.path.to_string_lossy(),
+    "offset": session.received,
+    "size": session.expected_size,
+  })))
+}
+
+#[derive(Deserialize)]
+pub(super) struct UploadChunkPayload {
+  offset: u64,
+  content: String,
+}
+
+pub(super) async fn post_fs_upload_chunk(
+  State(state): State<ApiState>,
+  UrlPath(upload_id): UrlPath<String>,
+  Json(payload): Json<UploadChunkPayload>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let data = base64::engine::general_purpose::STANDARD
+    .decode(payload.content)
+    .map_err(|_| ApiError::bad_request("Invalid base64 content"))?;
+  append_chunk(&state.uploads, &upload_id, payload.offset, &data).await
+}
+
+#[derive(Deserialize)]
+pub(super) struct UploadChunkQuery {
+  offset: u64,
+}
+
+pub(super) async fn post_fs_upload_chunk_raw(
+  State(state): State<ApiState>,
+  UrlPath(upload_id): UrlPath<String>,
+  Query(query): Query<UploadChunkQuery>,
+  body: Bytes,
+) -> ApiResult<Json<serde_json::Value>> {
+  append_chunk(&state.uploads, &upload_id, query.offset, &body).await
+}
+
+pub(super) async fn post_fs_upload_complete(
+  State(state): State<ApiState>,
+  UrlPath(upload_id): UrlPath<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let session = find_upload(&state.uploads, &upload_id).await?;
+  let mut session = session.lock().await;
+  if session.finished {
+    return Err(ApiError::status(StatusCode::NOT_FOUND, "Upload not found"));
+  }
+  if let Some(expected) = session.expected_size {
+    if session.received != expected {
+      return Err(ApiError::status(
+        StatusCode::CONFLICT,
+        &format!("Upload incomplete: received {} of {expected} bytes", session.received),
+      ));
+    }
+  }
+  let sha256 = format!("{:x}", session.hasher.clone().finalize());
+  if let Err(err) = verify_sha256(session.expected_sha256.as_deref(), &sha256) {
+    session.finished = true;
+    state.uploads.lock().await.remove(&upload_id);
+    let _ = tokio::fs::remove_file(&session.temp_path).await;
+    return Err(err);
+  }
+  tokio::fs::rename(&session.temp_path, &session.path)
+    .await
+    .map_err(|err| ApiError::internal(&format!("Failed to move upload into place: {err}")))?;
+  session.finished = true;
+  state.uploads.lock().await.remove(&upload_id);
+  Ok(Json(serde_json::json!({
+    "path": session.path.to_string_lossy(),
+    "size": session.received,
+    "sha256": sha256,
+  })))
+}
+
+pub(super) async fn delete_fs_upload(
+  State(state): State<ApiState>,
+  UrlPath(upload_id): UrlPath<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let session = state
+    .uploads
+    .lock()
+    .await
+    .remove(&upload_id)
+    .ok_or_else(|| ApiError::status(StatusCode::NOT_FOUND, "Upload not found"))?;
+  let mut session = session.lock().await;
+  session.finished = true;
+  let _ = tokio::fs::remove_file(&session.temp_path).await;
+  Ok(Json(serde_json::json!({})))
+}
+
+/// Drops every upload session along with its temporary file.
+pub(super) async fn discard_uploads(uploads: &UploadSessions) {
+  let sessions: Vec<_> = uploads.lock().await.drain().map(|(_, session)| session).collect();
+  for session in sessions {
+    let mut session = session.lock().await;
+    session.finished = true;
+    let _ = tokio::fs::remove_file(&session.temp_path).await;
+  }
+}
+
+async fn find_upload(
+  uploads: &UploadSessions,
+  upload_id: &str,
+) -> ApiResult<Arc<Mutex<UploadSession>>> {
+  uploads
+    .lock()
+    .await
+    .get(upload_id)
+    .cloned()
+    .ok_or_else(|| ApiError::status(StatusCode::NOT_FOUND, "Upload not found"))
+}
+
+/// Appends a chunk at `offset`, which must equal the bytes received so far.
+/// Clients resume an interrupted upload by asking for the current offset.
+async fn append_chunk(
+  uploads: &UploadSessions,
+  upload_id: &str,
+  offset: u64,
+  data: &[u8],
+) -> ApiResult<Json<serde_json::Value>> {
+  if data.len() as u64 > MAX_CHUNK_BYTES {
+    return Err(ApiError::bad_request(&format!(
+      "Chunk must be at most {MAX_CHUNK_BYTES} bytes"
+    )));
+  }
+  let session = find_upload(uploads, upload_id).await?;
+  let mut session = session.lock().await;
+  if session.finished {
+    return Err(ApiError::status(StatusCode::NOT_FOUND, "Upload not found"));
+  }
+  if offset != session.received {
+    return Err(ApiError::status(
+      StatusCode::CONFLICT,
+      &format!(
+        "Chunk offset {offset} does not match the {} bytes received so far",
+        session.received
+      ),
+    ));
+  }
+  if let Some(expected) = session.expected_size {
+    if session.received + data.len() as u64 > expected {
+      return Err(ApiError::bad_request("Chunk exceeds the declared upload size"));
+    }
+  }
+  let mut file = tokio::fs::OpenOptions::new()
+    .append(true)
+    .open(&session.temp_path)
+    .await
+    .map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to write file"))?;
+  file
+    .write_all(data)
+    .await
+    .map_err(|err| ApiError::internal(&err.to_string()))?;
+  file
+    .flush()
+    .await
+    .map_err(|err| ApiError::internal(&err.to_string()))?;
+  session.hasher.update(data);
+  session.received += data.len() as u64;
+  session.updated = Instant::now();
+  Ok(Json(serde_json::json!({
+    "upload_id": upload_id,
+    "offset": session.received,
+    "chunk_sha256": sha256_hex(data),
+  })))
+}
+
+async fn purge_stale_uploads(uploads: &UploadSessions) {
+  let mut uploads = uploads.lock().await;
+  let mut stale = Vec::new();
+  for (upload_id, session) in uploads.iter() {
+    if let Ok(session) = session.try_lock() {
+      if session.updated.elapsed() > UPLOAD_SESSION_TTL {
+        stale.push((upload_id.clone(), session.temp_path.clone()));
+      }
+    }
+  }
+  for (upload_id, temp_path) in stale {
+    uploads.remove(&upload_id);
+    let _ = tokio::fs::remove_file(temp_path).await;
+  }
+}
+
+#[derive(Deserialize)]
+pub(super) struct FsPathQuery {
+  path: String,
+}
+
+pub(super) async fn get_fs_stat(
+  State(state): State<ApiState>,
+  Query(query): Query<FsPathQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let path = FsPolicy::load(&state).await.readable(query.path)?;
+  let meta = std::fs::symlink_metadata(&path).map_err(|err| io_error(err, "stat path"))?;
+  let mut stat = entry_json(&path, &meta);
+  let symlink_target = meta
+    .file_type()
+    .is_symlink()
+    .then(|| std::fs::read_link(&path).ok())
+    .flatten();
+  stat["symlink_target"] = serde_json::json!(symlink_target.map(|target| target.to_string_lossy().to_string()));
+  stat["created"] = serde_json::json!(unix_seconds(meta.created()));
+  stat["accessed"] = serde_json::json!(unix_seconds(meta.accessed()));
+  stat["readonly"] = serde_json::json!(meta.permissions().readonly());
+  let (mode, uid, gid, owner) = ownership(&meta);
+  stat["mode"] = serde_json::json!(mode);
+  stat["uid"] = serde_json::json!(uid);
+  stat["gid"] = serde_json::json!(gid);
+  stat["owner"] = serde_json::json!(owner);
+  Ok(Json(stat))
+}
+
+#[derive(Deserialize)]
+pub(super) struct FsMkdirPayload {
+  path: String,
+  #[serde(default = "default_true")]
+  parents: bool,
+}
+
+pub(super) async fn post_fs_mkdir(
+  State(state): State<ApiState>,
+  Json(payload): Json<FsMkdirPayload>,
+) -> ApiResult<Json<serde_json::Value>> {
+  if payload.path.is_empty() {
+    return Err(ApiError::bad_request("Missing 'path' field"));
+  }
+  let path = FsPolicy::load(&state).await.writable(payload.path)?;
+  let result = if payload.parents {
+    tokio::fs::create_dir_all(&path).await
+  } else {
+    tokio::fs::create_dir(&path).await
+  };
+  result.map_err(|err| io_error(err, "create directory"))?;
+  Ok(Json(serde_json::json!({ "path": path.to_string_lossy() })))
+}
+
+#[derive(Deserialize)]
+pub(super) struct FsTransferPayload {
+  source: String,
+  destination: String,
+  #[serde(default)]
+  overwrite: bool,
+}
+
+/// A resolved move or copy. An existing destination being replaced is first
+/// renamed aside and only removed once the transfer succeeds.
+struct Transfer {
+  source: PathBuf,
+  destination: PathBuf,
+  replaced: Option<PathBuf>,
+}
+
+impl FsTransferPayload {
+  /// `source_writable` is set for moves, which remove the source. Every check
+  /// runs before anything on disk is touched.
+  fn resolve(self, policy: &FsPolicy, source_writable: bool) -> ApiResult<Transfer> {
+    if self.source.is_empty() || self.destination.is_empty() {
+      return Err(ApiError::bad_request("Missing 'source' or 'destination' field"));
+    }
+    let source = if source_writable {
+      policy.writable(self.source)?
+    } else {
+      policy.readable(self.source)?
+    };
+    let destination = policy.writable(self.destination)?;
+    if std::fs::symlink_metadata(&source).is_err() {
+      return Err(ApiError::status(StatusCode::NOT_FOUND, "Source not found"));
+    }
+    if same_file(&source, &destination) || is_nested(&destination, &source) {
+      return Err(ApiError::bad_request(
+        "Destination must not be the source or inside it",
+      ));
+    }
+    if source_writable && policy.contains_denied(&source) {
+      return Err(ApiError::status(
+        StatusCode::FORBIDDEN,
+        "Source contains a denied filesystem root",
+      ));
+    }
+    let mut replaced = None;
+    if std::fs::symlink_metadata(&destination).is_ok() {
+      if !self.overwrite {
+        return Err(ApiError::status(
+          StatusCode::CONFLICT,
+          "Destination already exists; pass overwrite=true to replace it",
+        ));
+      }
+      refuse_protected(&destination)?;
+      if policy.contains_denied(&destination) {
+        return Err(ApiError::status(
+          StatusCode::FORBIDDEN,
+          "Destination contains a denied filesystem root",
+        ));
+      }
+      let name = destination.file_name().unwrap_or_default().to_string_lossy();
+      let aside = destination.with_file_name(format!(
+        ".{name}.cyberdriver-replaced-{}",
+        uuid::Uuid::new_v4().simple()
+      ));
+      std::fs::rename(&destination, &aside)
+        .map_err(|err| io_error(err, "replace destination"))?;
+      replaced = Some(aside);
+    }
+    let transfer = Transfer { source, destination, replaced };
+    if let Some(parent) = transfer.destination.parent() {
+      if let Err(err) = std::fs::create_dir_all(parent) {
+        return transfer.finish(Err(io_error(err, "create directory")));
+      }
+    }
+    Ok(transfer)
+  }
+}
+
+impl Transfer {
+  /// Removes the replaced destination after a successful transfer, or puts
+  /// it back after a failed one.
+  fn finish<T>(mut self, result: ApiResult<T>) -> ApiResult<T> {
+    let Some(replaced) = self.replaced.take() else {
+      return result;
+    };
+    if result.is_ok() {
+      let _ = remove_path(&replaced, true);
+    } else {
+      if std::fs::symlink_metadata(&self.destination).is_ok() {
+        let _ = remove_path(&self.destination, true);
+      }
+      let _ = std::fs::rename(&replaced, &self.destination);
+    }
+    result
+  }
+}
+
+pub(super) async fn post_fs_move(
+  State(state): State<ApiState>,
+  Json(payload): Json<FsTransferPayload>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let policy = FsPolicy::load(&state).await;
+  let (source, destination) = blocking(move || {
+    let transfer = payload.resolve(&policy, true)?;
+    let (source, destination) = (transfer.source.clone(), transfer.destination.clone());
+    let result = match std::fs::rename(&source, &destination) {
+      Ok(()) => Ok(()),
+      Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+        copy_recursive(&source, &destination, &|path| policy.permits(path))
+          .map_err(|err| io_error(err, "move"))
+      }
+      Err(err) => Err(io_error(err, "move")),
+    };
+    let copied = result.as_ref().is_ok() && std::fs::symlink_metadata(&source).is_ok();
+    transfer.finish(result)?;
+    if copied {
+      remove_path(&source, true).map_err(|err| io_error(err, "remove source after move"))?;
+    }
+    Ok((source, destination))
+  })
+  .await?;
+  Ok(Json(serde_json::json!({
+    "source": source.to_string_lossy(),
+    "destination": destination.to_string_lossy(),
+  })))
+}
+
+pub(super) async fn post_fs_copy(
+  State(state): State<ApiState>,
+  Json(payload): Json<FsTransferPayload>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let policy = FsPolicy::load(&state).await;
+  let (source, destination, bytes) = blocking(move || {
+    let transfer = payload.resolve(&policy, false)?;
+    let (source, destination) = (transfer.source.clone(), transfer.destination.clone());
+    let bytes = transfer.finish(
+      copy_recursive(&source, &destination, &|path| policy.permits(path))
+        .map_err(|err| io_error(err, "copy")),
+    )?;
+    Ok((source, destination, bytes))
+  })
+  .await?;
+  Ok(Json(serde_json::json!({
+    "source": source.to_string_lossy(),
+    "destination": destination.to_string_lossy(),
+    "bytes_copied": bytes,
+  })))
+}
+
+#[derive(Deserialize)]
+pub(super) struct FsDeletePayload {
+  path: String,
+  #[serde(default)]
+  recursive: bool,
+}
+
+pub(super) async fn post_fs_delete(
+  State(state): State<ApiState>,
+  Json(payload): Json<FsDeletePayload>,
+) -> ApiResult<Json<serde_json::Value>> {
+  if payload.path.is_empty() {
+    return Err(ApiError::bad_request("Missing 'path' field"));
+  }
+  let policy = FsPolicy::load(&state).await;
+  let path = policy.writable(payload.path)?;
+  refuse_protected(&path)?;
+  let recursive = payload.recursive;
+  if recursive && policy.contains_denied(&path) {
+    return Err(ApiError::status(
+      StatusCode::FORBIDDEN,
+      "Directory contains a denied filesystem root",
+    ));
+  }
+  let target = path.clone();
+  blocking(move || {
+    let meta = std::fs::symlink_metadata(&target).map_err(|err| io_error(err, "delete"))?;
+    if meta.is_dir() && !recursive && std::fs::read_dir(&target).map(|mut dir| dir.next().is_some()).unwrap_or(false) {
+      return Err(ApiError::status(
+        StatusCode::CONFLICT,
+        "Directory is not empty; pass recursive=true to delete it",
+      ));
+    }
+    remove_path(&target, recursive).map_err(|err| io_error(err, "delete"))
+  })
+  .await?;
+  Ok(Json(serde_json::json!({ "path": path.to_string_lossy() })))
+}
+
+#[derive(Deserialize)]
+pub(super) struct FsGlobQuery {
+  pattern: String,
+  root: Option<String>,
+  limit: Option<usize>,
+}
+
+const DEFAULT_GLOB_LIMIT: usize = 1000;
+const MAX_GLOB_LIMIT: usize = 10_000;
+/// Deepest level a `**` pattern descends to.
+const MAX_GLOB_DEPTH: usize = 32;
+/// Hard cap on entries visited by one glob, so a broad pattern over a huge
+/// tree cannot stall the API.
+const MAX_GLOB_SCANNED_ENTRIES: usize = 200_000;
+
+pub(super) async fn get_fs_glob(
+  State(state): State<ApiState>,
+  Query(query): Query<FsGlobQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+  if query.pattern.is_empty() {
+    return Err(ApiError::bad_request("Missing 'pattern' parameter"));
+  }
+  let policy = FsPolicy::load(&state).await;
+  let pattern = PathBuf::from(&query.pattern).expand_dir();
+  let pattern = match query.root {
+    Some(root) if pattern.is_relative() => PathBuf::from(root).expand_dir().join(pattern),
+    _ => pattern,
+  };
+  let (base, rest) = split_glob(&pattern);
+  let base = if base.as_os_str().is_empty() { PathBuf::from(".") } else { base };
+  let base = policy.readable(base)?;
+  let matcher = glob::Pattern::new(&base.join(&rest).to_string_lossy())
+    .map_err(|err| ApiError::bad_request(&format!("Invalid glob pattern: {err}")))?;
+  let max_depth = if rest.components().any(|component| component.as_os_str() == "**") {
+    MAX_GLOB_DEPTH
+  } else {
+    rest.components().count()
+  };
+  let limit = query.limit.unwrap_or(DEFAULT_GLOB_LIMIT).clamp(1, MAX_GLOB_LIMIT);
+  let (matches, truncated) = blocking(move || {
+    let mut walk = GlobWalk {
+      matcher,
+      policy,
+      max_depth,
+      limit,
+      scanned: 0,
+      matches: Vec::new(),
+      truncated: false,
+    };
+    if max_depth == 0 {
+      // No wildcard: the pattern is the resolved path itself.
+      if let Ok(meta) = std::fs::symlink_metadata(&base) {
+        walk.matches.push(entry_json(&base, &meta));
+      }
+    } else {
+      walk.visit(&base, 1);
+    }
+    Ok((walk.matches, walk.truncated))
+  })
+  .await?;
+  Ok(Json(serde_json::json!({ "matches": matches, "truncated": truncated })))
+}
+
+/// Splits a glob into the literal directory prefix and the pattern part.
+pub(super) fn split_glob(path: &Path) -> (PathBuf, PathBuf) {
+  let mut base = PathBuf::new();
+  let mut rest = PathBuf::new();
+  for component in path.components() {
+    let text = component.as_os_str().to_string_lossy();
+    if rest.as_os_str().is_empty() && !text.contains(['*', '?', '[']) {
+      base.push(component);
+    } else {
+      rest.push(component);
+    }
+  }
+  (base, rest)
+}
+
+/// Walks the tree below a glob's literal prefix, staying inside the
+/// filesystem policy and within the depth and scan limits.
+struct GlobWalk {
+  matcher: glob::Pattern,
+  policy: FsPolicy,
+  max_depth: usize,
+  limit: usize,
+  scanned: usize,
+  matches: Vec<serde_json::Value>,
+  truncated: bool,
+}
+
+impl GlobWalk {
+  fn visit(&mut self, dir: &Path, depth: usize) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+      return;
+    };
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+    paths.sort();
+    let options = glob::MatchOptions {
+      require_literal_separator: true,
+      ..Default::default()
+    };
+    for path in paths {
+      if self.truncated {
+        return;
+      }
+      if self.scanned >= MAX_GLOB_SCANNED_ENTRIES {
+        self.truncated = true;
+        return;
+      }
+      self.scanned += 1;
+      if !self.policy.permits(&path) {
+        continue;
+      }
+      let Ok(meta) = std::fs::symlink_metadata(&path) else {
+        continue;
+      };
+      if self.matcher.matches_path_with(&path, options) {
+        if self.matches.len() == self.limit {
+          self.truncated = true;
+          return;
+        }
+        self.matches.push(entry_json(&path, &meta));
+      }
+      if meta.is_dir() && depth < self.max_depth {
+        self.visit(&path, depth + 1);
+      }
+    }
+  }
+}
+
+fn default_true() -> bool {
+  true
+}
+
+/// Runs blocking filesystem work off the async runtime.
+pub(super) async fn blocking<T, F>(work: F) -> ApiResult<T>
+where
+  T: Send + 'static,
+  F: FnOnce() -> ApiResult<T> + Send + 'static,
+{
+  tokio::task::spawn_blocking(work)
+    .await
+    .map_err(|err| ApiError::internal(&err.to_string()))?
+}
+
+/// Maps an I/O error to the status code an agent can act on.
+fn io_error(err: std::io::Error, action: &str) -> ApiError {
+  let status = match err.kind() {
+    std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+    std::io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+    std::io::ErrorKind::AlreadyExists | std::io::ErrorKind::DirectoryNotEmpty => StatusCode::CONFLICT,
+    std::io::ErrorKind::NotADirectory
+    | std::io::ErrorKind::IsADirectory
+    | std::io::ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+    _ => StatusCode::INTERNAL_SERVER_ERROR,
+  };
+  ApiError::status(status, &format!("Failed to {action}: {err}"))
+}
+
+pub(super) fn entry_json(path: &Path, meta: &std::fs::Metadata) -> serde_json::Value {
+  let file_type = meta.file_type();
+  let kind = if file_type.is_symlink() {
+    "symlink"
+  } else if file_type.is_dir() {
+    "dir"
+  } else if file_type.is_file() {
+    "file"
+  } else {
+    "other"
+  };
+  serde_json::json!({
+    "name": path.file_name().map(|name| name.to_string_lossy().to_string()),
+    "path": path.to_string_lossy(),
+    "type": kind,
+    "is_dir": meta.is_dir(),
+    "size": meta.is_file().then(|| meta.len()),
+    "modified": unix_seconds(meta.modified()),
+  })
+}
+
+fn unix_seconds(time: std::io::Result<std::time::SystemTime>) -> Option<f64> {
+  time
+    .ok()
+    .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+    .map(|duration| duration.as_secs_f64())
+}
+
+/// Unix mode (octal), uid, gid and owner name. Windows ACLs are not mapped.
+#[cfg(unix)]
+fn ownership(meta: &std::fs::Metadata) -> (Option<String>, Option<u32>, Option<u32>, Option<String>) {
+  use std::os::unix::fs::MetadataExt;
+  let owner = sysinfo::Uid::try_from(meta.uid() as usize).ok().and_then(|uid| {
+    sysinfo::Users::new_with_refreshed_list()
+      .get_user_by_id(&uid)
+      .map(|user| user.name().to_string())
+  });
+  (
+    Some(format!("{:o}", meta.mode() & 0o7777)),
+    Some(meta.uid()),
+    Some(meta.gid()),
+    owner,
+  )
+}
+
+#[cfg(not(unix))]
+fn ownership(_meta: &std::fs::Metadata) -> (Option<String>, Option<u32>, Option<u32>, Option<String>) {
+  (None, None, None, None)
+}
+
+/// Copies a file, symlink or directory tree and returns the bytes copied.
+/// Entries rejected by `permits` are skipped.
+fn copy_recursive(
+  source: &Path,
+  destination: &Path,
+  permits: &dyn Fn(&Path) -> bool,
+) -> std::io::Result<u64> {
+  let meta = std::fs::symlink_metadata(source)?;
+  if meta.file_type().is_symlink() {
+    let target = std::fs::read_link(source)?;
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(&target, destination)?;
+    #[cfg(windows)]
+    if source.is_dir() {
+      std::os::windows::fs::symlink_dir(&target, destination)?;
+    } else {
+      std::os::windows::fs::symlink_file(&target, destination)?;
+    }
+    return Ok(0);
+  }
+  if !meta.is_dir() {
+    return std::fs::copy(source, destination);
+  }
+  std::fs::create_dir_all(destination)?;
+  let mut total = 0;
+  for entry in std::fs::read_dir(source)? {
+    let entry = entry?;
+    if !permits(&entry.path()) {
+      continue;
+    }
+    total += copy_recursive(&entry.path(), &destination.join(entry.file_name()), permits)?;
+  }
+  Ok(total)
+}
+
+/// Refuses to delete or replace a filesystem root or the home directory.
+fn refuse_protected(path: &Path) -> ApiResult<()> {
+  if path.parent().is_none() || dirs::home_dir().is_some_and(|home| same_file(&home, path)) {
+    return Err(ApiError::bad_request(
+      "Refusing to delete a filesystem root or the home directory",
+    ));
+  }
+  Ok(())
+}
+
+/// Whether both paths name the same existing file, also when they differ
+/// only in case on a case-insensitive filesystem.
+fn same_file(a: &Path, b: &Path) -> bool {
+  if a == b {
+    return true;
+  }
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::MetadataExt;
+    match (std::fs::symlink_metadata(a), std::fs::symlink_metadata(b)) {
+      (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+      _ => false,
+    }
+  }
+  #[cfg(not(unix))]
+  {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+      (Ok(a), Ok(b)) => a.to_string_lossy().eq_ignore_ascii_case(&b.to_string_lossy()),
+      _ => false,
+    }
+  }
+}
+
+/// Whether `path` lies strictly inside `root`. Components are compared
+/// without case, so case-insensitive filesystems are covered too.
+fn is_nested(path: &Path, root: &Path) -> bool {
+  let mut parts = path.components();
+  let inside = root.components().all(|root| {
+    parts.next().is_some_and(|part| {
+      part
+        .as_os_str()
+        .to_string_lossy()
+        .to_lowercase()
+        == root.as_os_str().to_string_lossy().to_lowercase()
+    })
+  });
+  inside && parts.next().is_some()
+}
+
+fn remove_path(path: &Path, recursive: bool) -> std::io::Result<()> {
+  let meta = std::fs::symlink_metadata(path)?;
+  if !meta.is_dir() {
+    return std::fs::remove_file(path);
+  }
+  if recursive {
+    std::fs::remove_dir_all(path)
+  } else {
+    std::fs::remove_dir(path)
+  }
+}
+
+/// Expands `~` and sends bare file names to `~/CyberdeskTransfers`.
+fn resolve_write_path(path: &str) -> PathBuf {
+  let mut safe_path = PathBuf::from(path).expand_dir();
+  if safe_path.parent().map(|p| p == Path::new(".")).unwrap_or(false) {
+    safe_path = dirs::home_dir()
+      .unwrap_or_else(|| PathBuf::from("."))
+      .join("CyberdeskTransfers")
+      .join(safe_path.file_name().unwrap());
+  }
+  safe_path
+}
+
+fn verify_sha256(expected: Option<&str>, actual: &str) -> ApiResult<()> {
+  match expected {
+    Some(expected) if !expected.eq_ignore_ascii_case(actual) => Err(ApiError::status(
+      StatusCode::UNPROCESSABLE_ENTITY,
+      &format!("SHA-256 mismatch: expected {expected}, got {actual}"),
+    )),
+    _ => Ok(()),
+  }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+  format!("{:x}", Sha256::digest(data))
+}
+
+async fn hash_reader<R: tokio::io::AsyncRead + Unpin>(mut reader: R) -> ApiResult<String> {
+  let mut hasher = Sha256::new();
+  let mut buffer = vec![0u8; HASH_BUFFER_BYTES];
+  loop {
+    let read = reader
+      .read(&mut buffer)
+      .await
+      .map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to read file"))?;
+    if read == 0 {
+      break;
+    }
+    hasher.update(&buffer[..read]);
+  }
+  Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub(super) trait ExpandPath {
+  fn expand_dir(self) -> PathBuf;
+}
+
+impl ExpandPath for PathBuf {
+  fn expand_dir(self) -> PathBuf {
+    if let Some(str_path) = self.to_str() {
+      if str_path.starts_with("~") {
+        if let Some(home) = dirs::home_dir() {
+          return PathBuf::from(str_path.replacen("~", home.to_string_lossy().as_ref(), 1));
+        }
+      }
+    }
+    self
+  }
+}
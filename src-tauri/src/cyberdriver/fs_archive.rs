@@ -0,0 +1,567 @@
+use std::{
+  io::{Read, Seek, Write},
+  path::{Component, Path, PathBuf},
+};
+
+use axum::{
+  body::{Body, Bytes},
+  extract::{Query, State},
+  http::{header, StatusCode},
+  response::{IntoResponse, Response},
+  Json,
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use glob::Pattern;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use super::{
+  api::{ApiError, ApiResult, ApiState},
+  fs_policy::FsPolicy,
+};
+
+const STREAM_CHUNK_BYTES: usize = 64 * 1024;
+/// Most entries (files, directories and skipped entries) one extraction reads.
+const MAX_EXTRACT_ENTRIES: usize = 100_000;
+/// Most bytes one extraction writes, so a small archive cannot fill the disk.
+const MAX_EXTRACT_BYTES: u64 = 16 * 1024 * 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ArchiveFormat {
+  Zip,
+  TarGz,
+}
+
+impl ArchiveFormat {
+  fn from_str(format: &str) -> Option<Self> {
+    match format.to_lowercase().as_str() {
+      "zip" => Some(Self::Zip),
+      "tar.gz" | "tgz" | "targz" => Some(Self::TarGz),
+      _ => None,
+    }
+  }
+
+  fn sniff(header: &[u8]) -> Option<Self> {
+    if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+      Some(Self::Zip)
+    } else if header.starts_with(&[0x1f, 0x8b]) {
+      Some(Self::TarGz)
+    } else {
+      None
+    }
+  }
+
+  fn extension(self) -> &'static str {
+    match self {
+      Self::Zip => "zip",
+      Self::TarGz => "tar.gz",
+    }
+  }
+
+  fn content_type(self) -> &'static str {
+    match self {
+      Self::Zip => "application/zip",
+      Self::TarGz => "application/gzip",
+    }
+  }
+}
+
+#[derive(Deserialize)]
+pub(super) struct ArchivePayload {
+  path: String,
+  format: Option<String>,
+  #[serde(default)]
+  include: Vec<String>,
+  #[serde(default)]
+  exclude: Vec<String>,
+}
+
+/// Streams a directory (or single file) as a zip or tar.gz archive. Entries
+/// are stored under the directory's own name. `include` globs select files,
+/// `exclude` globs drop files and whole subtrees; both match paths relative
+/// to the archived directory.
+pub(super) async fn post_fs_archive(
+  State(state): State<ApiState>,
+  Json(payload): Json<ArchivePayload>,
+) -> ApiResult<Response> {
+  if payload.path.is_empty() {
+    return Err(ApiError::bad_request("Missing 'path' field"));
+  }
+  let format = match payload.format.as_deref() {
+    Some(format) => ArchiveFormat::from_str(format)
+      .ok_or_else(|| ApiError::bad_request("format must be 'zip' or 'tar.gz'"))?,
+    None => ArchiveFormat::Zip,
+  };
+  let policy = FsPolicy::load(&state).await;
+  let root = policy.readable(payload.path)?;
+  if !root.exists() {
+    return Err(ApiError::status(StatusCode::NOT_FOUND, "Path not found"));
+  }
+  let filter = EntryFilter {
+    include: compile_patterns(&payload.include)?,
+    exclude: compile_patterns(&payload.exclude)?,
+  };
+  let base_name = root
+    .file_name()
+    .map(|name| name.to_string_lossy().to_string())
+    .unwrap_or_else(|| "archive".to_string());
+  let file_name = format!("{base_name}.{}", format.extension());
+
+  let (sender, receiver) = mpsc::channel::<std::io::Result<Bytes>>(8);
+  let error_sender = sender.clone();
+  tokio::task::spawn_blocking(move || {
+    let writer = ChannelWriter {
+      sender,
+      buffer: Vec::with_capacity(STREAM_CHUNK_BYTES),
+    };
+    let entries = collect_entries(&root, &base_name, &filter, &policy);
+    let result = match format {
+      ArchiveFormat::Zip => write_zip(writer, &entries),
+      ArchiveFormat::TarGz => write_tar_gz(writer, &entries),
+    };
+    if let Err(err) = result {
+      let _ = error_sender.blocking_send(Err(err));
+    }
+  });
+
+  let stream = futures_util::stream::unfold(receiver, |mut receiver| async move {
+    receiver.recv().await.map(|item| (item, receiver))
+  });
+  Ok(
+    (
+      [
+        (header::CONTENT_TYPE, format.content_type().to_string()),
+        (
+          header::CONTENT_DISPOSITION,
+          format!("attachment; filename=\"{file_name}\""),
+        ),
+      ],
+      Body::from_stream(stream),
+    )
+      .into_response(),
+  )
+}
+
+#[derive(Deserialize)]
+pub(super) struct ExtractQuery {
+  path: String,
+  source: Option<String>,
+  format: Option<String>,
+  #[serde(default)]
+  overwrite: bool,
+}
+
+/// Unpacks a zip or tar.gz archive into `path`. The archive is either the
+/// request body or, for archives too large for one request, an existing file
+/// named by `source` (for example one sent with the resumable upload API).
+/// Entries that would land outside the target, and symlinks, are skipped.
+/// Extraction stops with 413 after `MAX_EXTRACT_ENTRIES` entries or
+/// `MAX_EXTRACT_BYTES` written bytes, leaving what was extracted so far.
+pub(super) async fn post_fs_extract(
+  State(state): State<ApiState>,
+  Query(query): Query<ExtractQuery>,
+  body: Bytes,
+) -> ApiResult<Json<serde_json::Value>> {
+  if query.path.is_empty() {
+    return Err(ApiError::bad_request("Missing 'path' parameter"));
+  }
+  let policy = FsPolicy::load(&state).await;
+  let target = policy.writable(query.path)?;
+  let source = match query.source {
+    Some(source) => Some(policy.readable(source)?),
+    None if body.is_empty() => return Err(ApiError::bad_request("Missing archive body or 'source'")),
+    None => None,
+  };
+  let requested_format = match query.format.as_deref() {
+    Some(format) => Some(
+      ArchiveFormat::from_str(format)
+        .ok_or_else(|| ApiError::bad_request("format must be 'zip' or 'tar.gz'"))?,
+    ),
+    None => None,
+  };
+  let overwrite = query.overwrite;
+
+  let summary = tokio::task::spawn_blocking(move || -> ApiResult<ExtractSummary> {
+    std::fs::create_dir_all(&target)
+      .map_err(|err| ApiError::status(StatusCode::FORBIDDEN, &format!("Failed to create target: {err}")))?;
+    let target = std::fs::canonicalize(&target).map_err(|err| ApiError::internal(&err.to_string()))?;
+    let mut extractor = Extractor {
+      target,
+      policy,
+      overwrite,
+      summary: ExtractSummary::default(),
+      entries: 0,
+    };
+    match source {
+      Some(source) => {
+        let mut file = std::fs::File::open(&source)
+          .map_err(|_| ApiError::status(StatusCode::NOT_FOUND, "Archive not found"))?;
+        let format = detect_format(requested_format, &mut file)?;
+        extractor.extract(format, file)?;
+      }
+      None => {
+        let mut cursor = std::io::Cursor::new(body);
+        let format = detect_format(requested_format, &mut cursor)?;
+        extractor.extract(format, cursor)?;
+      }
+    }
+    Ok(extractor.summary)
+  })
+  .await
+  .map_err(|err| ApiError::internal(&err.to_string()))??;
+
+  Ok(Json(serde_json::json!({
+    "path": summary.target.to_string_lossy(),
+    "files": summary.files,
+    "directories": summary.directories,
+    "bytes": summary.bytes,
+    "skipped": summary.skipped,
+  })))
+}
+
+struct EntryFilter {
+  include: Vec<Pattern>,
+  exclude: Vec<Pattern>,
+}
+
+impl EntryFilter {
+  fn excluded(&self, relative: &str) -> bool {
+    self.exclude.iter().any(|pattern| pattern.matches(relative))
+  }
+
+  fn included(&self, relative: &str) -> bool {
+    self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(relative))
+  }
+}
+
+fn compile_patterns(patterns: &[String]) -> ApiResult<Vec<Pattern>> {
+  patterns
+    .iter()
+    .map(|pattern| {
+      Pattern::new(pattern)
+        .map_err(|err| ApiError::bad_request(&format!("Invalid glob pattern '{pattern}': {err}")))
+    })
+    .collect()
+}
+
+struct ArchiveEntry {
+  source: PathBuf,
+  name: String,
+  is_dir: bool,
+}
+
+/// Walks `root` depth-first and returns the entries to archive. Directory
+/// entries are only emitted when no include filter narrows the file set.
+fn collect_entries(
+  root: &Path,
+  base_name: &str,
+  filter: &EntryFilter,
+  policy: &FsPolicy,
+) -> Vec<ArchiveEntry> {
+  let mut entries = Vec::new();
+  if root.is_file() {
+    entries.push(ArchiveEntry {
+      source: root.to_path_buf(),
+      name: base_name.to_string(),
+      is_dir: false,
+    });
+    return entries;
+  }
+  let mut pending = vec![(root.to_path_buf(), String::new())];
+  while let Some((dir, relative_dir)) = pending.pop() {
+    let Ok(items) = std::fs::read_dir(&dir) else {
+      continue;
+    };
+    let mut items: Vec<_> = items.flatten().collect();
+    items.sort_by_key(|item| item.file_name());
+    for item in items {
+      let path = item.path();
+      let Ok(file_type) = item.file_type() else {
+        continue;
+      };
+      if file_type.is_symlink() || !policy.permits(&path) {
+        continue;
+      }
+      let relative = format!("{relative_dir}{}", item.file_name().to_string_lossy());
+      if filter.excluded(&relative) {
+        continue;
+      }
+      if file_type.is_dir() {
+        if filter.include.is_empty() {
+          entries.push(ArchiveEntry {
+            source: path.clone(),
+            name: format!("{base_name}/{relative}/"),
+            is_dir: true,
+          });
+        }
+        pending.push((path, format!("{relative}/")));
+      } else if file_type.is_file() && filter.included(&relative) {
+        entries.push(ArchiveEntry {
+          source: path,
+          name: format!("{base_name}/{relative}"),
+          is_dir: false,
+        });
+      }
+    }
+  }
+  entries
+}
+
+fn write_zip<W: Write>(writer: W, entries: &[ArchiveEntry]) -> std::io::Result<()> {
+  let mut zip = zip::ZipWriter::new_stream(writer);
+  let options = zip::write::SimpleFileOptions::default()
+    .compression_method(zip::CompressionMethod::Deflated)
+    .large_file(true);
+  for entry in entries {
+    if entry.is_dir {
+      zip.add_directory(entry.name.as_str(), options).map_err(std::io::Error::other)?;
+      continue;
+    }
+    let Ok(mut file) = std::fs::File::open(&entry.source) else {
+      continue;
+    };
+    zip.start_file(entry.name.as_str(), options).map_err(std::io::Error::other)?;
+    std::io::copy(&mut file, &mut zip)?;
+  }
+  zip.finish().map_err(std::io::Error::other)?.flush()
+}
+
+fn write_tar_gz<W: Write>(writer: W, entries: &[ArchiveEntry]) -> std::io::Result<()> {
+  let mut tar = tar::Builder::new(GzEncoder::new(writer, Compression::default()));
+  for entry in entries {
+    if entry.is_dir {
+      tar.append_dir(entry.name.trim_end_matches('/'), &entry.source)?;
+    } else if let Ok(mut file) = std::fs::File::open(&entry.source) {
+      tar.append_file(&entry.name, &mut file)?;
+    }
+  }
+  tar.into_inner()?.finish()?.flush()
+}
+
+/// Forwards everything written to it as response body chunks. Writes fail
+/// once the client has gone away, which aborts archive creation.
+struct ChannelWriter {
+  sender: mpsc::Sender<std::io::Result<Bytes>>,
+  buffer: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+  fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+    self.buffer.extend_from_slice(data);
+    if self.buffer.len() >= STREAM_CHUNK_BYTES {
+      self.flush()?;
+    }
+    Ok(data.len())
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    if self.buffer.is_empty() {
+      return Ok(());
+    }
+    let chunk = Bytes::from(std::mem::replace(
+      &mut self.buffer,
+      Vec::with_capacity(STREAM_CHUNK_BYTES),
+    ));
+    self
+      .sender
+      .blocking_send(Ok(chunk))
+      .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Client disconnected"))
+  }
+}
+
+impl Drop for ChannelWriter {
+  fn drop(&mut self) {
+    let _ = self.flush();
+  }
+}
+
+fn detect_format<R: Read + Seek>(
+  requested: Option<ArchiveFormat>,
+  reader: &mut R,
+) -> ApiResult<ArchiveFormat> {
+  if let Some(format) = requested {
+    return Ok(format);
+  }
+  let mut header = [0u8; 4];
+  let read = reader
+    .read(&mut header)
+    .map_err(|err| ApiError::internal(&err.to_string()))?;
+  reader
+    .rewind()
+    .map_err(|err| ApiError::internal(&err.to_string()))?;
+  ArchiveFormat::sniff(&header[..read])
+    .ok_or_else(|| ApiError::bad_request("Unrecognized archive format; pass format=zip or format=tar.gz"))
+}
+
+#[derive(Default)]
+struct ExtractSummary {
+  target: PathBuf,
+  files: usize,
+  directories: usize,
+  bytes: u64,
+  skipped: Vec<String>,
+}
+
+struct Extractor {
+  target: PathBuf,
+  policy: FsPolicy,
+  overwrite: bool,
+  summary: ExtractSummary,
+  entries: usize,
+}
+
+impl Extractor {
+  fn extract<R: Read + Seek>(&mut self, format: ArchiveFormat, reader: R) -> ApiResult<()> {
+    self.summary.target = self.target.clone();
+    match format {
+      ArchiveFormat::Zip => self.extract_zip(reader),
+      ArchiveFormat::TarGz => self.extract_tar_gz(reader),
+    }
+  }
+
+  fn extract_zip<R: Read + Seek>(&mut self, reader: R) -> ApiResult<()> {
+    let mut archive = zip::ZipArchive::new(reader)
+      .map_err(|err| ApiError::bad_request(&format!("Invalid zip archive: {err}")))?;
+    for index in 0..archive.len() {
+      self.count_entry()?;
+      let mut entry = archive
+        .by_index(index)
+        .map_err(|err| ApiError::bad_request(&format!("Invalid zip archive: {err}")))?;
+      let name = entry.name().to_string();
+      if entry.is_symlink() {
+        self.summary.skipped.push(name);
+        continue;
+      }
+      let Some(destination) = self.destination(&name) else {
+        self.summary.skipped.push(name);
+        continue;
+      };
+      if entry.is_dir() {
+        self.create_dir(&destination)?;
+      } else {
+        self.write_file(&name, &destination, &mut entry)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn extract_tar_gz<R: Read>(&mut self, reader: R) -> ApiResult<()> {
+    let mut archive = tar::Archive::new(GzDecoder::new(reader));
+    let entries = archive
+      .entries()
+      .map_err(|err| ApiError::bad_request(&format!("Invalid tar.gz archive: {err}")))?;
+    for entry in entries {
+      self.count_entry()?;
+      let mut entry =
+        entry.map_err(|err| ApiError::bad_request(&format!("Invalid tar.gz archive: {err}")))?;
+      let name = entry
+        .path()
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or_default();
+      let entry_type = entry.header().entry_type();
+      let Some(destination) = self.destination(&name) else {
+        self.summary.skipped.push(name);
+        continue;
+      };
+      if entry_type.is_dir() {
+        self.create_dir(&destination)?;
+      } else if entry_type.is_file() {
+        self.write_file(&name, &destination, &mut entry)?;
+      } else {
+        self.summary.skipped.push(name);
+      }
+    }
+    Ok(())
+  }
+
+  fn count_entry(&mut self) -> ApiResult<()> {
+    self.entries += 1;
+    if self.entries > MAX_EXTRACT_ENTRIES {
+      return Err(ApiError::status(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        &format!("Archive has more than {MAX_EXTRACT_ENTRIES} entries"),
+      ));
+    }
+    Ok(())
+  }
+
+  /// Maps an entry name to a path inside the target, rejecting absolute
+  /// paths, `..` segments and anything the sandbox does not allow.
+  fn destination(&self, name: &str) -> Option<PathBuf> {
+    let relative = Path::new(name);
+    if relative
+      .components()
+      .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+      return None;
+    }
+    let destination = self.target.join(relative);
+    (destination != self.target && self.policy.permits(&destination)).then_some(destination)
+  }
+
+  /// Creates the parent directories of `destination` and confirms that no
+  /// existing symlink along the way leads out of the target. The deepest
+  /// existing ancestor is checked before anything is created, so a symlinked
+  /// directory cannot be used to create directories outside the target.
+  fn prepare_parent(&self, destination: &Path) -> ApiResult<bool> {
+    let Some(parent) = destination.parent() else {
+      return Ok(false);
+    };
+    let Some(existing) = parent.ancestors().find(|ancestor| ancestor.exists()) else {
+      return Ok(false);
+    };
+    let contained = std::fs::canonicalize(existing)
+      .map(|existing| existing.starts_with(&self.target))
+      .unwrap_or(false);
+    if !contained {
+      return Ok(false);
+    }
+    std::fs::create_dir_all(parent)
+      .map_err(|err| ApiError::status(StatusCode::FORBIDDEN, &format!("Failed to create directory: {err}")))?;
+    Ok(
+      std::fs::canonicalize(parent)
+        .map(|parent| parent.starts_with(&self.target))
+        .unwrap_or(false),
+    )
+  }
+
+  fn create_dir(&mut self, destination: &Path) -> ApiResult<()> {
+    if !self.prepare_parent(destination)? {
+      self.summary.skipped.push(destination.to_string_lossy().to_string());
+      return Ok(());
+    }
+    if !destination.is_dir() {
+      std::fs::create_dir(destination)
+        .map_err(|err| ApiError::status(StatusCode::FORBIDDEN, &format!("Failed to create directory: {err}")))?;
+      self.summary.directories += 1;
+    }
+    Ok(())
+  }
+
+  fn write_file(&mut self, name: &str, destination: &Path, reader: &mut impl Read) -> ApiResult<()> {
+    let escapes = !self.prepare_parent(destination)?;
+    let is_link = std::fs::symlink_metadata(destination)
+      .map(|meta| meta.file_type().is_symlink())
+      .unwrap_or(false);
+    if escapes || is_link || (destination.exists() && !self.overwrite) {
+      self.summary.skipped.push(name.to_string());
+      return Ok(());
+    }
+    let mut file = std::fs::File::create(destination)
+      .map_err(|err| ApiError::status(StatusCode::FORBIDDEN, &format!("Failed to write {name}: {err}")))?;
+    let remaining = MAX_EXTRACT_BYTES - self.summary.bytes;
+    let bytes = std::io::copy(&mut reader.take(remaining + 1), &mut file)
+      .map_err(|err| ApiError::bad_request(&format!("Failed to extract {name}: {err}")))?;
+    if bytes > remaining {
+      drop(file);
+      let _ = std::fs::remove_file(destination);
+      return Err(ApiError::status(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        &format!("Archive expands to more than {MAX_EXTRACT_BYTES} bytes"),
+      ));
+    }
+    self.summary.files += 1;
+    self.summary.bytes += bytes;
+    Ok(())
+  }
+}
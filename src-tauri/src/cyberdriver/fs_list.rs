@@ -0,0 +1,313 @@
+use std::{
+  cmp::Ordering,
+  path::{Path, PathBuf},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+  extract::{Query, State},
+  http::StatusCode,
+  Json,
+};
+use glob::Pattern;
+use serde::Deserialize;
+
+use super::{
+  api::{ApiError, ApiResult, ApiState},
+  fs::blocking,
+  fs_policy::FsPolicy,
+};
+
+const MAX_LIST_LIMIT: usize = 10_000;
+const MAX_LIST_DEPTH: usize = 32;
+/// Hard cap on entries visited by one request, so a deep listing of a huge
+/// tree cannot stall the API.
+const MAX_SCANNED_ENTRIES: usize = 200_000;
+
+#[derive(Deserialize)]
+pub(super) struct FsListQuery {
+  path: Option<String>,
+  depth: Option<usize>,
+  pattern: Option<String>,
+  include_hidden: Option<bool>,
+  sort: Option<String>,
+  order: Option<String>,
+  limit: Option<usize>,
+  cursor: Option<String>,
+  mode: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum SortKey {
+  Type,
+  Name,
+  Size,
+  Modified,
+}
+
+impl SortKey {
+  fn from_str(sort: &str) -> Option<Self> {
+    match sort.to_lowercase().as_str() {
+      "type" => Some(Self::Type),
+      "name" => Some(Self::Name),
+      "size" => Some(Self::Size),
+      "modified" | "mtime" => Some(Self::Modified),
+      _ => None,
+    }
+  }
+}
+
+struct ListOptions {
+  depth: usize,
+  pattern: Option<Pattern>,
+  include_hidden: bool,
+  sort: SortKey,
+  descending: bool,
+}
+
+struct Entry {
+  path: PathBuf,
+  name: String,
+  depth: usize,
+  is_dir: bool,
+  size: Option<u64>,
+  modified: Option<SystemTime>,
+  child_count: Option<usize>,
+  children: Vec<Entry>,
+}
+
+impl Entry {
+  fn to_json(&self) -> serde_json::Value {
+    serde_json::json!({
+      "name": self.name,
+      "path": self.path.to_string_lossy(),
+      "is_dir": self.is_dir,
+      "size": self.size,
+      "modified": self
+        .modified
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs_f64()),
+      "depth": self.depth,
+    })
+  }
+
+  fn to_tree_json(&self) -> serde_json::Value {
+    let mut node = self.to_json();
+    if self.is_dir {
+      node["child_count"] = serde_json::json!(self.child_count);
+      node["children"] = self.children.iter().map(Entry::to_tree_json).collect();
+    }
+    node
+  }
+}
+
+/// Lists a directory. `depth` > 1 recurses (symlinked directories are not
+/// followed), `pattern` filters entry names, and flat results are sorted and
+/// paginated with `limit` and the opaque `cursor` from the previous page.
+/// Without `limit` every entry is returned, as before pagination existed.
+/// `mode=tree` returns nested nodes with per-directory child counts instead.
+pub(super) async fn get_fs_list(
+  State(state): State<ApiState>,
+  Query(query): Query<FsListQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let policy = FsPolicy::load(&state).await;
+  let path = query.path.unwrap_or_else(|| ".".to_string());
+  let safe_path = policy.readable(path)?;
+  if !safe_path.exists() {
+    return Err(ApiError::status(StatusCode::NOT_FOUND, "Directory not found"));
+  }
+  if !safe_path.is_dir() {
+    return Err(ApiError::bad_request("Path is not a directory"));
+  }
+  let options = ListOptions {
+    depth: query.depth.unwrap_or(1).clamp(1, MAX_LIST_DEPTH),
+    pattern: query
+      .pattern
+      .filter(|pattern| !pattern.is_empty())
+      .map(|pattern| {
+        Pattern::new(&pattern)
+          .map_err(|err| ApiError::bad_request(&format!("Invalid pattern: {err}")))
+      })
+      .transpose()?,
+    include_hidden: query.include_hidden.unwrap_or(true),
+    sort: match query.sort.as_deref() {
+      Some(sort) => SortKey::from_str(sort).ok_or_else(|| {
+        ApiError::bad_request("sort must be 'type', 'name', 'size' or 'modified'")
+      })?,
+      None => SortKey::Type,
+    },
+    descending: query
+      .order
+      .as_deref()
+      .is_some_and(|order| order.eq_ignore_ascii_case("desc")),
+  };
+  let limit = query
+    .limit
+    .map_or(usize::MAX, |limit| limit.clamp(1, MAX_LIST_LIMIT));
+  let offset = match query.cursor.as_deref() {
+    Some(cursor) => cursor
+      .parse::<usize>()
+      .map_err(|_| ApiError::bad_request("Invalid cursor"))?,
+    None => 0,
+  };
+  let tree_mode = query
+    .mode
+    .as_deref()
+    .is_some_and(|mode| mode.eq_ignore_ascii_case("tree"));
+
+  let root = safe_path.clone();
+  blocking(move || {
+    let mut scanned = 0;
+    let mut children = read_level(&root, 1, &options, &policy, &mut scanned)
+      .map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to list directory"))?;
+    let scan_truncated = scanned >= MAX_SCANNED_ENTRIES;
+
+    if tree_mode {
+      let mut emitted = 0;
+      prune_tree(&mut children, limit, &mut emitted);
+      return Ok(Json(serde_json::json!({
+        "path": root.to_string_lossy(),
+        "child_count": children.len(),
+        "children": children.iter().map(Entry::to_tree_json).collect::<Vec<_>>(),
+        "truncated": scan_truncated || emitted >= limit,
+      })));
+    }
+
+    let mut entries = Vec::new();
+    flatten(children, options.pattern.as_ref(), &mut entries);
+    entries.sort_by(|a, b| compare(a, b, &options));
+    let total = entries.len();
+    let page: Vec<_> = entries
+      .iter()
+      .skip(offset)
+      .take(limit)
+      .map(Entry::to_json)
+      .collect();
+    let next_offset = offset + page.len();
+    Ok(Json(serde_json::json!({
+      "path": root.to_string_lossy(),
+      "entries": page,
+      "total": total,
+      "next_cursor": (next_offset < total).then(|| next_offset.to_string()),
+      "truncated": scan_truncated,
+    })))
+  })
+  .await
+}
+
+/// Reads one directory level and, below the depth limit, its subdirectories.
+/// Directories are always descended into; `pattern` and the hidden filter
+/// only decide which entries are reported.
+fn read_level(
+  dir: &Path,
+  depth: usize,
+  options: &ListOptions,
+  policy: &FsPolicy,
+  scanned: &mut usize,
+) -> std::io::Result<Vec<Entry>> {
+  let mut entries = Vec::new();
+  for item in std::fs::read_dir(dir)?.flatten() {
+    if *scanned >= MAX_SCANNED_ENTRIES {
+      break;
+    }
+    *scanned += 1;
+    let path = item.path();
+    if !policy.permits(&path) {
+      continue;
+    }
+    let name = item.file_name().to_string_lossy().to_string();
+    let Ok(meta) = item.metadata() else {
+      continue;
+    };
+    if !options.include_hidden && is_hidden(&name, &meta) {
+      continue;
+    }
+    let is_dir = meta.is_dir();
+    let children = if is_dir && depth < options.depth {
+      read_level(&path, depth + 1, options, policy, scanned).unwrap_or_default()
+    } else {
+      Vec::new()
+    };
+    let child_count = is_dir.then(|| {
+      if depth < options.depth {
+        children.len()
+      } else {
+        std::fs::read_dir(&path).map(|dir| dir.count()).unwrap_or(0)
+      }
+    });
+    entries.push(Entry {
+      path,
+      name,
+      depth,
+      is_dir,
+      size: meta.is_file().then(|| meta.len()),
+      modified: meta.modified().ok(),
+      child_count,
+      children,
+    });
+  }
+  if let Some(pattern) = &options.pattern {
+    entries.retain(|entry| entry_matches(entry, pattern));
+  }
+  entries.sort_by(|a, b| compare(a, b, options));
+  Ok(entries)
+}
+
+/// Keeps an entry if its name matches, or if it is a directory with
+/// matching descendants (so tree mode still shows where matches live).
+fn entry_matches(entry: &Entry, pattern: &Pattern) -> bool {
+  pattern.matches(&entry.name) || !entry.children.is_empty()
+}
+
+/// Flattens a tree depth-first; with a pattern, only matching entries are
+/// kept (their ancestors were only retained to reach them).
+fn flatten(entries: Vec<Entry>, pattern: Option<&Pattern>, output: &mut Vec<Entry>) {
+  for mut entry in entries {
+    let children = std::mem::take(&mut entry.children);
+    if pattern.is_none_or(|pattern| pattern.matches(&entry.name)) {
+      output.push(entry);
+    }
+    flatten(children, pattern, output);
+  }
+}
+
+/// Limits a tree to `limit` nodes in depth-first order.
+fn prune_tree(entries: &mut Vec<Entry>, limit: usize, emitted: &mut usize) {
+  let mut keep = 0;
+  for entry in entries.iter_mut() {
+    if *emitted >= limit {
+      break;
+    }
+    *emitted += 1;
+    keep += 1;
+    prune_tree(&mut entry.children, limit, emitted);
+  }
+  entries.truncate(keep);
+}
+
+fn compare(a: &Entry, b: &Entry, options: &ListOptions) -> Ordering {
+  let ordering = match options.sort {
+    SortKey::Type => b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)),
+    SortKey::Name => a.name.cmp(&b.name),
+    SortKey::Size => a.size.cmp(&b.size).then_with(|| a.name.cmp(&b.name)),
+    SortKey::Modified => a.modified.cmp(&b.modified).then_with(|| a.name.cmp(&b.name)),
+  };
+  let ordering = ordering.then_with(|| a.path.cmp(&b.path));
+  if options.descending {
+    ordering.reverse()
+  } else {
+    ordering
+  }
+}
+
+#[cfg(windows)]
+fn is_hidden(name: &str, meta: &std::fs::Metadata) -> bool {
+  use std::os::windows::fs::MetadataExt;
+  const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+  name.starts_with('.') || meta.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0
+}
+
+#[cfg(not(windows))]
+fn is_hidden(name: &str, _meta: &std::fs::Metadata) -> bool {
+  name.starts_with('.')
+}
@@ -0,0 +1,149 @@
+use std::path::{Component, Path, PathBuf};
+
+use axum::http::StatusCode;
+
+use super::{
+  api::{ApiError, ApiResult, ApiState},
+  fs::ExpandPath,
+  CyberdriverSettings,
+};
+
+/// Confines the fs endpoints to the sandbox roots from the settings.
+///
+/// Paths are canonicalized (symlinks resolved) before they are compared, so
+/// `..` segments and links cannot be used to step outside an allowed root.
+pub(super) struct FsPolicy {
+  allowed_roots: Vec<PathBuf>,
+  denied_roots: Vec<PathBuf>,
+  read_only: bool,
+}
+
+impl FsPolicy {
+  pub(super) fn from_settings(settings: &CyberdriverSettings) -> Self {
+    Self {
+      allowed_roots: canonical_roots(&settings.fs_allowed_roots),
+      denied_roots: canonical_roots(&settings.fs_denied_roots),
+      read_only: settings.fs_read_only,
+    }
+  }
+
+  pub(super) async fn load(state: &ApiState) -> Self {
+    Self::from_settings(&*state.settings.lock().await)
+  }
+
+  /// Resolves `path` and checks that it may be read.
+  pub(super) fn readable(&self, path: impl Into<PathBuf>) -> ApiResult<PathBuf> {
+    let path = canonicalize_lenient(path.into().expand_dir())?;
+    if !self.permits(&path) {
+      return Err(ApiError::status(
+        StatusCode::FORBIDDEN,
+        &format!("Access to {} is outside the allowed filesystem roots", path.display()),
+      ));
+    }
+    Ok(path)
+  }
+
+  /// Resolves `path` and checks that it may be created, modified or deleted.
+  pub(super) fn writable(&self, path: impl Into<PathBuf>) -> ApiResult<PathBuf> {
+    if self.read_only {
+      return Err(ApiError::status(
+        StatusCode::FORBIDDEN,
+        "Filesystem access is read-only",
+      ));
+    }
+    self.readable(path)
+  }
+
+  /// True if an already resolved path (for example a listing entry) is inside
+  /// the sandbox, including wherever it points to when it is a symlink.
+  pub(super) fn permits(&self, path: &Path) -> bool {
+    let target = std::fs::canonicalize(path).ok();
+    [Some(path), target.as_deref()]
+      .into_iter()
+      .flatten()
+      .all(|path| self.permits_resolved(path))
+  }
+
+  /// True if a denied root lies underneath `path`, so recursive operations on
+  /// it would reach into the denied area.
+  pub(super) fn contains_denied(&self, path: &Path) -> bool {
+    self.denied_roots.iter().any(|root| is_within(root, path))
+  }
+
+  fn permits_resolved(&self, path: &Path) -> bool {
+    let allowed =
+      self.allowed_roots.is_empty() || self.allowed_roots.iter().any(|root| is_within(path, root));
+    allowed && !self.denied_roots.iter().any(|root| is_within(path, root))
+  }
+}
+
+fn canonical_roots(roots: &[String]) -> Vec<PathBuf> {
+  roots
+    .iter()
+    .map(|root| root.trim())
+    .filter(|root| !root.is_empty())
+    .map(|root| {
+      let root = PathBuf::from(root).expand_dir();
+      canonicalize_lenient(root.clone()).unwrap_or(root)
+    })
+    .collect()
+}
+
+/// Canonicalizes the parent directory of `path` and keeps the final component
+/// as-is, so a symlink itself (rather than its target) is what gets addressed.
+/// Missing directories are allowed, but not `..` segments inside them.
+fn canonicalize_lenient(path: PathBuf) -> ApiResult<PathBuf> {
+  let path = if path.is_absolute() {
+    path
+  } else {
+    std::env::current_dir()
+      .map_err(|err| ApiError::internal(&err.to_string()))?
+      .join(path)
+  };
+  let Some(name) = path.file_name().map(|name| name.to_os_string()) else {
+    // Filesystem roots and paths ending in `..` have no final component to keep.
+    return std::fs::canonicalize(&path)
+      .map_err(|_| ApiError::status(StatusCode::NOT_FOUND, "Path not found"));
+  };
+  let parent = path.parent().unwrap_or(Path::new(""));
+  let mut missing = Vec::new();
+  let mut existing = parent;
+  let base = loop {
+    match std::fs::canonicalize(existing) {
+      Ok(base) => break base,
+      Err(_) => {
+        let Some(component) = existing.components().next_back() else {
+          return Err(ApiError::bad_request("Path has no existing ancestor"));
+        };
+        if !matches!(component, Component::Normal(_)) {
+          return Err(ApiError::bad_request(
+            "Path must not contain '..' below a directory that does not exist",
+          ));
+        }
+        missing.push(component.as_os_str().to_os_string());
+        existing = existing
+          .parent()
+          .ok_or_else(|| ApiError::bad_request("Path has no existing ancestor"))?;
+      }
+    }
+  };
+  let mut resolved = base;
+  resolved.extend(missing.into_iter().rev());
+  resolved.push(name);
+  Ok(resolved)
+}
+
+fn is_within(path: &Path, root: &Path) -> bool {
+  if !cfg!(windows) {
+    return path.starts_with(root);
+  }
+  let mut path = path.components();
+  root.components().all(|root| {
+    path.next().is_some_and(|part| {
+      part
+        .as_os_str()
+        .to_string_lossy()
+        .eq_ignore_ascii_case(&root.as_os_str().to_string_lossy())
+    })
+  })
+}
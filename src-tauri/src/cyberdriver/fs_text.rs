@@ -0,0 +1,312 @@
+use std::{
+  io::{Read, Seek, SeekFrom},
+  path::{Path, PathBuf},
+};
+
+use axum::{
+  extract::{Query, State},
+  http::StatusCode,
+  Json,
+};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use glob::Pattern;
+use regex::RegexBuilder;
+use serde::Deserialize;
+
+use super::{
+  api::{ApiError, ApiResult, ApiState},
+  fs::blocking,
+  fs_policy::FsPolicy,
+};
+
+const DEFAULT_TEXT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const MAX_TEXT_MAX_BYTES: u64 = 100 * 1024 * 1024;
+const DEFAULT_SEARCH_MATCHES: usize = 200;
+const MAX_SEARCH_MATCHES: usize = 5000;
+const MAX_SEARCH_LINE_CHARS: usize = 500;
+/// Hard caps on files and directories visited, and on file bytes read, by one
+/// search, so a search over a huge tree cannot stall the API.
+const MAX_SEARCH_SCANNED_ENTRIES: usize = 200_000;
+const MAX_SEARCH_READ_BYTES: u64 = 1024 * 1024 * 1024;
+/// Files with a NUL byte in this prefix are treated as binary and skipped.
+const BINARY_SNIFF_BYTES: usize = 8192;
+
+#[derive(Deserialize)]
+pub(super) struct FsReadTextQuery {
+  path: String,
+  encoding: Option<String>,
+  start_line: Option<usize>,
+  end_line: Option<usize>,
+  tail: Option<usize>,
+  max_bytes: Option<u64>,
+}
+
+/// Returns a file as decoded text. The encoding is taken from a BOM, else
+/// UTF-8 if the bytes are valid UTF-8, else Windows-1252, unless `encoding`
+/// names one. `start_line`/`end_line` (1-based, inclusive) select a range,
+/// `tail` the last N lines. At most `max_bytes` are read; `tail` reads them
+/// from the end of the file.
+pub(super) async fn get_fs_read_text(
+  State(state): State<ApiState>,
+  Query(query): Query<FsReadTextQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let policy = FsPolicy::load(&state).await;
+  let path = policy.readable(query.path.as_str())?;
+  if !path.exists() {
+    return Err(ApiError::status(StatusCode::NOT_FOUND, "File not found"));
+  }
+  if !path.is_file() {
+    return Err(ApiError::bad_request("Path is not a file"));
+  }
+  let forced = match query.encoding.as_deref() {
+    Some(label) => Some(
+      Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| ApiError::bad_request(&format!("Unknown encoding '{label}'")))?,
+    ),
+    None => None,
+  };
+  if query.tail.is_some() && (query.start_line.is_some() || query.end_line.is_some()) {
+    return Err(ApiError::bad_request("tail cannot be combined with start_line/end_line"));
+  }
+  if query.start_line == Some(0) || query.end_line == Some(0) {
+    return Err(ApiError::bad_request("Line numbers start at 1"));
+  }
+  let max_bytes = query
+    .max_bytes
+    .unwrap_or(DEFAULT_TEXT_MAX_BYTES)
+    .clamp(1, MAX_TEXT_MAX_BYTES);
+
+  blocking(move || {
+    let mut file = std::fs::File::open(&path)
+      .map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to read file"))?;
+    let total_size = file
+      .metadata()
+      .map_err(|err| ApiError::internal(&err.to_string()))?
+      .len();
+    let mut head = [0u8; 4];
+    let head_len = file.read(&mut head).unwrap_or(0);
+    let bom = Encoding::for_bom(&head[..head_len]);
+
+    let bom_len = bom.map(|(_, len)| len as u64).unwrap_or(0);
+    let encoding = forced.or(bom.map(|(encoding, _)| encoding));
+    let from_end = query.tail.is_some() && total_size > max_bytes + bom_len;
+    let mut start = if from_end { total_size - max_bytes } else { bom_len };
+    if from_end && is_utf16(encoding) && (start - bom_len) % 2 == 1 {
+      // Keep UTF-16 code units aligned.
+      start += 1;
+    }
+    let mut bytes = Vec::new();
+    file
+      .seek(SeekFrom::Start(start))
+      .and_then(|_| (&mut file).take(max_bytes).read_to_end(&mut bytes))
+      .map_err(|_| ApiError::status(StatusCode::FORBIDDEN, "Permission denied to read file"))?;
+    let truncated = start > bom_len || start + (bytes.len() as u64) < total_size;
+
+    let (text, encoding) = decode(&bytes, encoding);
+    let mut lines: Vec<&str> = text.lines().collect();
+    if from_end && !lines.is_empty() {
+      // The first line was most likely cut by the byte window.
+      lines.remove(0);
+    }
+
+    let (first, selected) = if let Some(tail) = query.tail {
+      let skip = lines.len().saturating_sub(tail);
+      (skip + 1, &lines[skip..])
+    } else {
+      let first = query.start_line.unwrap_or(1);
+      let last = query.end_line.unwrap_or(lines.len()).min(lines.len());
+      let range = if first <= last { &lines[first - 1..last] } else { &lines[0..0] };
+      (first, range)
+    };
+
+    Ok(Json(serde_json::json!({
+      "path": path.to_string_lossy(),
+      "encoding": encoding.name(),
+      "had_bom": bom.is_some(),
+      "size": total_size,
+      "truncated": truncated,
+      // Line numbers are relative to the decoded window when it does not
+      // start at the beginning of the file.
+      "start_line": first,
+      "end_line": first + selected.len().saturating_sub(1),
+      "line_count": selected.len(),
+      "total_lines": (!truncated).then_some(lines.len()),
+      "content": selected.join("\n"),
+    })))
+  })
+  .await
+}
+
+#[derive(Deserialize)]
+pub(super) struct FsSearchQuery {
+  root: String,
+  query: String,
+  #[serde(default)]
+  regex: bool,
+  #[serde(default)]
+  case_sensitive: bool,
+  glob: Option<String>,
+  max_matches: Option<usize>,
+  max_file_size: Option<u64>,
+  include_hidden: Option<bool>,
+}
+
+/// Greps text files under `root` for `query` (a literal, or a regex with
+/// `regex=true`). Binary files and files over `max_file_size` are skipped,
+/// and `glob` filters file names. `truncated` is set when the search stopped
+/// early, at `max_matches` or at the scan caps.
+pub(super) async fn get_fs_search(
+  State(state): State<ApiState>,
+  Query(query): Query<FsSearchQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+  if query.query.is_empty() {
+    return Err(ApiError::bad_request("Missing 'query' parameter"));
+  }
+  let policy = FsPolicy::load(&state).await;
+  let root = policy.readable(query.root.as_str())?;
+  if !root.exists() {
+    return Err(ApiError::status(StatusCode::NOT_FOUND, "Path not found"));
+  }
+  let pattern = if query.regex {
+    query.query.clone()
+  } else {
+    regex::escape(&query.query)
+  };
+  let matcher = RegexBuilder::new(&pattern)
+    .case_insensitive(!query.case_sensitive)
+    .build()
+    .map_err(|err| ApiError::bad_request(&format!("Invalid regex: {err}")))?;
+  let name_filter = query
+    .glob
+    .as_deref()
+    .filter(|glob| !glob.is_empty())
+    .map(|glob| {
+      Pattern::new(glob).map_err(|err| ApiError::bad_request(&format!("Invalid glob: {err}")))
+    })
+    .transpose()?;
+  let max_matches = query
+    .max_matches
+    .unwrap_or(DEFAULT_SEARCH_MATCHES)
+    .clamp(1, MAX_SEARCH_MATCHES);
+  let max_file_size = query
+    .max_file_size
+    .unwrap_or(DEFAULT_TEXT_MAX_BYTES)
+    .min(MAX_TEXT_MAX_BYTES);
+  let include_hidden = query.include_hidden.unwrap_or(false);
+
+  blocking(move || {
+    let mut matches = Vec::new();
+    let mut files_scanned = 0usize;
+    let mut entries_scanned = 0usize;
+    let mut bytes_read = 0u64;
+    let mut scan_truncated = false;
+    let mut pending = vec![root.clone()];
+    while let Some(path) = pending.pop() {
+      if matches.len() >= max_matches {
+        break;
+      }
+      if entries_scanned >= MAX_SEARCH_SCANNED_ENTRIES {
+        scan_truncated = true;
+        break;
+      }
+      entries_scanned += 1;
+      let Ok(meta) = std::fs::symlink_metadata(&path) else {
+        continue;
+      };
+      if meta.is_dir() {
+        if let Ok(items) = std::fs::read_dir(&path) {
+          let mut children: Vec<PathBuf> = items
+            .flatten()
+            .filter(|item| include_hidden || !item.file_name().to_string_lossy().starts_with('.'))
+            .map(|item| item.path())
+            .filter(|child| policy.permits(child))
+            .collect();
+          children.sort_unstable_by(|a, b| b.cmp(a));
+          pending.extend(children);
+        }
+        continue;
+      }
+      if !meta.is_file() || meta.len() > max_file_size {
+        continue;
+      }
+      let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+      if name_filter.as_ref().is_some_and(|filter| !filter.matches(&name)) {
+        continue;
+      }
+      if bytes_read + meta.len() > MAX_SEARCH_READ_BYTES {
+        scan_truncated = true;
+        break;
+      }
+      bytes_read += meta.len();
+      let Some(text) = read_text_file(&path) else {
+        continue;
+      };
+      files_scanned += 1;
+      for (index, line) in text.lines().enumerate() {
+        if let Some(found) = matcher.find(line) {
+          matches.push(serde_json::json!({
+            "path": path.to_string_lossy(),
+            "line": index + 1,
+            "column": line[..found.start()].chars().count() + 1,
+            "text": line.chars().take(MAX_SEARCH_LINE_CHARS).collect::<String>(),
+          }));
+          if matches.len() >= max_matches {
+            break;
+          }
+        }
+      }
+    }
+    let truncated = scan_truncated || matches.len() >= max_matches;
+    Ok(Json(serde_json::json!({
+      "root": root.to_string_lossy(),
+      "matches": matches,
+      "files_scanned": files_scanned,
+      "truncated": truncated,
+    })))
+  })
+  .await
+}
+
+fn is_utf16(encoding: Option<&'static Encoding>) -> bool {
+  encoding.is_some_and(|encoding| encoding == UTF_16LE || encoding == UTF_16BE)
+}
+
+/// Decodes `bytes` (without a BOM) as `encoding`, or detects UTF-8 versus
+/// Windows-1252 when none is known.
+fn decode(bytes: &[u8], encoding: Option<&'static Encoding>) -> (String, &'static Encoding) {
+  let encoding = encoding.unwrap_or_else(|| {
+    if std::str::from_utf8(bytes).is_ok() || utf8_valid_up_to_cut(bytes) {
+      UTF_8
+    } else {
+      WINDOWS_1252
+    }
+  });
+  let (text, _) = encoding.decode_without_bom_handling(bytes);
+  (text.into_owned(), encoding)
+}
+
+/// True if `bytes` is valid UTF-8 apart from a sequence cut at either end,
+/// as happens with byte windows from the middle of a file.
+fn utf8_valid_up_to_cut(bytes: &[u8]) -> bool {
+  let start = bytes
+    .iter()
+    .take(3)
+    .position(|byte| byte & 0xC0 != 0x80)
+    .unwrap_or(0);
+  match std::str::from_utf8(&bytes[start..]) {
+    Ok(_) => true,
+    Err(err) => err.error_len().is_none(),
+  }
+}
+
+/// Reads a whole file as text for searching, or `None` if it looks binary.
+fn read_text_file(path: &Path) -> Option<String> {
+  let bytes = std::fs::read(path).ok()?;
+  if let Some((encoding, bom_len)) = Encoding::for_bom(&bytes) {
+    return Some(decode(&bytes[bom_len..], Some(encoding)).0);
+  }
+  if bytes.iter().take(BINARY_SNIFF_BYTES).any(|byte| *byte == 0) {
+    return None;
+  }
+  Some(decode(&bytes, None).0)
+}
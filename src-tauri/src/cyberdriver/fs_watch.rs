@@ -0,0 +1,249 @@
+use std::{
+  collections::HashMap,
+  path::{Path, PathBuf},
+  time::{Duration, Instant, SystemTime},
+};
+
+use axum::{extract::State, Json};
+use notify::{RecursiveMode, Watcher};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use super::{
+  api::{ApiError, ApiResult, ApiState},
+  fs::{entry_json, split_glob, ExpandPath},
+  fs_policy::FsPolicy,
+};
+
+const DEFAULT_WAIT_TIMEOUT_SECONDS: f64 = 30.0;
+const MAX_WAIT_TIMEOUT_SECONDS: f64 = 600.0;
+const DEFAULT_STABLE_MS: u64 = 1000;
+/// Re-check interval when no watcher events arrive; covers network drives
+/// and the stable-size timer.
+const RECHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum WaitCondition {
+  Exists,
+  Stable,
+  Deleted,
+  Modified,
+}
+
+impl WaitCondition {
+  fn from_str(condition: &str) -> Option<Self> {
+    match condition.to_lowercase().as_str() {
+      "exists" | "created" => Some(Self::Exists),
+      "stable" => Some(Self::Stable),
+      "deleted" | "removed" => Some(Self::Deleted),
+      "modified" | "changed" => Some(Self::Modified),
+      _ => None,
+    }
+  }
+
+  fn label(self) -> &'static str {
+    match self {
+      Self::Exists => "exists",
+      Self::Stable => "stable",
+      Self::Deleted => "deleted",
+      Self::Modified => "modified",
+    }
+  }
+}
+
+#[derive(Deserialize)]
+pub(super) struct FsWaitPayload {
+  path: String,
+  condition: Option<String>,
+  stable_ms: Option<u64>,
+  timeout: Option<f64>,
+}
+
+/// Blocks until `path` (a literal path or glob) meets `condition` or the
+/// timeout passes. Filesystem events trigger re-checks, with a short poll as
+/// a fallback. A timeout is reported as `matched: false`, not as an error.
+pub(super) async fn post_fs_wait(
+  State(state): State<ApiState>,
+  Json(payload): Json<FsWaitPayload>,
+) -> ApiResult<Json<serde_json::Value>> {
+  if payload.path.is_empty() {
+    return Err(ApiError::bad_request("Missing 'path' field"));
+  }
+  let condition = match payload.condition.as_deref() {
+    Some(condition) => WaitCondition::from_str(condition).ok_or_else(|| {
+      ApiError::bad_request("condition must be 'exists', 'stable', 'deleted' or 'modified'")
+    })?,
+    None => WaitCondition::Exists,
+  };
+  let timeout = payload.timeout.unwrap_or(DEFAULT_WAIT_TIMEOUT_SECONDS);
+  if !(0.0..=MAX_WAIT_TIMEOUT_SECONDS).contains(&timeout) {
+    return Err(ApiError::bad_request("timeout must be between 0 and 600 seconds"));
+  }
+  let stable_for = Duration::from_millis(payload.stable_ms.unwrap_or(DEFAULT_STABLE_MS));
+
+  let policy = FsPolicy::load(&state).await;
+  let raw = PathBuf::from(&payload.path).expand_dir();
+  let is_glob = raw.to_string_lossy().contains(['*', '?', '[']);
+  let target = if is_glob {
+    let (base, rest) = split_glob(&raw);
+    policy.readable(base)?.join(rest)
+  } else {
+    policy.readable(raw)?
+  };
+  if is_glob {
+    glob::Pattern::new(&target.to_string_lossy())
+      .map_err(|err| ApiError::bad_request(&format!("Invalid glob pattern: {err}")))?;
+  }
+
+  let (sender, mut events) = mpsc::unbounded_channel::<()>();
+  // Registering a recursive watch walks the whole tree, so keep it off the
+  // async runtime.
+  let watch_target = target.clone();
+  let watcher = tokio::task::spawn_blocking(move || {
+    let mut watcher = notify::recommended_watcher(move |_event: notify::Result<notify::Event>| {
+      let _ = sender.send(());
+    })
+    .ok()?;
+    let (root, mode) = watch_root(&watch_target, is_glob)?;
+    watcher.watch(&root, mode).ok()?;
+    Some(watcher)
+  })
+  .await
+  .ok()
+  .flatten();
+
+  let mut waiter = Waiter {
+    target: target.clone(),
+    is_glob,
+    policy,
+    condition,
+    stable_for,
+    baseline: HashMap::new(),
+    observed: HashMap::new(),
+  };
+  waiter.baseline = waiter.snapshot();
+
+  let started = Instant::now();
+  let deadline = started + Duration::from_secs_f64(timeout);
+  let matched = loop {
+    if let Some(matched) = waiter.check() {
+      break Some(matched);
+    }
+    let now = Instant::now();
+    if now >= deadline {
+      break None;
+    }
+    tokio::select! {
+      Some(()) = events.recv() => {
+        // A burst of events needs only one re-check.
+        while events.try_recv().is_ok() {}
+      }
+      _ = tokio::time::sleep(RECHECK_INTERVAL.min(deadline - now)) => {}
+    }
+  };
+  drop(watcher);
+
+  let file = matched.as_ref().and_then(|matched| {
+    matched.as_ref().and_then(|path| {
+      std::fs::symlink_metadata(path)
+        .ok()
+        .map(|meta| entry_json(path, &meta))
+    })
+  });
+  Ok(Json(serde_json::json!({
+    "matched": matched.is_some(),
+    "condition": condition.label(),
+    "path": target.to_string_lossy(),
+    "elapsed": started.elapsed().as_secs_f64(),
+    "file": file,
+  })))
+}
+
+type FileState = (u64, Option<SystemTime>);
+
+struct Waiter {
+  target: PathBuf,
+  is_glob: bool,
+  policy: FsPolicy,
+  condition: WaitCondition,
+  stable_for: Duration,
+  baseline: HashMap<PathBuf, FileState>,
+  observed: HashMap<PathBuf, (FileState, Instant)>,
+}
+
+impl Waiter {
+  fn snapshot(&self) -> HashMap<PathBuf, FileState> {
+    let paths: Vec<PathBuf> = if self.is_glob {
+      glob::glob(&self.target.to_string_lossy())
+        .map(|paths| paths.flatten().collect())
+        .unwrap_or_default()
+    } else {
+      vec![self.target.clone()]
+    };
+    paths
+      .into_iter()
+      .filter(|path| self.policy.permits(path))
+      .filter_map(|path| {
+        let meta = std::fs::metadata(&path).ok()?;
+        Some((path, (meta.len(), meta.modified().ok())))
+      })
+      .collect()
+  }
+
+  /// Returns `Some(matched path)` once the condition holds; the path is
+  /// `None` for deletions.
+  fn check(&mut self) -> Option<Option<PathBuf>> {
+    let current = self.snapshot();
+    match self.condition {
+      WaitCondition::Exists => first_path(current.keys()).map(Some),
+      WaitCondition::Deleted => current.is_empty().then_some(None),
+      WaitCondition::Modified => {
+        let changed = current
+          .iter()
+          .filter(|(path, state)| self.baseline.get(*path) != Some(*state))
+          .map(|(path, _)| path);
+        first_path(changed).map(Some)
+      }
+      WaitCondition::Stable => {
+        let now = Instant::now();
+        self.observed.retain(|path, _| current.contains_key(path));
+        for (path, state) in &current {
+          match self.observed.get(path) {
+            Some((previous, _)) if previous == state => {}
+            _ => {
+              self.observed.insert(path.clone(), (*state, now));
+            }
+          }
+        }
+        let stable = self
+          .observed
+          .iter()
+          .filter(|(_, (_, since))| now.duration_since(*since) >= self.stable_for)
+          .map(|(path, _)| path);
+        first_path(stable).map(Some)
+      }
+    }
+  }
+}
+
+fn first_path<'a>(paths: impl Iterator<Item = &'a PathBuf>) -> Option<PathBuf> {
+  paths.min().cloned()
+}
+
+/// Picks the directory to watch: the nearest existing ancestor of the
+/// target. Recursion is needed when the target is deeper than that
+/// directory's direct children.
+fn watch_root(target: &Path, is_glob: bool) -> Option<(PathBuf, RecursiveMode)> {
+  let literal = if is_glob { split_glob(target).0 } else { target.to_path_buf() };
+  let mut root = if is_glob { literal.clone() } else { literal.parent()?.to_path_buf() };
+  while !root.is_dir() {
+    root = root.parent()?.to_path_buf();
+  }
+  let depth = target.components().count() - root.components().count();
+  let mode = if depth > 1 || target.to_string_lossy().contains("**") {
+    RecursiveMode::Recursive
+  } else {
+    RecursiveMode::NonRecursive
+  };
+  Some((root, mode))
+}
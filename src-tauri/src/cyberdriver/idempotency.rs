@@ -0,0 +1,329 @@
+use std::{
+  collections::{HashMap, VecDeque},
+  path::PathBuf,
+  sync::{Mutex, PoisonError},
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use super::{config, tunnel::TunnelResponse};
+
+/// One JSON entry per line, appended as requests finish.
+const CACHE_FILE: &str = "idempotency-cache.jsonl";
+/// Larger responses are kept in memory only.
+const MAX_PERSISTED_BODY_BYTES: usize = 64 * 1024;
+/// Header added to responses served from the cache instead of re-running
+/// the request.
+const REPLAYED_HEADER: &str = "x-idempotency-replayed";
+
+enum Entry {
+  Done {
+    stored_at: SystemTime,
+    response: TunnelResponse,
+  },
+  /// The first copy of a request is still running; later copies wait for
+  /// the value sent here.
+  InFlight(watch::Receiver<Option<TunnelResponse>>),
+}
+
+/// The cached entries, plus the completed keys oldest first, so expiring
+/// looks only at the front instead of scanning and sorting every entry.
+#[derive(Default)]
+struct Entries {
+  map: HashMap<String, Entry>,
+  done: VecDeque<(SystemTime, String)>,
+}
+
+/// Responses to requests carrying `X-Idempotency-Key`, kept for `ttl` so a
+/// retried request is answered without running again. Copies that arrive
+/// while the first is still running wait for its result. With persistence
+/// on, small completed entries are also written to the config directory
+/// (readable by the current user only) so they survive a restart (e.g.
+/// after `/internal/update`). File contents from `/computer/fs/read*` and
+/// clipboard contents are never written to disk.
+pub struct IdempotencyCache {
+  ttl: Duration,
+  max_entries: usize,
+  entries: Mutex<Entries>,
+  /// Hands entries to the thread that writes the cache file, so requests
+  /// never wait on disk.
+  writer: Option<std::sync::mpsc::Sender<PersistedEntry>>,
+}
+
+pub(super) enum Claim<'a> {
+  /// A stored or just-finished response for the same key.
+  Replay(TunnelResponse),
+  /// This request runs; its result must be passed to
+  /// [`InFlight::finish`] for others to reuse it.
+  Run(InFlight<'a>),
+}
+
+/// The claim on a key held by the request that runs. Dropping it without
+/// finishing (the request failed before producing a cacheable response, or
+/// was aborted) releases the key, and a waiting copy runs instead.
+pub(super) struct InFlight<'a> {
+  cache: &'a IdempotencyCache,
+  key: String,
+  persist: bool,
+  sender: watch::Sender<Option<TunnelResponse>>,
+  finished: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedEntry {
+  key: String,
+  stored_at_ms: u64,
+  status: u16,
+  headers: HashMap<String, String>,
+  body_base64: String,
+}
+
+impl IdempotencyCache {
+  /// Creates the cache, loading unexpired entries from disk when `persist`
+  /// is set. Without persistence any file left by an earlier run is removed.
+  pub fn new(ttl: Duration, max_entries: usize, persist: bool) -> Self {
+    let file = config::get_config_dir().join(CACHE_FILE);
+    let max_entries = max_entries.max(1);
+    if !persist {
+      let _ = std::fs::remove_file(&file);
+      return Self {
+        ttl,
+        max_entries,
+        entries: Mutex::new(Entries::default()),
+        writer: None,
+      };
+    }
+    let data = std::fs::read_to_string(&file).unwrap_or_default();
+    let mut loaded = HashMap::new();
+    // Later lines win, so a key stored twice keeps its newest response.
+    for line in data.lines() {
+      if let Ok(entry) = serde_json::from_str::<PersistedEntry>(line) {
+        loaded.insert(entry.key.clone(), entry);
+      }
+    }
+    let mut persisted: Vec<PersistedEntry> = loaded.into_values().collect();
+    persisted.sort_by_key(|entry| entry.stored_at_ms);
+    let mut entries = Entries::default();
+    for entry in &persisted {
+      let Ok(body) = base64::engine::general_purpose::STANDARD.decode(&entry.body_base64) else {
+        continue;
+      };
+      let stored_at = UNIX_EPOCH + Duration::from_millis(entry.stored_at_ms);
+      let response = TunnelResponse {
+        status: entry.status,
+        headers: entry.headers.clone(),
+        body,
+      };
+      entries.map.insert(entry.key.clone(), Entry::Done { stored_at, response });
+      entries.done.push_back((stored_at, entry.key.clone()));
+    }
+    let (writer, queue) = std::sync::mpsc::channel();
+    let mut file_writer = CacheFile { path: file, ttl, max_entries, entries: persisted, lines: 0 };
+    std::thread::spawn(move || {
+      file_writer.compact();
+      for entry in queue {
+        file_writer.append(entry);
+      }
+    });
+    let cache = Self {
+      ttl,
+      max_entries,
+      entries: Mutex::new(entries),
+      writer: Some(writer),
+    };
+    cache.expire(&mut cache.entries.lock().unwrap_or_else(PoisonError::into_inner));
+    cache
+  }
+
+  /// Returns the cached response for `key`, waiting for a copy already in
+  /// flight if there is one, or claims the key for this request. Responses
+  /// for `path` are kept in memory only if they may hold file or clipboard
+  /// contents.
+  pub(super) async fn claim(&self, key: &str, path: &str) -> Claim<'_> {
+    loop {
+      let mut running = {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        self.expire(&mut entries);
+        match entries.map.get(key) {
+          Some(Entry::Done { response, .. }) => return Claim::Replay(replayed(response)),
+          Some(Entry::InFlight(receiver)) => receiver.clone(),
+          None => {
+            let (sender, receiver) = watch::channel(None);
+            entries.map.insert(key.to_string(), Entry::InFlight(receiver));
+            return Claim::Run(InFlight {
+              cache: self,
+              key: key.to_string(),
+              persist: may_persist(path),
+              sender,
+              finished: false,
+            });
+          }
+        }
+      };
+      // An error means the running copy gave up its claim; try again.
+      if let Ok(result) = running.wait_for(Option::is_some).await {
+        if let Some(response) = result.as_ref() {
+          return Claim::Replay(replayed(response));
+        }
+      }
+    }
+  }
+
+  /// Drops completed entries past the TTL, then the oldest ones beyond
+  /// `max_entries`.
+  fn expire(&self, entries: &mut Entries) {
+    let now = SystemTime::now();
+    while let Some((stored_at, _)) = entries.done.front() {
+      let expired = now.duration_since(*stored_at).is_ok_and(|age| age > self.ttl);
+      if !expired && entries.done.len() <= self.max_entries {
+        break;
+      }
+      if let Some((_, key)) = entries.done.pop_front() {
+        entries.map.remove(&key);
+      }
+    }
+  }
+}
+
+/// The cache file, owned by the writer thread. Entries are appended as
+/// requests finish, and the file is compacted once it has grown to twice
+/// `max_entries` lines.
+struct CacheFile {
+  path: PathBuf,
+  ttl: Duration,
+  max_entries: usize,
+  /// What the file should hold after compaction, oldest first.
+  entries: Vec<PersistedEntry>,
+  lines: usize,
+}
+
+impl CacheFile {
+  fn append(&mut self, entry: PersistedEntry) {
+    let Ok(mut line) = serde_json::to_vec(&entry) else {
+      return;
+    };
+    line.push(b'\n');
+    self.entries.push(entry);
+    if self.lines + 1 >= self.max_entries * 2 {
+      self.compact();
+      return;
+    }
+    if let Some(dir) = self.path.parent() {
+      let _ = std::fs::create_dir_all(dir);
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let written = options
+      .open(&self.path)
+      .and_then(|mut file| std::io::Write::write_all(&mut file, &line));
+    if written.is_ok() {
+      self.lines += 1;
+    }
+  }
+
+  /// Rewrites the file with the unexpired entries. Written beside the
+  /// target and renamed, so a crash never leaves a truncated cache.
+  fn compact(&mut self) {
+    let now = SystemTime::now();
+    let ttl = self.ttl;
+    self.entries.retain(|entry| {
+      let stored_at = UNIX_EPOCH + Duration::from_millis(entry.stored_at_ms);
+      now.duration_since(stored_at).is_ok_and(|age| age <= ttl)
+    });
+    let excess = self.entries.len().saturating_sub(self.max_entries);
+    self.entries.drain(..excess);
+    let mut data = Vec::new();
+    for entry in &self.entries {
+      if let Ok(line) = serde_json::to_vec(entry) {
+        data.extend(line);
+        data.push(b'\n');
+      }
+    }
+    if let Some(dir) = self.path.parent() {
+      let _ = std::fs::create_dir_all(dir);
+    }
+    let partial = self.path.with_extension("jsonl.part");
+    let _ = std::fs::remove_file(&partial);
+    let mut options = std::fs::OpenOptions::new();
+    options.create_new(true).write(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let written = options
+      .open(&partial)
+      .and_then(|mut file| std::io::Write::write_all(&mut file, &data));
+    if written.is_ok() && std::fs::rename(&partial, &self.path).is_ok() {
+      self.lines = self.entries.len();
+    } else {
+      let _ = std::fs::remove_file(&partial);
+      self.lines = 0;
+    }
+  }
+}
+
+impl InFlight<'_> {
+  /// Stores the response and hands it to any copies waiting on the key.
+  pub(super) fn finish(mut self, response: TunnelResponse) {
+    self.finished = true;
+    let stored_at = SystemTime::now();
+    {
+      let mut entries = self.cache.entries.lock().unwrap_or_else(PoisonError::into_inner);
+      entries.map.insert(
+        self.key.clone(),
+        Entry::Done {
+          stored_at,
+          response: response.clone(),
+        },
+      );
+      entries.done.push_back((stored_at, self.key.clone()));
+      self.cache.expire(&mut entries);
+    }
+    if let Some(writer) = &self.cache.writer {
+      if self.persist && response.body.len() <= MAX_PERSISTED_BODY_BYTES {
+        let _ = writer.send(persisted(&self.key, stored_at, &response));
+      }
+    }
+    self.sender.send_replace(Some(response));
+  }
+}
+
+impl Drop for InFlight<'_> {
+  fn drop(&mut self) {
+    if self.finished {
+      return;
+    }
+    let mut entries = self.cache.entries.lock().unwrap_or_else(PoisonError::into_inner);
+    if matches!(entries.map.get(&self.key), Some(Entry::InFlight(_))) {
+      entries.map.remove(&self.key);
+    }
+  }
+}
+
+/// Whether a response to `path` may be written to the cache file. File and
+/// clipboard contents stay in memory.
+fn may_persist(path: &str) -> bool {
+  !(path.starts_with("/computer/fs/read") || path.starts_with("/computer/clipboard"))
+}
+
+fn persisted(key: &str, stored_at: SystemTime, response: &TunnelResponse) -> PersistedEntry {
+  PersistedEntry {
+    key: key.to_string(),
+    stored_at_ms: stored_at
+      .duration_since(UNIX_EPOCH)
+      .map(|since| since.as_millis() as u64)
+      .unwrap_or_default(),
+    status: response.status,
+    headers: response.headers.clone(),
+    body_base64: base64::engine::general_purpose::STANDARD.encode(&response.body),
+  }
+}
+
+fn replayed(response: &TunnelResponse) -> TunnelResponse {
+  let mut response = response.clone();
+  response.headers.insert(REPLAYED_HEADER.to_string(), "true".to_string());
+  response
+}
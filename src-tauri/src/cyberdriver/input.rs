@@ -3,11 +3,16 @@ use std::time::Duration;
 use device_query::{DeviceQuery, DeviceState};
 use enigo::{Axis, Button, Coordinate, Direction, Enigo, Key, Keyboard, Mouse};
 use tokio::sync::{Mutex, oneshot};
+use tokio_util::sync::CancellationToken;
 use tauri::AppHandle;
 
 use crate::error::{CyberdriverError, Result};
 
-use super::windows;
+use super::{
+  api,
+  presence::{self, Injected},
+  windows,
+};
 
 #[derive(Clone, Debug)]
 pub struct MousePosition {
@@ -56,6 +61,7 @@ pub async fn ensure_capslock_off() -> Result<()> {
   #[cfg(windows)]
   {
     if windows::caps_lock_is_on() {
+      presence::record_injected(Injected::Keys(2));
       windows::send_scancode(0x3A, false);
       windows::send_scancode(0x3A, true);
       std::thread::sleep(Duration::from_millis(50));
@@ -72,6 +78,7 @@ pub async fn ensure_capslock_off() -> Result<()> {
     if let Some(out) = output {
       if out.contains("Caps Lock:   on") || out.contains("Caps Lock: on") {
         let mut enigo = Enigo::new(&enigo::Settings::default())?;
+        presence::record_injected(Injected::Keys(2));
         enigo.key(Key::CapsLock, Direction::Click)?;
       }
     }
@@ -83,31 +90,47 @@ pub async fn ensure_capslock_off() -> Result<()> {
   }
 }
 
+/// Types `text` a piece at a time, stopping between pieces once `cancel` is
+/// cancelled.
 pub async fn type_text(
   enigo: &std::sync::Arc<Mutex<Enigo>>,
   text: &str,
   experimental_space: bool,
+  cancel: &CancellationToken,
 ) -> Result<()> {
   ensure_capslock_off().await?;
-  if cfg!(windows) {
-    if type_with_scancodes(text, experimental_space) {
-      return Ok(());
+  let chars: Vec<char> = text.chars().collect();
+  for piece in chars.chunks(TYPE_CHUNK_CHARS) {
+    if cancel.is_cancelled() {
+      return Err(cancelled());
+    }
+    let piece: String = piece.iter().collect();
+    if cfg!(windows) && type_with_scancodes(&piece, experimental_space) {
+      continue;
     }
+    let mut enigo = enigo.lock().await;
+    // A character may take Shift as well as its own key.
+    presence::record_injected(Injected::Keys(piece.chars().count() * 4));
+    enigo.text(&piece)?;
   }
-  let mut enigo = enigo.lock().await;
-  enigo.text(text)?;
   Ok(())
 }
 
+/// Runs a key sequence, stopping between key combinations once `cancel` is
+/// cancelled. Modifiers left down are released by the emergency stop.
 pub async fn execute_xdo_sequence(
   app: &AppHandle,
   enigo: &std::sync::Arc<Mutex<Enigo>>,
   sequence: &str,
   experimental_space: bool,
+  cancel: &CancellationToken,
 ) -> Result<()> {
   if cfg!(windows) {
     let groups = parse_xdo_sequence(sequence);
     for group in groups {
+      if cancel.is_cancelled() {
+        return Err(cancelled());
+      }
       for event in group {
         let key = normalize_key(&event.key);
         press_key_with_scancode(&key, !event.down, experimental_space)?;
@@ -120,14 +143,15 @@ pub async fn execute_xdo_sequence(
     let enigo = std::sync::Arc::clone(enigo);
     let sequence = sequence.to_string();
     let experimental_space = experimental_space;
+    let cancel = cancel.clone();
     return run_on_main_thread(&app, move || {
       let mut enigo = tauri::async_runtime::block_on(enigo.lock());
-      execute_xdo_sequence_inner(&mut enigo, &sequence, experimental_space)
+      execute_xdo_sequence_inner(&mut enigo, &sequence, experimental_space, &cancel)
     })
     .await;
   }
   let mut enigo = enigo.lock().await;
-  execute_xdo_sequence_inner(&mut enigo, sequence, experimental_space)
+  execute_xdo_sequence_inner(&mut enigo, sequence, experimental_space, cancel)
 }
 
 pub async fn mouse_position() -> Result<MousePosition> {
@@ -142,7 +166,7 @@ pub async fn move_mouse(
   y: i32,
 ) -> Result<()> {
   let mut enigo = enigo.lock().await;
-  enigo.move_mouse(x, y, Coordinate::Abs)?;
+  move_to(&mut enigo, x, y)?;
   Ok(())
 }
 
@@ -157,7 +181,7 @@ pub async fn mouse_click(
 ) -> Result<()> {
   let mut enigo = enigo.lock().await;
   let moved = if let (Some(x), Some(y)) = (x, y) {
-    enigo.move_mouse(x, y, Coordinate::Abs)?;
+    move_to(&mut enigo, x, y)?;
     true
   } else {
     false
@@ -167,87 +191,362 @@ pub async fn mouse_click(
   }
   if clicks > 0 {
     for _ in 0..clicks {
-      enigo.button(button, Direction::Press)?;
+      set_button(&mut enigo, button, Direction::Press)?;
       std::thread::sleep(Duration::from_millis(24));
-      enigo.button(button, Direction::Release)?;
+      set_button(&mut enigo, button, Direction::Release)?;
       std::thread::sleep(Duration::from_millis(80));
     }
     return Ok(());
   }
   if press && release {
-    enigo.button(button, Direction::Press)?;
+    set_button(&mut enigo, button, Direction::Press)?;
     std::thread::sleep(Duration::from_millis(24));
-    enigo.button(button, Direction::Release)?;
+    set_button(&mut enigo, button, Direction::Release)?;
     return Ok(());
   }
   if press {
-    enigo.button(button, Direction::Press)?;
+    set_button(&mut enigo, button, Direction::Press)?;
   }
   if release {
-    enigo.button(button, Direction::Release)?;
+    set_button(&mut enigo, button, Direction::Release)?;
   }
   Ok(())
 }
 
 pub async fn mouse_drag(
   enigo: &std::sync::Arc<Mutex<Enigo>>,
-  start_x: i32,
-  start_y: i32,
-  end_x: i32,
-  end_y: i32,
+  (start_x, start_y): (i32, i32),
+  (end_x, end_y): (i32, i32),
   button: Button,
   duration: Option<f64>,
+  cancel: &CancellationToken,
 ) -> Result<()> {
+  let shared = enigo;
   let mut enigo = enigo.lock().await;
-  enigo.move_mouse(start_x, start_y, Coordinate::Abs)?;
-  std::thread::sleep(Duration::from_millis(20));
-  enigo.button(button, Direction::Press)?;
+  move_to(&mut enigo, start_x, start_y)?;
   std::thread::sleep(Duration::from_millis(20));
-  if let Some(duration) = duration.filter(|d| *d > 0.0) {
-    let steps = (duration * 60.0).max(1.0) as i32;
-    for i in 1..=steps {
-      let t = i as f64 / steps as f64;
-      let x = start_x as f64 + (end_x - start_x) as f64 * t;
-      let y = start_y as f64 + (end_y - start_y) as f64 * t;
-      enigo.move_mouse(x.round() as i32, y.round() as i32, Coordinate::Abs)?;
-      std::thread::sleep(Duration::from_secs_f64(duration / steps as f64));
+  set_button(&mut enigo, button, Direction::Press)?;
+  let held = HeldButton::new(shared, button);
+  let dragged = async {
+    pause(Duration::from_millis(20), cancel).await?;
+    let duration = duration.map(|d| d.min(MAX_INPUT_DURATION.as_secs_f64()));
+    if let Some(duration) = duration.filter(|d| *d > 0.0) {
+      let steps = (duration * 60.0).max(1.0) as i32;
+      for i in 1..=steps {
+        let t = i as f64 / steps as f64;
+        let x = start_x as f64 + (end_x - start_x) as f64 * t;
+        let y = start_y as f64 + (end_y - start_y) as f64 * t;
+        move_to(&mut enigo, x.round() as i32, y.round() as i32)?;
+        pause(Duration::from_secs_f64(duration / steps as f64), cancel).await?;
+      }
+    } else {
+      move_to(&mut enigo, end_x, end_y)?;
     }
-  } else {
-    enigo.move_mouse(end_x, end_y, Coordinate::Abs)?;
+    pause(Duration::from_millis(20), cancel).await
   }
-  std::thread::sleep(Duration::from_millis(20));
-  enigo.button(button, Direction::Release)?;
-  Ok(())
+  .await;
+  // The button is let go even when the drag was cancelled.
+  held.release(&mut enigo)?;
+  dragged
 }
 
-pub async fn mouse_scroll(
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScrollUnit {
+  Notch,
+  Pixel,
+}
+
+impl ScrollUnit {
+  pub fn from_str(unit: &str) -> Option<Self> {
+    match unit.to_lowercase().as_str() {
+      "notch" | "notches" | "click" | "clicks" => Some(Self::Notch),
+      "pixel" | "pixels" | "px" => Some(Self::Pixel),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct ScrollUntilStableResult {
+  pub steps: u32,
+  pub stable: bool,
+}
+
+#[cfg_attr(target_os = "macos", allow(dead_code))]
+const PIXELS_PER_NOTCH: i32 = 40;
+const SMOOTH_PIXEL_STEP: i32 = 10;
+const SMOOTH_STEP_DELAY: Duration = Duration::from_millis(12);
+const STABLE_THUMBNAIL_SIZE: (u32, u32) = (160, 90);
+const STABLE_MAX_MEAN_DIFF: f64 = 0.5;
+/// Longest a single input request may take, safely below the tunnel's 30
+/// second request timeout, so holds, drags and scrolls finish before the
+/// tunnel gives up on them.
+pub const MAX_INPUT_DURATION: Duration = Duration::from_secs(25);
+/// Text is typed in pieces of this many characters, so an emergency stop can
+/// interrupt it.
+const TYPE_CHUNK_CHARS: usize = 32;
+
+pub fn parse_button(name: &str) -> Option<Button> {
+  match name.to_lowercase().as_str() {
+    "left" => Some(Button::Left),
+    "right" => Some(Button::Right),
+    "middle" => Some(Button::Middle),
+    "back" | "x1" => Some(Button::Back),
+    "forward" | "x2" => Some(Button::Forward),
+    _ => None,
+  }
+}
+
+pub async fn mouse_hold(
   enigo: &std::sync::Arc<Mutex<Enigo>>,
-  direction: &str,
-  amount: i32,
   x: Option<i32>,
   y: Option<i32>,
+  button: Button,
+  duration: Duration,
+  cancel: &CancellationToken,
 ) -> Result<()> {
+  let held = {
+    let mut locked = enigo.lock().await;
+    if let (Some(x), Some(y)) = (x, y) {
+      move_to(&mut locked, x, y)?;
+      tokio::time::sleep(Duration::from_millis(14)).await;
+    }
+    set_button(&mut locked, button, Direction::Press)?;
+    HeldButton::new(enigo, button)
+  };
+  // Other input (e.g. moving the pointer while the button is held) may run
+  // during the hold. A cancelled hold still lets go of the button.
+  let result = pause(duration.min(MAX_INPUT_DURATION), cancel).await;
+  held.release(&mut *enigo.lock().await)?;
+  result
+}
+
+/// A pressed mouse button that is let go even if the request holding it is
+/// dropped halfway, e.g. by the tunnel's request timeout or a disconnect:
+/// dropping it unreleased releases the button from a spawned task.
+struct HeldButton {
+  enigo: Option<std::sync::Arc<Mutex<Enigo>>>,
+  button: Button,
+}
+
+impl HeldButton {
+  fn new(enigo: &std::sync::Arc<Mutex<Enigo>>, button: Button) -> Self {
+    Self {
+      enigo: Some(enigo.clone()),
+      button,
+    }
+  }
+
+  fn release(mut self, enigo: &mut Enigo) -> Result<()> {
+    self.enigo = None;
+    set_button(enigo, self.button, Direction::Release)?;
+    Ok(())
+  }
+}
+
+impl Drop for HeldButton {
+  fn drop(&mut self) {
+    let Some(enigo) = self.enigo.take() else {
+      return;
+    };
+    let button = self.button;
+    tauri::async_runtime::spawn(async move {
+      let _ = set_button(&mut enigo.lock().await, button, Direction::Release);
+    });
+  }
+}
+
+pub async fn mouse_scroll(
+  enigo: &std::sync::Arc<Mutex<Enigo>>,
+  scroll: &ScrollStep<'_>,
+  cancel: &CancellationToken,
+) -> Result<()> {
+  let ScrollStep { direction, amount, unit, smooth, x, y } = *scroll;
   if amount == 0 {
     return Ok(());
   }
+  let (axis, sign) = scroll_axis(direction)?;
   let mut enigo = enigo.lock().await;
   if let (Some(x), Some(y)) = (x, y) {
-    enigo.move_mouse(x, y, Coordinate::Abs)?;
+    move_to(&mut enigo, x, y)?;
   }
-  match direction {
-    "up" => enigo.scroll(amount, Axis::Vertical)?,
-    "down" => enigo.scroll(-amount, Axis::Vertical)?,
-    "left" => enigo.scroll(-amount, Axis::Horizontal)?,
-    "right" => enigo.scroll(amount, Axis::Horizontal)?,
-    _ => {
-      return Err(CyberdriverError::RuntimeError(
-        "Invalid scroll direction".into(),
-      ))
+  match (unit, smooth) {
+    (ScrollUnit::Notch, false) => enigo.scroll(sign * amount, axis)?,
+    (ScrollUnit::Notch, true) => {
+      for _ in 0..amount {
+        enigo.scroll(sign, axis)?;
+        pause(SMOOTH_STEP_DELAY, cancel).await?;
+      }
+    }
+    (ScrollUnit::Pixel, false) => scroll_pixels(&mut enigo, axis, sign * amount)?,
+    (ScrollUnit::Pixel, true) => {
+      let mut remaining = amount;
+      while remaining > 0 {
+        let step = remaining.min(SMOOTH_PIXEL_STEP);
+        scroll_pixels(&mut enigo, axis, sign * step)?;
+        remaining -= step;
+        pause(SMOOTH_STEP_DELAY, cancel).await?;
+      }
+    }
+  }
+  Ok(())
+}
+
+/// A single scroll, repeated by [`scroll_until_stable`].
+pub struct ScrollStep<'a> {
+  pub direction: &'a str,
+  pub amount: i32,
+  pub unit: ScrollUnit,
+  pub smooth: bool,
+  pub x: Option<i32>,
+  pub y: Option<i32>,
+}
+
+/// Scrolls one step at a time, comparing a screen capture taken before and
+/// after each step, and stops once a step no longer changes the screen, or
+/// unstable once `MAX_INPUT_DURATION` has passed.
+pub async fn scroll_until_stable(
+  enigo: &std::sync::Arc<Mutex<Enigo>>,
+  scroll: &ScrollStep<'_>,
+  max_steps: u32,
+  settle: Duration,
+  cancel: &CancellationToken,
+) -> Result<ScrollUntilStableResult> {
+  scroll_axis(scroll.direction)?;
+  let deadline = std::time::Instant::now() + MAX_INPUT_DURATION;
+  let mut previous = capture_thumbnail().await?;
+  for step in 1..=max_steps {
+    if std::time::Instant::now() + settle >= deadline {
+      return Ok(ScrollUntilStableResult { steps: step - 1, stable: false });
+    }
+    mouse_scroll(enigo, scroll, cancel).await?;
+    pause(settle, cancel).await?;
+    let current = capture_thumbnail().await?;
+    if thumbnails_match(&previous, &current) {
+      return Ok(ScrollUntilStableResult { steps: step, stable: true });
+    }
+    previous = current;
+  }
+  Ok(ScrollUntilStableResult { steps: max_steps, stable: false })
+}
+
+/// Releases every modifier and mouse button the remote side may be holding.
+/// Uses a fresh `Enigo` so it never waits on an in-flight request.
+pub fn release_all() -> Result<()> {
+  #[cfg(windows)]
+  {
+    for code in [0x2A, 0x36, 0x1D, 0xE01D, 0x38, 0xE038, 0xE05B, 0xE05C] {
+      presence::record_injected(Injected::Keys(1));
+      windows::send_scancode(code, true);
     }
   }
+  let mut enigo = Enigo::new(&enigo::Settings::default())?;
+  for key in [Key::Shift, Key::Control, Key::Alt, Key::Meta] {
+    let _ = safe_key(&mut enigo, key, Direction::Release);
+  }
+  for button in [Button::Left, Button::Right, Button::Middle, Button::Back, Button::Forward] {
+    let _ = set_button(&mut enigo, button, Direction::Release);
+  }
+  Ok(())
+}
+
+/// Moves the pointer, telling the presence monitor the move is ours.
+fn move_to(enigo: &mut Enigo, x: i32, y: i32) -> Result<()> {
+  presence::record_injected(Injected::Move);
+  enigo.move_mouse(x, y, Coordinate::Abs)?;
   Ok(())
 }
 
+/// Presses or releases a button, telling the presence monitor it is ours.
+fn set_button(enigo: &mut Enigo, button: Button, direction: Direction) -> Result<()> {
+  let transitions = if matches!(direction, Direction::Click) { 2 } else { 1 };
+  presence::record_injected(Injected::Buttons(transitions));
+  enigo.button(button, direction)?;
+  Ok(())
+}
+
+/// Sleeps for `duration`, failing early once `cancel` is cancelled.
+async fn pause(duration: Duration, cancel: &CancellationToken) -> Result<()> {
+  tokio::select! {
+    _ = tokio::time::sleep(duration) => Ok(()),
+    _ = cancel.cancelled() => Err(cancelled()),
+  }
+}
+
+fn cancelled() -> CyberdriverError {
+  CyberdriverError::RuntimeError("Input cancelled by the emergency stop".into())
+}
+
+fn scroll_axis(direction: &str) -> Result<(Axis, i32)> {
+  match direction {
+    "up" => Ok((Axis::Vertical, 1)),
+    "down" => Ok((Axis::Vertical, -1)),
+    "left" => Ok((Axis::Horizontal, -1)),
+    "right" => Ok((Axis::Horizontal, 1)),
+    _ => Err(CyberdriverError::RuntimeError(
+      "Invalid scroll direction".into(),
+    )),
+  }
+}
+
+fn scroll_pixels(enigo: &mut Enigo, axis: Axis, pixels: i32) -> Result<()> {
+  #[cfg(windows)]
+  {
+    const WHEEL_DELTA: i32 = 120;
+    let _ = enigo;
+    let mut delta = pixels * WHEEL_DELTA / PIXELS_PER_NOTCH;
+    if delta == 0 {
+      delta = pixels.signum();
+    }
+    match axis {
+      Axis::Vertical => windows::send_wheel(-delta, false),
+      Axis::Horizontal => windows::send_wheel(delta, true),
+    }
+    Ok(())
+  }
+  #[cfg(target_os = "macos")]
+  {
+    enigo.smooth_scroll(pixels, axis)?;
+    Ok(())
+  }
+  #[cfg(all(not(windows), not(target_os = "macos")))]
+  {
+    let mut notches = (pixels as f64 / PIXELS_PER_NOTCH as f64).round() as i32;
+    if notches == 0 {
+      notches = pixels.signum();
+    }
+    enigo.scroll(notches, axis)?;
+    Ok(())
+  }
+}
+
+async fn capture_thumbnail() -> Result<image::GrayImage> {
+  tokio::task::spawn_blocking(|| -> Result<image::GrayImage> {
+    let gray = api::capture_display().map_err(CyberdriverError::RuntimeError)?.to_luma8();
+    Ok(image::imageops::thumbnail(
+      &gray,
+      STABLE_THUMBNAIL_SIZE.0,
+      STABLE_THUMBNAIL_SIZE.1,
+    ))
+  })
+  .await
+  .map_err(|err| CyberdriverError::RuntimeError(err.to_string()))?
+}
+
+fn thumbnails_match(a: &image::GrayImage, b: &image::GrayImage) -> bool {
+  if a.dimensions() != b.dimensions() {
+    return false;
+  }
+  let total: u64 = a
+    .as_raw()
+    .iter()
+    .zip(b.as_raw().iter())
+    .map(|(x, y)| x.abs_diff(*y) as u64)
+    .sum();
+  let mean = total as f64 / a.as_raw().len().max(1) as f64;
+  mean <= STABLE_MAX_MEAN_DIFF
+}
+
 fn normalize_key(key: &str) -> String {
   key.to_lowercase().replace('_', "")
 }
@@ -256,10 +555,14 @@ fn execute_xdo_sequence_inner(
   enigo: &mut Enigo,
   sequence: &str,
   _experimental_space: bool,
+  cancel: &CancellationToken,
 ) -> Result<()> {
   let groups = parse_xdo_sequence(sequence);
   let mut modifier_pressed = false;
   for group in groups {
+    if cancel.is_cancelled() {
+      return Err(cancelled());
+    }
     for event in group {
       let key_name = normalize_key(&event.key);
       let is_modifier = is_modifier_key(&key_name);
@@ -301,6 +604,8 @@ fn is_modifier_key(key: &str) -> bool {
 }
 
 fn safe_key(enigo: &mut Enigo, key: Key, direction: Direction) -> Result<()> {
+  let transitions = if matches!(direction, Direction::Click) { 2 } else { 1 };
+  presence::record_injected(Injected::Keys(transitions));
   let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
     enigo.key(key, direction)
   }));
@@ -379,6 +684,7 @@ fn type_with_scancodes(text: &str, experimental_space: bool) -> bool {
     return false;
   }
   for ch in text.chars() {
+    presence::record_injected(Injected::Keys(4));
     if ch == ' ' && experimental_space {
       windows::send_vk_space(false);
       windows::send_vk_space(true);
@@ -411,6 +717,7 @@ fn press_key_with_scancode(key: &str, key_up: bool, experimental_space: bool) ->
     return Err(CyberdriverError::RuntimeError("Scancodes only supported on Windows".into()));
   }
   let key = normalize_key(key);
+  presence::record_injected(Injected::Keys(1));
   if key == "space" && experimental_space {
     windows::send_vk_space(key_up);
     return Ok(());
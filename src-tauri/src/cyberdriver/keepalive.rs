@@ -11,6 +11,8 @@ use enigo::{Coordinate, Direction, Enigo, Key, Keyboard, Mouse, Settings};
 
 use crate::error::Result;
 
+use super::presence::{self, Injected};
+
 #[derive(Clone)]
 pub struct KeepAliveManager {
   state: Arc<Mutex<KeepAliveState>>,
@@ -188,13 +190,17 @@ impl KeepAliveManager {
         height - (rand::random::<i32>().abs() % 3 + 1),
       ),
     };
+    presence::record_injected(Injected::Move);
     enigo.move_mouse(click_x, click_y, Coordinate::Abs)?;
+    presence::record_injected(Injected::Buttons(2));
     enigo.button(enigo::Button::Left, Direction::Click)?;
 
     for phrase in chosen {
+      presence::record_injected(Injected::Keys(phrase.chars().count() * 4));
       enigo.text(phrase)?;
       std::thread::sleep(Duration::from_millis(80));
     }
+    presence::record_injected(Injected::Keys(2));
     enigo.key(Key::Escape, Direction::Click)?;
     Ok(())
   }
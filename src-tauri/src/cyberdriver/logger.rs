@@ -61,6 +61,22 @@ impl DebugLogger {
     self.write_line(&line);
   }
 
+  /// Records a security decision in `audit.log`. Unlike the debug log this
+  /// is always written, and the entry is mirrored to the debug log.
+  pub fn audit(&self, category: &str, message: &str, fields: &[(&str, String)]) {
+    let timestamp = Local::now().to_rfc3339();
+    let mut line = format!("[{timestamp}] [{category}] {message}");
+    for (key, value) in fields {
+      line.push_str(&format!(" {key}={value}"));
+    }
+    let _ = fs::create_dir_all(&self.log_dir);
+    let path = self.log_dir.join("audit.log");
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+      let _ = writeln!(file, "{line}");
+    }
+    self.write_line(&line);
+  }
+
   pub fn info(&self, category: &str, message: &str) {
     self.log(category, message, &[]);
   }
@@ -0,0 +1,429 @@
+//! A stand-in for the Cyberdesk tunnel endpoint, for exercising
+//! `TunnelClient` without the cloud. It accepts plain `ws://` connections on
+//! `/tunnel/ws`, checks the handshake headers the client sends, and speaks
+//! the same framing: a JSON request meta, binary body chunks and `"end"` one
+//! way; a JSON response meta, binary chunks and `"end"` back. Point the
+//! client at it with host `localhost`, the server's port and the `ws` tunnel
+//! scheme.
+
+use std::{
+  collections::{HashMap, VecDeque},
+  io::Read,
+  net::SocketAddr,
+  sync::Arc,
+};
+
+use futures_util::{SinkExt, StreamExt};
+use http::{HeaderMap, HeaderValue, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::{
+  net::{TcpListener, TcpStream},
+  sync::{mpsc, Mutex},
+  task::JoinHandle,
+};
+use tokio_tungstenite::WebSocketStream;
+use tungstenite::{
+  handshake::server::{ErrorResponse, Request, Response},
+  protocol::{frame::coding::CloseCode, CloseFrame},
+  Message,
+};
+
+use crate::error::{CyberdriverError, Result};
+
+/// Body bytes per binary frame when sending a request.
+const REQUEST_CHUNK_BYTES: usize = 64 * 1024;
+
+/// What the mock server expects from connecting clients.
+#[derive(Clone, Debug)]
+pub struct MockTunnelOptions {
+  /// Required `Authorization: Bearer <secret>`; `None` accepts any token.
+  pub secret: Option<String>,
+  /// Require non-empty `X-PIGLET-FINGERPRINT` and `X-PIGLET-VERSION`.
+  pub require_piglet_headers: bool,
+  /// Response encodings to accept (`zstd`, `gzip`) when the client offers
+  /// them; empty keeps responses uncompressed.
+  pub content_encodings: Vec<String>,
+}
+
+impl Default for MockTunnelOptions {
+  fn default() -> Self {
+    Self {
+      secret: None,
+      require_piglet_headers: true,
+      content_encodings: Vec::new(),
+    }
+  }
+}
+
+/// A request to send down the tunnel.
+#[derive(Clone, Debug, Default)]
+pub struct MockRequest {
+  pub method: String,
+  pub path: String,
+  pub query: Option<String>,
+  pub headers: HashMap<String, String>,
+  pub body: Vec<u8>,
+}
+
+impl MockRequest {
+  pub fn get(path: &str) -> Self {
+    Self {
+      method: "GET".to_string(),
+      path: path.to_string(),
+      ..Self::default()
+    }
+  }
+
+  pub fn post_json(path: &str, body: &serde_json::Value) -> Self {
+    Self {
+      method: "POST".to_string(),
+      path: path.to_string(),
+      headers: [("content-type".to_string(), "application/json".to_string())]
+        .into_iter()
+        .collect(),
+      body: body.to_string().into_bytes(),
+      ..Self::default()
+    }
+  }
+
+  pub fn with_header(mut self, name: &str, value: &str) -> Self {
+    self.headers.insert(name.to_string(), value.to_string());
+    self
+  }
+}
+
+/// A response the client sent back, reassembled from its frames. `body` is
+/// decompressed; `content_encoding` records how it was sent.
+#[derive(Clone, Debug)]
+pub struct MockResponse {
+  pub request_id: String,
+  pub status: u16,
+  pub headers: HashMap<String, String>,
+  pub content_encoding: Option<String>,
+  pub body: Vec<u8>,
+}
+
+impl MockResponse {
+  pub fn text(&self) -> String {
+    String::from_utf8_lossy(&self.body).to_string()
+  }
+
+  pub fn json(&self) -> Result<serde_json::Value> {
+    Ok(serde_json::from_slice(&self.body)?)
+  }
+}
+
+#[derive(Deserialize)]
+struct ResponseMetaFrame {
+  #[serde(rename = "requestId")]
+  request_id: String,
+  status: u16,
+  #[serde(default)]
+  headers: HashMap<String, String>,
+  #[serde(rename = "contentEncoding")]
+  content_encoding: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RequestMetaFrame<'a> {
+  #[serde(rename = "requestId")]
+  request_id: &'a str,
+  method: &'a str,
+  path: &'a str,
+  query: Option<&'a str>,
+  headers: &'a HashMap<String, String>,
+}
+
+struct Shared {
+  options: MockTunnelOptions,
+  /// Status codes to answer upcoming handshakes with, in order.
+  rejections: Mutex<VecDeque<StatusCode>>,
+  handshakes: Mutex<Vec<HeaderMap>>,
+}
+
+/// Listens for tunnel clients. Each accepted client is handed out as a
+/// [`MockTunnelConnection`] from [`MockTunnelServer::accept`]; connections
+/// nobody accepts yet wait in a queue.
+pub struct MockTunnelServer {
+  addr: SocketAddr,
+  shared: Arc<Shared>,
+  connections: mpsc::UnboundedReceiver<MockTunnelConnection>,
+  listener: JoinHandle<()>,
+}
+
+/// One connected client. Requests are sent with [`send_request`] and their
+/// responses collected with [`response_for`] (or both with [`request`]);
+/// responses to other requests that arrive first are kept until asked for.
+///
+/// [`send_request`]: MockTunnelConnection::send_request
+/// [`response_for`]: MockTunnelConnection::response_for
+/// [`request`]: MockTunnelConnection::request
+pub struct MockTunnelConnection {
+  socket: WebSocketStream<TcpStream>,
+  headers: HeaderMap,
+  responses: HashMap<String, MockResponse>,
+  partial: Option<MockResponse>,
+}
+
+impl MockTunnelServer {
+  /// Binds to `addr` (port 0 picks a free one) and starts accepting.
+  pub async fn bind(addr: &str, options: MockTunnelOptions) -> Result<Self> {
+    let listener = TcpListener::bind(addr).await?;
+    let addr = listener.local_addr()?;
+    let shared = Arc::new(Shared {
+      options,
+      rejections: Mutex::new(VecDeque::new()),
+      handshakes: Mutex::new(Vec::new()),
+    });
+    let (sender, connections) = mpsc::unbounded_channel();
+    let listener = tokio::spawn(accept_loop(listener, shared.clone(), sender));
+    Ok(Self {
+      addr,
+      shared,
+      connections,
+      listener,
+    })
+  }
+
+  pub fn local_addr(&self) -> SocketAddr {
+    self.addr
+  }
+
+  /// Waits for the next client whose handshake was accepted.
+  pub async fn accept(&mut self) -> Option<MockTunnelConnection> {
+    self.connections.recv().await
+  }
+
+  /// Answers the next `count` handshakes with `status` instead of upgrading,
+  /// e.g. 403 to make the client report an auth failure.
+  pub async fn reject_next_handshakes(&self, count: usize, status: StatusCode) {
+    let mut rejections = self.shared.rejections.lock().await;
+    rejections.extend(std::iter::repeat_n(status, count));
+  }
+
+  /// Headers of every handshake attempt so far, rejected ones included.
+  pub async fn handshakes(&self) -> Vec<HeaderMap> {
+    self.shared.handshakes.lock().await.clone()
+  }
+}
+
+impl Drop for MockTunnelServer {
+  fn drop(&mut self) {
+    self.listener.abort();
+  }
+}
+
+async fn accept_loop(
+  listener: TcpListener,
+  shared: Arc<Shared>,
+  connections: mpsc::UnboundedSender<MockTunnelConnection>,
+) {
+  while let Ok((stream, _)) = listener.accept().await {
+    let shared = shared.clone();
+    let connections = connections.clone();
+    tokio::spawn(async move {
+      if let Some(connection) = handshake(stream, &shared).await {
+        let _ = connections.send(connection);
+      }
+    });
+  }
+}
+
+async fn handshake(stream: TcpStream, shared: &Shared) -> Option<MockTunnelConnection> {
+  let rejection = shared.rejections.lock().await.pop_front();
+  let mut headers = HeaderMap::new();
+  let callback = |request: &Request, mut response: Response| {
+    headers = request.headers().clone();
+    let refuse = |status: StatusCode, message: &str| -> std::result::Result<_, ErrorResponse> {
+      let mut response = ErrorResponse::new(Some(message.to_string()));
+      *response.status_mut() = status;
+      Err(response)
+    };
+    if request.uri().path() != "/tunnel/ws" {
+      return refuse(StatusCode::NOT_FOUND, "Not found");
+    }
+    if let Some(status) = rejection {
+      return refuse(status, "Rejected by mock tunnel script");
+    }
+    if let Some(problem) = check_headers(request.headers(), &shared.options) {
+      return refuse(StatusCode::FORBIDDEN, &problem);
+    }
+    let offered = request.headers().contains_key("x-piglet-content-encoding");
+    let accepted = shared.options.content_encodings.join(", ");
+    if offered && !accepted.is_empty() {
+      if let Ok(value) = HeaderValue::from_str(&accepted) {
+        response.headers_mut().insert("x-piglet-content-encoding", value);
+      }
+    }
+    Ok(response)
+  };
+  let socket = tokio_tungstenite::accept_hdr_async(stream, callback).await;
+  shared.handshakes.lock().await.push(headers.clone());
+  Some(MockTunnelConnection {
+    socket: socket.ok()?,
+    headers,
+    responses: HashMap::new(),
+    partial: None,
+  })
+}
+
+/// Why the handshake headers are unacceptable, if they are.
+fn check_headers(headers: &HeaderMap, options: &MockTunnelOptions) -> Option<String> {
+  let header = |name: &str| {
+    headers
+      .get(name)
+      .and_then(|value| value.to_str().ok())
+      .map(str::trim)
+      .filter(|value| !value.is_empty())
+  };
+  let Some(token) = header("authorization").and_then(|value| value.strip_prefix("Bearer ")) else {
+    return Some("Missing bearer token".to_string());
+  };
+  if options.secret.as_deref().is_some_and(|secret| secret != token) {
+    return Some("Invalid API key".to_string());
+  }
+  if options.require_piglet_headers {
+    for name in ["x-piglet-fingerprint", "x-piglet-version"] {
+      if header(name).is_none() {
+        return Some(format!("Missing {name} header"));
+      }
+    }
+  }
+  None
+}
+
+impl MockTunnelConnection {
+  /// The headers the client connected with.
+  pub fn headers(&self) -> &HeaderMap {
+    &self.headers
+  }
+
+  /// Sends a request and returns its generated request id.
+  pub async fn send_request(&mut self, request: &MockRequest) -> Result<String> {
+    let request_id = uuid::Uuid::new_v4().to_string();
+    self.send_request_with_id(&request_id, request).await?;
+    Ok(request_id)
+  }
+
+  /// Sends a request under a chosen id, e.g. to replay one after reconnecting.
+  pub async fn send_request_with_id(
+    &mut self,
+    request_id: &str,
+    request: &MockRequest,
+  ) -> Result<()> {
+    let meta = RequestMetaFrame {
+      request_id,
+      method: &request.method,
+      path: &request.path,
+      query: request.query.as_deref(),
+      headers: &request.headers,
+    };
+    let mut frames = vec![Message::Text(serde_json::to_string(&meta)?.into())];
+    for chunk in request.body.chunks(REQUEST_CHUNK_BYTES) {
+      frames.push(Message::Binary(chunk.to_vec().into()));
+    }
+    frames.push(Message::Text("end".to_string().into()));
+    for frame in frames {
+      self.socket.feed(frame).await.map_err(socket_error)?;
+    }
+    self.socket.flush().await.map_err(socket_error)
+  }
+
+  /// Waits for the response to `request_id`.
+  pub async fn response_for(&mut self, request_id: &str) -> Result<MockResponse> {
+    loop {
+      if let Some(response) = self.responses.remove(request_id) {
+        return Ok(response);
+      }
+      let response = self.next_response().await?;
+      self.responses.insert(response.request_id.clone(), response);
+    }
+  }
+
+  /// Sends a request and waits for its response.
+  pub async fn request(&mut self, request: &MockRequest) -> Result<MockResponse> {
+    let request_id = self.send_request(request).await?;
+    self.response_for(&request_id).await
+  }
+
+  /// Reads frames until the next complete response.
+  pub async fn next_response(&mut self) -> Result<MockResponse> {
+    loop {
+      let message = match self.socket.next().await {
+        Some(message) => message.map_err(socket_error)?,
+        None => return Err(CyberdriverError::RuntimeError("Client disconnected".into())),
+      };
+      match message {
+        Message::Text(text) if text.as_str() == "end" => {
+          if let Some(mut response) = self.partial.take() {
+            if let Some(encoding) = &response.content_encoding {
+              response.body = decompress(encoding, &response.body)?;
+            }
+            return Ok(response);
+          }
+        }
+        Message::Text(text) => {
+          let meta: ResponseMetaFrame = serde_json::from_str(text.as_str())?;
+          self.partial = Some(MockResponse {
+            request_id: meta.request_id,
+            status: meta.status,
+            headers: meta.headers,
+            content_encoding: meta.content_encoding,
+            body: Vec::new(),
+          });
+        }
+        Message::Binary(bytes) => {
+          let Some(response) = self.partial.as_mut() else {
+            return Err(CyberdriverError::RuntimeError(
+              "Binary frame outside a response".into(),
+            ));
+          };
+          response.body.extend_from_slice(&bytes);
+        }
+        Message::Close(_) => {
+          return Err(CyberdriverError::RuntimeError("Client closed the tunnel".into()));
+        }
+        _ => {}
+      }
+    }
+  }
+
+  /// Closes with a policy violation, which the client treats as an auth
+  /// failure.
+  pub async fn close_policy(self, reason: &str) -> Result<()> {
+    self.close(CloseCode::Policy, reason).await
+  }
+
+  pub async fn close(mut self, code: CloseCode, reason: &str) -> Result<()> {
+    let frame = CloseFrame {
+      code,
+      reason: reason.to_string().into(),
+    };
+    self.socket.close(Some(frame)).await.map_err(socket_error)
+  }
+
+  /// Drops the TCP connection without a close frame, like a network drop.
+  pub fn disconnect(self) {
+    drop(self.socket);
+  }
+}
+
+fn decompress(encoding: &str, body: &[u8]) -> Result<Vec<u8>> {
+  let mut decoded = Vec::new();
+  match encoding {
+    "gzip" => {
+      flate2::read::GzDecoder::new(body).read_to_end(&mut decoded)?;
+    }
+    "zstd" => decoded = zstd::decode_all(body)?,
+    other => {
+      return Err(CyberdriverError::RuntimeError(format!(
+        "Unexpected content encoding '{other}'"
+      )));
+    }
+  }
+  Ok(decoded)
+}
+
+fn socket_error(err: tungstenite::Error) -> CyberdriverError {
+  CyberdriverError::RuntimeError(format!("Mock tunnel socket error: {err}"))
+}
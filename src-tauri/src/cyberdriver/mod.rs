@@ -2,10 +2,31 @@ pub mod api;
 mod black_screen;
 mod config;
 mod diagnostics;
+mod emergency_stop;
+mod fs;
+mod fs_archive;
+mod fs_list;
+mod fs_policy;
+mod fs_text;
+mod fs_watch;
+mod idempotency;
 mod input;
 mod keepalive;
 mod logger;
+#[cfg(any(test, feature = "mock-tunnel"))]
+pub mod mock_tunnel;
+mod presence;
+mod process_limits;
+mod processes;
+mod proxy;
+mod shell;
+mod shell_policy;
+mod shell_session;
+mod shell_stream;
+mod system_processes;
 mod tunnel;
+mod tunnel_compression;
+mod tunnel_tls;
 mod update;
 mod windows;
 
@@ -23,9 +44,14 @@ use crate::error::{Result, CyberdriverError};
 use self::{
   api::ApiState,
   config::{Config, ConnectionInfo, RuntimePidInfo},
+  emergency_stop::{EmergencyStop, DEFAULT_EMERGENCY_STOP_SHORTCUT},
+  idempotency::IdempotencyCache,
   keepalive::KeepAliveManager,
   logger::DebugLogger,
+  presence::{LocalPresence, YieldMode},
+  proxy::ProxySettings,
   tunnel::TunnelClient,
+  tunnel_tls::TunnelTls,
 };
 
 const DEFAULT_HOST: &str = "api.cyberdesk.io";
@@ -33,6 +59,10 @@ const DEFAULT_PORT: u16 = 443;
 const DEFAULT_TARGET_PORT: u16 = 3000;
 const DEFAULT_KEEPALIVE_THRESHOLD_MINUTES: f64 = 3.0;
 const DEFAULT_BLACK_SCREEN_INTERVAL_SECONDS: f64 = 30.0;
+const DEFAULT_LOCAL_USER_IDLE_SECONDS: f64 = 5.0;
+const DEFAULT_TUNNEL_MAX_CONCURRENT_REQUESTS: u16 = 8;
+const DEFAULT_IDEMPOTENCY_CACHE_TTL_SECONDS: f64 = 60.0;
+const DEFAULT_IDEMPOTENCY_CACHE_MAX_ENTRIES: u16 = 1000;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(default)]
@@ -51,6 +81,29 @@ pub struct CyberdriverSettings {
   pub register_as_keepalive_for: Option<String>,
   pub experimental_space: bool,
   pub driver_path: Option<String>,
+  pub emergency_stop_shortcut: String,
+  pub yield_to_local_user: bool,
+  pub local_user_idle_seconds: f64,
+  pub local_user_yield_mode: String,
+  pub fs_allowed_roots: Vec<String>,
+  pub fs_denied_roots: Vec<String>,
+  pub fs_read_only: bool,
+  pub shell_allowed_commands: Vec<String>,
+  pub shell_denied_commands: Vec<String>,
+  pub shell_unlisted_action: String,
+  pub shell_run_as_user: String,
+  pub tunnel_max_concurrent_requests: u16,
+  pub tunnel_compression: bool,
+  pub proxy_url: String,
+  pub proxy_username: String,
+  pub proxy_password: String,
+  pub proxy_use_environment: bool,
+  pub tunnel_scheme: String,
+  pub tunnel_ca_bundle_path: String,
+  pub tunnel_spki_pin: String,
+  pub idempotency_cache_ttl_seconds: f64,
+  pub idempotency_cache_max_entries: u16,
+  pub idempotency_cache_persist: bool,
 }
 
 impl Default for CyberdriverSettings {
@@ -70,6 +123,29 @@ impl Default for CyberdriverSettings {
       register_as_keepalive_for: None,
       experimental_space: false,
       driver_path: None,
+      emergency_stop_shortcut: DEFAULT_EMERGENCY_STOP_SHORTCUT.to_string(),
+      yield_to_local_user: false,
+      local_user_idle_seconds: DEFAULT_LOCAL_USER_IDLE_SECONDS,
+      local_user_yield_mode: "reject".to_string(),
+      fs_allowed_roots: Vec::new(),
+      fs_denied_roots: Vec::new(),
+      fs_read_only: false,
+      shell_allowed_commands: Vec::new(),
+      shell_denied_commands: Vec::new(),
+      shell_unlisted_action: "allow".to_string(),
+      shell_run_as_user: String::new(),
+      tunnel_max_concurrent_requests: DEFAULT_TUNNEL_MAX_CONCURRENT_REQUESTS,
+      tunnel_compression: true,
+      proxy_url: String::new(),
+      proxy_username: String::new(),
+      proxy_password: String::new(),
+      proxy_use_environment: true,
+      tunnel_scheme: "wss".to_string(),
+      tunnel_ca_bundle_path: String::new(),
+      tunnel_spki_pin: String::new(),
+      idempotency_cache_ttl_seconds: DEFAULT_IDEMPOTENCY_CACHE_TTL_SECONDS,
+      idempotency_cache_max_entries: DEFAULT_IDEMPOTENCY_CACHE_MAX_ENTRIES,
+      idempotency_cache_persist: false,
     }
   }
 }
@@ -96,6 +172,60 @@ impl CyberdriverSettings {
       read_string_opt(&store, "cyberdriver_register_as_keepalive_for");
     settings.experimental_space = read_bool(&store, "cyberdriver_experimental_space", settings.experimental_space);
     settings.driver_path = read_string_opt(&store, "cyberdriver_driver_path");
+    settings.emergency_stop_shortcut = read_string(
+      &store,
+      "cyberdriver_emergency_stop_shortcut",
+      &settings.emergency_stop_shortcut,
+    );
+    settings.yield_to_local_user =
+      read_bool(&store, "cyberdriver_yield_to_local_user", settings.yield_to_local_user);
+    settings.local_user_idle_seconds =
+      read_f64(&store, "cyberdriver_local_user_idle_seconds", settings.local_user_idle_seconds);
+    settings.local_user_yield_mode =
+      read_string(&store, "cyberdriver_local_user_yield_mode", &settings.local_user_yield_mode);
+    settings.fs_allowed_roots = read_string_list(&store, "cyberdriver_fs_allowed_roots");
+    settings.fs_denied_roots = read_string_list(&store, "cyberdriver_fs_denied_roots");
+    settings.fs_read_only = read_bool(&store, "cyberdriver_fs_read_only", settings.fs_read_only);
+    settings.shell_allowed_commands = read_string_list(&store, "cyberdriver_shell_allowed_commands");
+    settings.shell_denied_commands = read_string_list(&store, "cyberdriver_shell_denied_commands");
+    settings.shell_unlisted_action =
+      read_string(&store, "cyberdriver_shell_unlisted_action", &settings.shell_unlisted_action);
+    settings.shell_run_as_user =
+      read_string(&store, "cyberdriver_shell_run_as_user", &settings.shell_run_as_user);
+    settings.tunnel_max_concurrent_requests = read_u16(
+      &store,
+      "cyberdriver_tunnel_max_concurrent_requests",
+      settings.tunnel_max_concurrent_requests,
+    );
+    settings.tunnel_compression =
+      read_bool(&store, "cyberdriver_tunnel_compression", settings.tunnel_compression);
+    settings.proxy_url = read_string(&store, "cyberdriver_proxy_url", "");
+    settings.proxy_username = read_string(&store, "cyberdriver_proxy_username", "");
+    settings.proxy_password = read_string(&store, "cyberdriver_proxy_password", "");
+    settings.proxy_use_environment = read_bool(
+      &store,
+      "cyberdriver_proxy_use_environment",
+      settings.proxy_use_environment,
+    );
+    settings.tunnel_scheme =
+      read_string(&store, "cyberdriver_tunnel_scheme", &settings.tunnel_scheme);
+    settings.tunnel_ca_bundle_path = read_string(&store, "cyberdriver_tunnel_ca_bundle_path", "");
+    settings.tunnel_spki_pin = read_string(&store, "cyberdriver_tunnel_spki_pin", "");
+    settings.idempotency_cache_ttl_seconds = read_f64(
+      &store,
+      "cyberdriver_idempotency_cache_ttl_seconds",
+      settings.idempotency_cache_ttl_seconds,
+    );
+    settings.idempotency_cache_max_entries = read_u16(
+      &store,
+      "cyberdriver_idempotency_cache_max_entries",
+      settings.idempotency_cache_max_entries,
+    );
+    settings.idempotency_cache_persist = read_bool(
+      &store,
+      "cyberdriver_idempotency_cache_persist",
+      settings.idempotency_cache_persist,
+    );
     Ok(settings)
   }
 
@@ -124,6 +254,50 @@ impl CyberdriverSettings {
     );
     store.set("cyberdriver_experimental_space", self.experimental_space);
     store.set("cyberdriver_driver_path", self.driver_path.clone());
+    store.set(
+      "cyberdriver_emergency_stop_shortcut",
+      self.emergency_stop_shortcut.clone(),
+    );
+    store.set("cyberdriver_yield_to_local_user", self.yield_to_local_user);
+    store.set("cyberdriver_local_user_idle_seconds", self.local_user_idle_seconds);
+    store.set(
+      "cyberdriver_local_user_yield_mode",
+      self.local_user_yield_mode.clone(),
+    );
+    store.set("cyberdriver_fs_allowed_roots", self.fs_allowed_roots.clone());
+    store.set("cyberdriver_fs_denied_roots", self.fs_denied_roots.clone());
+    store.set("cyberdriver_fs_read_only", self.fs_read_only);
+    store.set(
+      "cyberdriver_shell_allowed_commands",
+      self.shell_allowed_commands.clone(),
+    );
+    store.set("cyberdriver_shell_denied_commands", self.shell_denied_commands.clone());
+    store.set(
+      "cyberdriver_shell_unlisted_action",
+      self.shell_unlisted_action.clone(),
+    );
+    store.set("cyberdriver_shell_run_as_user", self.shell_run_as_user.clone());
+    store.set(
+      "cyberdriver_tunnel_max_concurrent_requests",
+      self.tunnel_max_concurrent_requests,
+    );
+    store.set("cyberdriver_tunnel_compression", self.tunnel_compression);
+    store.set("cyberdriver_proxy_url", self.proxy_url.clone());
+    store.set("cyberdriver_proxy_username", self.proxy_username.clone());
+    store.set("cyberdriver_proxy_password", self.proxy_password.clone());
+    store.set("cyberdriver_proxy_use_environment", self.proxy_use_environment);
+    store.set("cyberdriver_tunnel_scheme", self.tunnel_scheme.clone());
+    store.set("cyberdriver_tunnel_ca_bundle_path", self.tunnel_ca_bundle_path.clone());
+    store.set("cyberdriver_tunnel_spki_pin", self.tunnel_spki_pin.clone());
+    store.set(
+      "cyberdriver_idempotency_cache_ttl_seconds",
+      self.idempotency_cache_ttl_seconds,
+    );
+    store.set(
+      "cyberdriver_idempotency_cache_max_entries",
+      self.idempotency_cache_max_entries,
+    );
+    store.set("cyberdriver_idempotency_cache_persist", self.idempotency_cache_persist);
     Ok(())
   }
 }
@@ -136,6 +310,10 @@ pub struct CyberdriverStatus {
   pub keepalive_enabled: bool,
   pub black_screen_recovery: bool,
   pub debug_enabled: bool,
+  pub remote_control_frozen: bool,
+  pub local_user_active: bool,
+  pub local_user_idle_seconds: Option<f64>,
+  pub remote_input_paused: bool,
   pub last_error: Option<String>,
   pub machine_uuid: String,
   pub version: String,
@@ -162,11 +340,17 @@ pub struct CyberdriverRuntime {
   config: Config,
   settings: Arc<Mutex<CyberdriverSettings>>,
   keepalive: Arc<KeepAliveManager>,
+  /// The local API, shared by the loopback listener and the tunnel so both
+  /// see the same jobs, sessions and uploads. Released, ending them, once
+  /// neither is running.
+  api: Option<ApiState>,
   server: Option<ServerHandle>,
   tunnel: Option<TunnelHandle>,
   black_screen: Option<BlackScreenHandle>,
   debug_logger: DebugLogger,
   connection_info: Arc<Mutex<ConnectionInfo>>,
+  emergency_stop: EmergencyStop,
+  presence: LocalPresence,
   last_error: Option<String>,
 }
 
@@ -174,6 +358,11 @@ impl CyberdriverRuntime {
   pub fn new(app: AppHandle) -> Result<Self> {
     let config = config::get_config()?;
     let settings = CyberdriverSettings::from_store(&app)?;
+    let presence = LocalPresence::new(
+      settings.yield_to_local_user,
+      settings.local_user_idle_seconds,
+      YieldMode::from_str(&settings.local_user_yield_mode),
+    );
     let keepalive = KeepAliveManager::new(
       settings.keepalive_enabled,
       settings.keepalive_threshold_minutes,
@@ -181,17 +370,25 @@ impl CyberdriverRuntime {
       settings.keepalive_click_y,
     );
     let debug_logger = DebugLogger::new(settings.debug)?;
+    let emergency_stop = EmergencyStop::new(app.clone(), debug_logger.clone());
+    let last_error = emergency_stop
+      .register_shortcut(&settings.emergency_stop_shortcut)
+      .err()
+      .map(|err| err.to_string());
     Ok(Self {
       app,
       config,
       settings: Arc::new(Mutex::new(settings)),
       keepalive,
+      api: None,
       server: None,
       tunnel: None,
       black_screen: None,
       debug_logger,
       connection_info: Arc::new(Mutex::new(ConnectionInfo::default())),
-      last_error: None,
+      emergency_stop,
+      presence,
+      last_error,
     })
   }
 
@@ -205,6 +402,10 @@ impl CyberdriverRuntime {
       keepalive_enabled: settings.keepalive_enabled,
       black_screen_recovery: settings.black_screen_recovery,
       debug_enabled: settings.debug,
+      remote_control_frozen: self.emergency_stop.is_frozen(),
+      local_user_active: self.presence.is_local_user_active(),
+      local_user_idle_seconds: self.presence.seconds_since_local_activity(),
+      remote_input_paused: self.presence.should_yield(),
       last_error: self.last_error.clone(),
       machine_uuid: self.config.fingerprint.clone(),
       version: self.config.version.clone(),
@@ -245,6 +446,11 @@ impl CyberdriverRuntime {
       settings.keepalive_click_x,
       settings.keepalive_click_y,
     ).await;
+    self.presence.update_config(
+      settings.yield_to_local_user,
+      settings.local_user_idle_seconds,
+      YieldMode::from_str(&settings.local_user_yield_mode),
+    );
     if settings.keepalive_enabled {
       self.start_keepalive_if_enabled().await;
     } else {
@@ -255,6 +461,9 @@ impl CyberdriverRuntime {
     } else {
       self.stop_black_screen().await;
     }
+    self
+      .emergency_stop
+      .register_shortcut(&settings.emergency_stop_shortcut)?;
     Ok(())
   }
 
@@ -266,15 +475,7 @@ impl CyberdriverRuntime {
     let port = config::find_available_port("127.0.0.1", settings.target_port)
       .ok_or_else(|| CyberdriverError::RuntimeError("No available port found".into()))?;
 
-    let state = ApiState::new(
-      self.app.clone(),
-      self.config.clone(),
-      self.keepalive.clone(),
-      self.settings.clone(),
-      self.debug_logger.clone(),
-      self.connection_info.clone(),
-    );
-    let router = api::router(state);
+    let router = self.api_router();
     let listener = tokio::net::TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], port)))
       .await
       .map_err(|err| CyberdriverError::RuntimeError(format!("Failed to bind server: {err}")))?;
@@ -308,12 +509,40 @@ impl CyberdriverRuntime {
     Ok(port)
   }
 
+  fn api_router(&mut self) -> axum::Router {
+    let state = self.api.get_or_insert_with(|| {
+      ApiState::new(
+        self.app.clone(),
+        self.config.clone(),
+        self.keepalive.clone(),
+        self.settings.clone(),
+        self.debug_logger.clone(),
+        self.connection_info.clone(),
+        self.emergency_stop.clone(),
+        self.presence.clone(),
+      )
+    });
+    api::router(state.clone())
+  }
+
+  /// Ends the API's sessions, jobs and uploads once neither the listener
+  /// nor the tunnel can reach them any more.
+  async fn release_api_if_unused(&mut self) {
+    if self.server.is_some() || self.tunnel.is_some() {
+      return;
+    }
+    if let Some(state) = self.api.take() {
+      state.shutdown().await;
+    }
+  }
+
   pub async fn stop_local_server(&mut self) -> Result<()> {
     if let Some(server) = self.server.take() {
       server.stop.cancel();
       let _ = tokio::time::timeout(Duration::from_secs(2), server.task).await;
       self.debug_logger.info("RUNTIME", "Local API stopped");
     }
+    self.release_api_if_unused().await;
     Ok(())
   }
 
@@ -325,7 +554,26 @@ impl CyberdriverRuntime {
     if settings.secret.trim().is_empty() {
       return Err(CyberdriverError::RuntimeError("Missing API key".into()));
     }
-    let local_port = self.start_local_server().await?;
+    let transport = ProxySettings::from_settings(&settings)
+      .and_then(|proxy| Ok((proxy, TunnelTls::from_settings(&settings)?)));
+    let (proxy, tls) = match transport {
+      Ok(transport) => transport,
+      Err(err) => {
+        self.connection_info.lock().await.last_error = Some(err.to_string());
+        return Err(err);
+      }
+    };
+    // The tunnel calls the API in-process; the listener is only for local
+    // clients, so failing to bind it does not stop the tunnel.
+    let local_port = match self.start_local_server().await {
+      Ok(port) => Some(port),
+      Err(err) => {
+        self
+          .debug_logger
+          .error("RUNTIME", &format!("Local API listener unavailable: {err}"));
+        None
+      }
+    };
 
     let stop = CancellationToken::new();
     let stop_signal = stop.clone();
@@ -338,12 +586,22 @@ impl CyberdriverRuntime {
       settings.host.clone(),
       settings.port,
       settings.secret.clone(),
-      local_port,
+      self.api_router(),
       self.config.clone(),
       keepalive,
       settings.register_as_keepalive_for.clone(),
       self.debug_logger.clone(),
       self.connection_info.clone(),
+      settings.tunnel_max_concurrent_requests.max(1) as usize,
+      settings.tunnel_compression,
+      IdempotencyCache::new(
+        Duration::try_from_secs_f64(settings.idempotency_cache_ttl_seconds)
+          .unwrap_or(Duration::from_secs_f64(DEFAULT_IDEMPOTENCY_CACHE_TTL_SECONDS)),
+        settings.idempotency_cache_max_entries as usize,
+        settings.idempotency_cache_persist,
+      ),
+      proxy,
+      tls,
     );
 
     self
@@ -357,7 +615,7 @@ impl CyberdriverRuntime {
     config::write_pid_info(RuntimePidInfo {
       pid: std::process::id(),
       command: "join".to_string(),
-      local_port: Some(local_port),
+      local_port,
       cloud_host: settings.host.clone(),
       cloud_port: settings.port,
       version: None,
@@ -378,6 +636,7 @@ impl CyberdriverRuntime {
     self.debug_logger.info("RUNTIME", "Tunnel disconnected");
     self.stop_keepalive().await;
     self.stop_black_screen().await;
+    self.release_api_if_unused().await;
     Ok(())
   }
 
@@ -428,6 +687,10 @@ impl CyberdriverRuntime {
     Ok(())
   }
 
+  pub async fn set_remote_control_frozen(&self, frozen: bool) {
+    self.emergency_stop.set_frozen(frozen).await;
+  }
+
   pub async fn install_persistent_display(&self) -> Result<()> {
     self.debug_logger.info("RUNTIME", "Installing persistent display driver");
     let settings = self.settings.lock().await.clone();
@@ -436,6 +699,12 @@ impl CyberdriverRuntime {
 
 }
 
+/// Passes the local user's answer from the shell approval window on to the
+/// command waiting for it.
+pub fn answer_shell_approval(id: &str, approved: bool) {
+  shell_policy::answer_approval(id, approved);
+}
+
 pub fn log_dir_path() -> std::path::PathBuf {
   config::get_config_dir().join("logs")
 }
@@ -484,6 +753,20 @@ fn read_string_opt<R: Runtime>(store: &tauri_plugin_store::Store<R>, key: &str)
     .and_then(|value| value.as_str().map(|v| v.to_string()))
 }
 
+fn read_string_list<R: Runtime>(store: &tauri_plugin_store::Store<R>, key: &str) -> Vec<String> {
+  store
+    .get(key)
+    .and_then(|value| {
+      value.as_array().map(|items| {
+        items
+          .iter()
+          .filter_map(|item| item.as_str().map(|v| v.to_string()))
+          .collect()
+      })
+    })
+    .unwrap_or_default()
+}
+
 fn read_u16<R: Runtime>(store: &tauri_plugin_store::Store<R>, key: &str, default: u16) -> u16 {
   store
     .get(key)
@@ -0,0 +1,301 @@
+use std::{
+  sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+  },
+  time::{Duration, Instant},
+};
+
+use device_query::{DeviceQuery, DeviceState, Keycode};
+use tokio_util::sync::CancellationToken;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const MAX_QUEUE_WAIT: Duration = Duration::from_secs(30);
+
+/// Input events Cyberdriver injected so far, by kind. The device monitor
+/// credits the changes it observes against them, and anything beyond them
+/// is the local user, even while remote input is running.
+static INJECTED_MOVES: AtomicU64 = AtomicU64::new(0);
+static INJECTED_KEYS: AtomicU64 = AtomicU64::new(0);
+static INJECTED_BUTTONS: AtomicU64 = AtomicU64::new(0);
+
+/// An input event about to be injected. Key and button counts are
+/// transitions: a click or a typed character is a press and a release.
+pub enum Injected {
+  Move,
+  Keys(usize),
+  Buttons(usize),
+}
+
+/// Tells the device monitor that Cyberdriver itself is about to inject
+/// `input`, so the change it causes is not taken for the local user.
+pub fn record_injected(input: Injected) {
+  let (counter, count) = match input {
+    Injected::Move => (&INJECTED_MOVES, 1),
+    Injected::Keys(count) => (&INJECTED_KEYS, count),
+    Injected::Buttons(count) => (&INJECTED_BUTTONS, count),
+  };
+  counter.fetch_add(count as u64, Ordering::SeqCst);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YieldMode {
+  /// Reject remote input with a retryable 503 while the local user is active.
+  Reject,
+  /// Hold remote input until the local user goes idle (bounded wait).
+  Queue,
+}
+
+impl YieldMode {
+  pub fn from_str(mode: &str) -> Self {
+    match mode.to_lowercase().as_str() {
+      "queue" | "wait" => Self::Queue,
+      _ => Self::Reject,
+    }
+  }
+}
+
+/// Tracks whether someone is physically using the mouse or keyboard.
+///
+/// The monitor polls device state and compares each change with the input
+/// Cyberdriver injected (see [`record_injected`]): pointer moves, key and
+/// button transitions beyond what was injected, and keys or buttons held
+/// that were not pressed by injection, are the local user.
+#[derive(Clone)]
+pub struct LocalPresence {
+  inner: Arc<PresenceInner>,
+}
+
+struct PresenceInner {
+  epoch: Instant,
+  enabled: AtomicBool,
+  queue: AtomicBool,
+  idle_threshold_ms: AtomicU64,
+  last_local_ms: AtomicU64,
+  poller: std::sync::Mutex<Option<CancellationToken>>,
+}
+
+impl PresenceInner {
+  fn now_ms(&self) -> u64 {
+    self.epoch.elapsed().as_millis() as u64 + 1
+  }
+}
+
+impl LocalPresence {
+  pub fn new(enabled: bool, idle_seconds: f64, mode: YieldMode) -> Self {
+    let presence = Self {
+      inner: Arc::new(PresenceInner {
+        epoch: Instant::now(),
+        enabled: AtomicBool::new(false),
+        queue: AtomicBool::new(false),
+        idle_threshold_ms: AtomicU64::new(0),
+        last_local_ms: AtomicU64::new(0),
+        poller: std::sync::Mutex::new(None),
+      }),
+    };
+    presence.update_config(enabled, idle_seconds, mode);
+    presence
+  }
+
+  pub fn update_config(&self, enabled: bool, idle_seconds: f64, mode: YieldMode) {
+    let inner = &self.inner;
+    inner.enabled.store(enabled, Ordering::SeqCst);
+    inner.queue.store(mode == YieldMode::Queue, Ordering::SeqCst);
+    inner
+      .idle_threshold_ms
+      .store((idle_seconds.max(0.1) * 1000.0) as u64, Ordering::SeqCst);
+    if enabled {
+      self.ensure_started();
+    } else {
+      self.stop();
+    }
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    self.inner.enabled.load(Ordering::SeqCst)
+  }
+
+  pub fn mode(&self) -> YieldMode {
+    if self.inner.queue.load(Ordering::SeqCst) {
+      YieldMode::Queue
+    } else {
+      YieldMode::Reject
+    }
+  }
+
+  pub fn seconds_since_local_activity(&self) -> Option<f64> {
+    let last = self.inner.last_local_ms.load(Ordering::SeqCst);
+    if last == 0 {
+      return None;
+    }
+    Some(self.inner.now_ms().saturating_sub(last) as f64 / 1000.0)
+  }
+
+  /// Time left until the local user counts as idle, or `None` if they already are.
+  pub fn remaining_active(&self) -> Option<Duration> {
+    let last = self.inner.last_local_ms.load(Ordering::SeqCst);
+    if last == 0 {
+      return None;
+    }
+    let threshold = self.inner.idle_threshold_ms.load(Ordering::SeqCst);
+    let elapsed = self.inner.now_ms().saturating_sub(last);
+    (elapsed < threshold).then(|| Duration::from_millis(threshold - elapsed))
+  }
+
+  pub fn is_local_user_active(&self) -> bool {
+    self.remaining_active().is_some()
+  }
+
+  /// True when remote input should currently be held back.
+  pub fn should_yield(&self) -> bool {
+    self.is_enabled() && self.is_local_user_active()
+  }
+
+  /// Waits until the local user has been idle for the configured period.
+  /// Returns false if they were still active after the maximum queue wait.
+  pub async fn wait_until_idle(&self) -> bool {
+    let deadline = Instant::now() + MAX_QUEUE_WAIT;
+    while let Some(remaining) = self.remaining_active() {
+      let now = Instant::now();
+      if now >= deadline {
+        return false;
+      }
+      tokio::time::sleep(remaining.min(deadline - now)).await;
+    }
+    true
+  }
+
+  fn ensure_started(&self) {
+    let mut poller = match self.inner.poller.lock() {
+      Ok(poller) => poller,
+      Err(_) => return,
+    };
+    if poller.is_some() {
+      return;
+    }
+    let stop = CancellationToken::new();
+    let inner = self.inner.clone();
+    let stop_signal = stop.clone();
+    std::thread::spawn(move || poll_devices(inner, stop_signal));
+    *poller = Some(stop);
+  }
+
+  pub fn stop(&self) {
+    if let Ok(mut poller) = self.inner.poller.lock() {
+      if let Some(stop) = poller.take() {
+        stop.cancel();
+      }
+    }
+  }
+}
+
+#[derive(PartialEq)]
+struct DeviceSnapshot {
+  coords: (i32, i32),
+  buttons: Vec<bool>,
+  keys: Vec<Keycode>,
+}
+
+/// Keys and buttons pressed by injection. They stay down when the remote
+/// agent holds them (e.g. `mouse_hold`, a drag, a modifier left pressed by a
+/// key sequence) and are not local activity until they are released.
+#[derive(Default)]
+struct SyntheticHeld {
+  keys: Vec<Keycode>,
+  buttons: Vec<usize>,
+}
+
+impl SyntheticHeld {
+  /// Forgets released keys and buttons, returning whether anything else is
+  /// held down.
+  fn holding_other(&mut self, snapshot: &DeviceSnapshot) -> bool {
+    self.keys.retain(|key| snapshot.keys.contains(key));
+    self.buttons.retain(|button| snapshot.buttons.get(*button) == Some(&true));
+    snapshot.keys.iter().any(|key| !self.keys.contains(key))
+      || snapshot
+        .buttons
+        .iter()
+        .enumerate()
+        .any(|(button, pressed)| *pressed && !self.buttons.contains(&button))
+  }
+}
+
+/// Observed changes of one kind are paid for out of what was injected since
+/// the last poll, plus what the poll before left unused. Older credit lapses,
+/// so injected events the monitor never saw cannot hide later local input.
+struct Credit {
+  injected: &'static AtomicU64,
+  seen: u64,
+  carried: u64,
+}
+
+impl Credit {
+  fn new(injected: &'static AtomicU64) -> Self {
+    Self {
+      injected,
+      seen: injected.load(Ordering::SeqCst),
+      carried: 0,
+    }
+  }
+
+  /// Pays for `observed` changes, returning whether some were not injected.
+  fn exceeded_by(&mut self, observed: u64) -> bool {
+    let total = self.injected.load(Ordering::SeqCst);
+    let fresh = total.wrapping_sub(self.seen);
+    self.seen = total;
+    let available = fresh + self.carried;
+    self.carried = available.saturating_sub(observed).min(fresh);
+    observed > available
+  }
+}
+
+fn poll_devices(inner: Arc<PresenceInner>, stop: CancellationToken) {
+  let state = DeviceState::new();
+  let mut previous: Option<DeviceSnapshot> = None;
+  let mut synthetic_held = SyntheticHeld::default();
+  let mut moves = Credit::new(&INJECTED_MOVES);
+  let mut keys = Credit::new(&INJECTED_KEYS);
+  let mut buttons = Credit::new(&INJECTED_BUTTONS);
+  while !stop.is_cancelled() {
+    let mouse = state.get_mouse();
+    let snapshot = DeviceSnapshot {
+      coords: mouse.coords,
+      buttons: mouse.button_pressed,
+      keys: state.get_keys(),
+    };
+    if let Some(previous) = &previous {
+      let pressed_keys: Vec<Keycode> = snapshot
+        .keys
+        .iter()
+        .filter(|key| !previous.keys.contains(key))
+        .copied()
+        .collect();
+      let released_keys = previous.keys.iter().filter(|key| !snapshot.keys.contains(key)).count();
+      let is_down = |snapshot: &DeviceSnapshot, button: usize| {
+        snapshot.buttons.get(button) == Some(&true)
+      };
+      let button_count = snapshot.buttons.len().max(previous.buttons.len());
+      let changed_buttons: Vec<usize> = (0..button_count)
+        .filter(|button| is_down(&snapshot, *button) != is_down(previous, *button))
+        .collect();
+
+      let moved = moves.exceeded_by(u64::from(snapshot.coords != previous.coords));
+      let typed = keys.exceeded_by((pressed_keys.len() + released_keys) as u64);
+      let clicked = buttons.exceeded_by(changed_buttons.len() as u64);
+      if !typed {
+        synthetic_held.keys.extend(pressed_keys);
+      }
+      if !clicked {
+        synthetic_held
+          .buttons
+          .extend(changed_buttons.into_iter().filter(|button| is_down(&snapshot, *button)));
+      }
+      let holding = synthetic_held.holding_other(&snapshot);
+      if moved || typed || clicked || holding {
+        inner.last_local_ms.store(inner.now_ms(), Ordering::SeqCst);
+      }
+    }
+    previous = Some(snapshot);
+    std::thread::sleep(POLL_INTERVAL);
+  }
+}
@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+
+use super::api::{ApiError, ApiResult, ApiState};
+
+/// Per-command resource caps. On Unix CPU time, memory and open files are
+/// rlimits set in the child before it execs, so they are inherited by (and
+/// counted separately for) every process it starts; CPU time is cumulative
+/// per process, so a busy process is sent SIGXCPU and then killed. The
+/// wall-clock limit covers the whole job: when it passes, the job's process
+/// group is killed.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub(super) struct ResourceLimits {
+  cpu_seconds: Option<u64>,
+  memory_mb: Option<u64>,
+  open_files: Option<u64>,
+  pub(super) wall_seconds: Option<f64>,
+}
+
+impl ResourceLimits {
+  /// Rejects zero or negative caps, and the rlimit caps where they cannot
+  /// be enforced.
+  pub(super) fn validate(&self) -> ApiResult<()> {
+    let counts = [self.cpu_seconds, self.memory_mb, self.open_files];
+    if counts.contains(&Some(0)) || self.wall_seconds.is_some_and(|seconds| seconds <= 0.0) {
+      return Err(ApiError::bad_request("limits must be positive"));
+    }
+    if cfg!(not(unix)) && counts.iter().any(Option::is_some) {
+      return Err(ApiError::bad_request(
+        "cpu_seconds, memory_mb and open_files limits are not supported on Windows",
+      ));
+    }
+    Ok(())
+  }
+
+  pub(super) fn is_empty(&self) -> bool {
+    self.cpu_seconds.is_none()
+      && self.memory_mb.is_none()
+      && self.open_files.is_none()
+      && self.wall_seconds.is_none()
+  }
+}
+
+/// The account from the `shell_run_as_user` setting that shell commands
+/// are started as. Switching users needs Cyberdriver itself to run as root;
+/// supplementary groups are dropped.
+#[derive(Clone)]
+pub(super) struct RunAs {
+  pub(super) name: String,
+  #[cfg(unix)]
+  uid: u32,
+  #[cfg(unix)]
+  gid: u32,
+  #[cfg(unix)]
+  home: String,
+}
+
+impl RunAs {
+  /// Resolves the configured user, or `None` when commands run as the
+  /// current user. An unknown user fails every command rather than
+  /// silently running it with full rights.
+  pub(super) async fn configured(state: &ApiState) -> ApiResult<Option<Self>> {
+    let name = state.settings.lock().await.shell_run_as_user.trim().to_string();
+    if name.is_empty() {
+      return Ok(None);
+    }
+    Self::lookup(name).map(Some)
+  }
+
+  #[cfg(unix)]
+  fn lookup(name: String) -> ApiResult<Self> {
+    use std::ffi::{CStr, CString};
+
+    let unknown =
+      || ApiError::internal(&format!("Configured shell user '{name}' does not exist"));
+    let c_name = CString::new(name.as_str()).map_err(|_| unknown())?;
+    // SAFETY: an all-zero passwd is a valid out-parameter for getpwnam_r.
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = std::ptr::null_mut();
+    // SAFETY: every pointer refers to live storage of the stated size.
+    let status = unsafe {
+      libc::getpwnam_r(
+        c_name.as_ptr(),
+        &mut entry,
+        buffer.as_mut_ptr(),
+        buffer.len(),
+        &mut found,
+      )
+    };
+    if status != 0 || found.is_null() {
+      return Err(unknown());
+    }
+    // SAFETY: on success pw_dir points into `buffer`, which is still alive.
+    let home = unsafe { CStr::from_ptr(entry.pw_dir) }.to_string_lossy().to_string();
+    Ok(Self {
+      uid: entry.pw_uid,
+      gid: entry.pw_gid,
+      home,
+      name,
+    })
+  }
+
+  #[cfg(not(unix))]
+  fn lookup(_name: String) -> ApiResult<Self> {
+    Err(ApiError::internal(
+      "Running shell commands as another user is not supported on Windows",
+    ))
+  }
+
+  /// Makes the user the owner of a file Cyberdriver created for them.
+  #[cfg(unix)]
+  pub(super) fn give(&self, path: &std::path::Path) -> std::io::Result<()> {
+    std::os::unix::fs::chown(path, Some(self.uid), Some(self.gid))
+  }
+
+  #[cfg(not(unix))]
+  pub(super) fn give(&self, _path: &std::path::Path) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+/// Applies the rlimits and user switch to a command about to be spawned.
+/// The wall-clock limit is enforced by the caller.
+pub(super) fn apply(
+  cmd: &mut tokio::process::Command,
+  limits: &ResourceLimits,
+  run_as: Option<&RunAs>,
+) {
+  #[cfg(unix)]
+  {
+    if let Some(user) = run_as {
+      cmd.uid(user.uid).gid(user.gid);
+      cmd
+        .env("HOME", &user.home)
+        .env("USER", &user.name)
+        .env("LOGNAME", &user.name);
+    }
+    // The hard CPU limit is one second higher so SIGXCPU arrives first.
+    let cpu = limits.cpu_seconds.map(|seconds| (seconds, seconds.saturating_add(1)));
+    let memory = limits.memory_mb.map(|mb| {
+      let bytes = mb.saturating_mul(1024 * 1024);
+      (bytes, bytes)
+    });
+    let files = limits.open_files.map(|files| (files, files));
+    let rlimits = [
+      (libc::RLIMIT_CPU, cpu),
+      (libc::RLIMIT_AS, memory),
+      (libc::RLIMIT_NOFILE, files),
+    ];
+    if rlimits.iter().any(|(_, limit)| limit.is_some()) {
+      // SAFETY: the closure only calls setrlimit, which is async-signal-safe,
+      // and does not allocate.
+      unsafe {
+        cmd.pre_exec(move || {
+          for (resource, limit) in rlimits {
+            let Some((soft, hard)) = limit else {
+              continue;
+            };
+            let limit = libc::rlimit {
+              rlim_cur: soft,
+              rlim_max: hard,
+            };
+            if libc::setrlimit(resource, &limit) != 0 {
+              return Err(std::io::Error::last_os_error());
+            }
+          }
+          Ok(())
+        });
+      }
+    }
+  }
+  #[cfg(not(unix))]
+  let _ = (cmd, limits, run_as);
+}
@@ -0,0 +1,954 @@
+use std::{
+  collections::HashMap,
+  process::Stdio,
+  sync::{Arc, Weak},
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+  extract::{Path, Query, State},
+  http::StatusCode,
+  Json,
+};
+use base64::Engine;
+use serde::Deserialize;
+use tokio::{
+  io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+  process::ChildStdin,
+  sync::{watch, Mutex, Notify},
+};
+
+use super::{
+  api::{ApiError, ApiResult, ApiState},
+  process_limits::{self, ResourceLimits, RunAs},
+  shell_policy,
+};
+
+/// Finished jobs (and their output) are kept this long before being dropped.
+const FINISHED_JOB_TTL: Duration = Duration::from_secs(60 * 60);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_RUNNING_JOBS: usize = 64;
+/// Per-stream output retained for a job; older bytes are discarded first.
+const MAX_JOB_OUTPUT_BYTES: usize = 16 * 1024 * 1024;
+const DEFAULT_READ_BYTES: usize = 1024 * 1024;
+/// How long a finished job's pipes may keep draining before it is reported
+/// as exited; background grandchildren can hold them open indefinitely.
+const OUTPUT_DRAIN_GRACE: Duration = Duration::from_secs(2);
+const DEFAULT_WAIT_TIMEOUT_SECONDS: f64 = 30.0;
+const MAX_WAIT_TIMEOUT_SECONDS: f64 = 600.0;
+/// How long a killed job may take to be reaped before giving up on it.
+pub(super) const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// Background processes started through `/computer/processes`, keyed by
+/// job id. On Unix each job runs in its own process group so it can be
+/// signalled as a whole; on Windows trees are killed with `taskkill /T`.
+pub struct ProcessJobs {
+  jobs: Mutex<HashMap<String, Arc<Job>>>,
+}
+
+pub(super) struct JobSpec {
+  /// A command line, or a script body when `script` is set.
+  pub(super) command: String,
+  pub(super) script: bool,
+  pub(super) interpreter: Interpreter,
+  pub(super) working_directory: Option<String>,
+  pub(super) env: HashMap<String, String>,
+  pub(super) stdin: JobInput,
+  pub(super) limits: ResourceLimits,
+  pub(super) run_as: Option<RunAs>,
+}
+
+pub(super) enum JobInput {
+  Null,
+  /// Kept open for `/computer/processes/:id/stdin` writes.
+  Open,
+  /// Written up front, then closed.
+  Data(Vec<u8>),
+}
+
+/// Programs a job can run its command or script with.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum Interpreter {
+  Sh,
+  Bash,
+  Zsh,
+  PowerShell,
+  Pwsh,
+  Python,
+}
+
+impl Interpreter {
+  pub(super) fn from_name(name: Option<&str>) -> ApiResult<Self> {
+    let Some(name) = name else {
+      return Ok(if cfg!(windows) { Self::PowerShell } else { Self::Sh });
+    };
+    match name.to_lowercase().as_str() {
+      "sh" => Ok(Self::Sh),
+      "bash" => Ok(Self::Bash),
+      "zsh" => Ok(Self::Zsh),
+      "powershell" => Ok(Self::PowerShell),
+      "pwsh" => Ok(Self::Pwsh),
+      "python" | "python3" => Ok(Self::Python),
+      _ => Err(ApiError::bad_request(
+        "shell must be 'sh', 'bash', 'zsh', 'powershell', 'pwsh' or 'python'",
+      )),
+    }
+  }
+
+  fn program(self) -> &'static str {
+    match self {
+      Self::Sh => "/bin/sh",
+      Self::Bash => "bash",
+      Self::Zsh => "zsh",
+      Self::PowerShell => "powershell",
+      Self::Pwsh => "pwsh",
+      Self::Python if cfg!(windows) => "python",
+      Self::Python => "python3",
+    }
+  }
+
+  fn is_powershell(self) -> bool {
+    matches!(self, Self::PowerShell | Self::Pwsh)
+  }
+
+  fn script_extension(self) -> &'static str {
+    match self {
+      Self::Sh | Self::Bash | Self::Zsh => "sh",
+      Self::PowerShell | Self::Pwsh => "ps1",
+      Self::Python => "py",
+    }
+  }
+
+  /// Builds the invocation for an inline command, or for a script file.
+  fn command(self, command: &str, script: Option<&std::path::Path>) -> tokio::process::Command {
+    let mut cmd = tokio::process::Command::new(self.program());
+    if self.is_powershell() {
+      cmd.args(["-NoLogo", "-NoProfile", "-NonInteractive", "-ExecutionPolicy", "Bypass"]);
+    }
+    match (script, self) {
+      (Some(path), interpreter) if interpreter.is_powershell() => {
+        cmd.arg("-File").arg(path);
+      }
+      (Some(path), _) => {
+        cmd.arg(path);
+      }
+      (None, interpreter) if interpreter.is_powershell() => {
+        cmd.arg("-Command").arg(command);
+      }
+      (None, _) => {
+        cmd.arg("-c").arg(command);
+      }
+    }
+    cmd
+  }
+}
+
+pub(super) struct Job {
+  pub(super) id: String,
+  command: String,
+  working_directory: Option<String>,
+  pub(super) pid: Option<u32>,
+  limits: ResourceLimits,
+  user: Option<String>,
+  started: SystemTime,
+  stdin: Mutex<Option<ChildStdin>>,
+  pub(super) stdout: Arc<JobStream>,
+  pub(super) stderr: Arc<JobStream>,
+  exit: watch::Receiver<Option<JobExit>>,
+  last_signal: std::sync::Mutex<Option<String>>,
+}
+
+#[derive(Clone)]
+pub(super) struct JobExit {
+  pub(super) code: Option<i32>,
+  signal: Option<i32>,
+  finished: SystemTime,
+  finished_at: Instant,
+}
+
+/// Output captured from one of a job's pipes. Offsets are absolute byte
+/// positions in the stream, so they stay valid after old output is dropped.
+pub(super) struct JobStream {
+  buffer: std::sync::Mutex<StreamBuffer>,
+  notify: Notify,
+}
+
+#[derive(Default)]
+struct StreamBuffer {
+  data: Vec<u8>,
+  start: u64,
+  closed: bool,
+}
+
+pub(super) struct StreamChunk {
+  pub(super) data: Vec<u8>,
+  pub(super) next_offset: u64,
+  pub(super) skipped: u64,
+}
+
+impl JobStream {
+  fn new() -> Arc<Self> {
+    Arc::new(Self {
+      buffer: std::sync::Mutex::new(StreamBuffer::default()),
+      notify: Notify::new(),
+    })
+  }
+
+  fn push(&self, bytes: &[u8]) {
+    if let Ok(mut buffer) = self.buffer.lock() {
+      buffer.data.extend_from_slice(bytes);
+      let excess = buffer.data.len().saturating_sub(MAX_JOB_OUTPUT_BYTES);
+      if excess > 0 {
+        buffer.data.drain(..excess);
+        buffer.start += excess as u64;
+      }
+    }
+    self.notify.notify_waiters();
+  }
+
+  fn close(&self) {
+    if let Ok(mut buffer) = self.buffer.lock() {
+      buffer.closed = true;
+    }
+    self.notify.notify_waiters();
+  }
+
+  pub(super) fn is_closed(&self) -> bool {
+    self.buffer.lock().map(|buffer| buffer.closed).unwrap_or(true)
+  }
+
+  /// Total bytes written to the stream so far.
+  pub(super) fn written(&self) -> u64 {
+    self
+      .buffer
+      .lock()
+      .map(|buffer| buffer.start + buffer.data.len() as u64)
+      .unwrap_or(0)
+  }
+
+  /// Waits until output past `offset` arrives, the stream closes or
+  /// `deadline` passes.
+  pub(super) async fn wait_past(&self, offset: u64, deadline: tokio::time::Instant) {
+    loop {
+      let notified = self.notify.notified();
+      if self.written() > offset || self.is_closed() {
+        return;
+      }
+      if tokio::time::timeout_at(deadline, notified).await.is_err() {
+        return;
+      }
+    }
+  }
+
+  /// Reads up to `max` bytes from `offset`. A read never ends inside a
+  /// UTF-8 sequence while more output may follow, so chunks decode cleanly.
+  pub(super) fn read(&self, offset: u64, max: usize) -> StreamChunk {
+    let Ok(buffer) = self.buffer.lock() else {
+      return StreamChunk {
+        data: Vec::new(),
+        next_offset: offset,
+        skipped: 0,
+      };
+    };
+    let end = buffer.start + buffer.data.len() as u64;
+    let from = offset.clamp(buffer.start, end);
+    let skipped = from.saturating_sub(offset);
+    let begin = (from - buffer.start) as usize;
+    let mut data = buffer.data[begin..].iter().take(max).copied().collect::<Vec<_>>();
+    if !(buffer.closed && begin + data.len() == buffer.data.len()) {
+      data.truncate(complete_utf8_len(&data));
+    }
+    StreamChunk {
+      next_offset: from + data.len() as u64,
+      data,
+      skipped,
+    }
+  }
+}
+
+/// Length of `bytes` without a trailing incomplete UTF-8 sequence.
+fn complete_utf8_len(bytes: &[u8]) -> usize {
+  match std::str::from_utf8(bytes) {
+    Ok(_) => bytes.len(),
+    Err(err) if err.error_len().is_none() => err.valid_up_to(),
+    Err(_) => bytes.len(),
+  }
+}
+
+impl Job {
+  pub(super) fn exit(&self) -> Option<JobExit> {
+    self.exit.borrow().clone()
+  }
+
+  /// Waits up to `timeout` for the job to finish.
+  pub(super) async fn wait(&self, timeout: Duration) -> Option<JobExit> {
+    let mut exit = self.exit.clone();
+    let _ = tokio::time::timeout(timeout, exit.wait_for(|exit| exit.is_some())).await;
+    self.exit()
+  }
+
+  /// Kills the job's whole process tree if any of it is still running,
+  /// recording `reason` as the last signal sent. On Unix the process group
+  /// is killed even after the top process exited, so children it left
+  /// behind die too.
+  pub(super) fn kill_tree(&self, reason: &str) -> bool {
+    let Some(pid) = self.pid else {
+      return false;
+    };
+    // Without process groups an exited pid may already be reused.
+    if self.exit().is_some() && !cfg!(unix) {
+      return false;
+    }
+    let killed = kill_process_tree(pid).is_ok();
+    if killed {
+      if let Ok(mut last_signal) = self.last_signal.lock() {
+        *last_signal = Some(reason.to_string());
+      }
+    }
+    killed
+  }
+
+  fn to_json(&self) -> serde_json::Value {
+    let exit = self.exit();
+    let status = match &exit {
+      None => "running",
+      Some(exit) if exit.signal.is_some() => "killed",
+      Some(_) => "exited",
+    };
+    serde_json::json!({
+      "job_id": self.id,
+      "pid": self.pid,
+      "command": self.command,
+      "working_directory": self.working_directory,
+      "user": self.user,
+      "limits": (!self.limits.is_empty()).then_some(&self.limits),
+      "status": status,
+      "running": exit.is_none(),
+      "exit_code": exit.as_ref().and_then(|exit| exit.code),
+      "signal": exit.as_ref().and_then(|exit| exit.signal),
+      "last_signal_sent": self.last_signal.lock().ok().and_then(|signal| signal.clone()),
+      "started": unix_seconds(self.started),
+      "finished": exit.as_ref().map(|exit| unix_seconds(exit.finished)),
+      "stdout_bytes": self.stdout.written(),
+      "stderr_bytes": self.stderr.written(),
+    })
+  }
+}
+
+impl ProcessJobs {
+  pub fn new() -> Arc<Self> {
+    let jobs = Arc::new(Self {
+      jobs: Mutex::new(HashMap::new()),
+    });
+    tauri::async_runtime::spawn(Self::sweep(Arc::downgrade(&jobs)));
+    jobs
+  }
+
+  async fn sweep(jobs: Weak<Self>) {
+    loop {
+      tokio::time::sleep(SWEEP_INTERVAL).await;
+      let Some(jobs) = jobs.upgrade() else {
+        return;
+      };
+      jobs.jobs.lock().await.retain(|_, job| {
+        job
+          .exit()
+          .is_none_or(|exit| exit.finished_at.elapsed() < FINISHED_JOB_TTL)
+      });
+    }
+  }
+
+  /// Spawns `spec.command` through its interpreter and starts capturing its
+  /// output. Script bodies are written to a temporary file that is removed
+  /// when the job exits.
+  pub(super) async fn start(&self, spec: JobSpec) -> ApiResult<Arc<Job>> {
+    let mut jobs = self.jobs.lock().await;
+    let running = jobs.values().filter(|job| job.exit().is_none()).count();
+    if running >= MAX_RUNNING_JOBS {
+      return Err(ApiError::status(
+        StatusCode::TOO_MANY_REQUESTS,
+        &format!("Too many running jobs (max {MAX_RUNNING_JOBS})"),
+      ));
+    }
+
+    let script = if spec.script {
+      Some(write_script(&spec.command, spec.interpreter, spec.run_as.as_ref())?)
+    } else {
+      None
+    };
+    let mut cmd = spec.interpreter.command(&spec.command, script.as_deref());
+    if let Some(dir) = spec.working_directory.as_deref() {
+      cmd.current_dir(dir);
+    }
+    cmd.envs(&spec.env);
+    process_limits::apply(&mut cmd, &spec.limits, spec.run_as.as_ref());
+    #[cfg(unix)]
+    cmd.process_group(0);
+    let stdin = match spec.stdin {
+      JobInput::Null => Stdio::null(),
+      JobInput::Open | JobInput::Data(_) => Stdio::piped(),
+    };
+    let spawned = cmd
+      .stdin(stdin)
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn();
+    let mut child = match spawned {
+      Ok(child) => child,
+      Err(err) => {
+        if let Some(script) = &script {
+          let _ = std::fs::remove_file(script);
+        }
+        let message = format!("Failed to start {}: {err}", spec.interpreter.program());
+        return Err(match err.kind() {
+          std::io::ErrorKind::NotFound => ApiError::bad_request(&message),
+          _ => ApiError::internal(&message),
+        });
+      }
+    };
+    let mut stdin = child.stdin.take();
+    if let (JobInput::Data(data), Some(mut pipe)) = (spec.stdin, stdin.take()) {
+      tauri::async_runtime::spawn(async move {
+        // A process that exits without reading its input is not an error.
+        let _ = pipe.write_all(&data).await;
+      });
+    }
+
+    let stdout = JobStream::new();
+    let stderr = JobStream::new();
+    let readers = [
+      child.stdout.take().map(|pipe| capture(pipe, stdout.clone())),
+      child.stderr.take().map(|pipe| capture(pipe, stderr.clone())),
+    ];
+    let (exit_sender, exit) = watch::channel(None);
+    let job = Arc::new(Job {
+      id: uuid::Uuid::new_v4().to_string(),
+      command: spec.command,
+      working_directory: spec.working_directory,
+      pid: child.id(),
+      limits: spec.limits,
+      user: spec.run_as.map(|user| user.name),
+      started: SystemTime::now(),
+      stdin: Mutex::new(stdin),
+      stdout,
+      stderr,
+      exit,
+      last_signal: std::sync::Mutex::new(None),
+    });
+
+    tauri::async_runtime::spawn(async move {
+      let status = child.wait().await.ok();
+      let drained = async {
+        for reader in readers.into_iter().flatten() {
+          let _ = reader.await;
+        }
+      };
+      let _ = tokio::time::timeout(OUTPUT_DRAIN_GRACE, drained).await;
+      if let Some(script) = script {
+        let _ = tokio::fs::remove_file(script).await;
+      }
+      let _ = exit_sender.send(Some(JobExit {
+        code: status.and_then(|status| status.code()),
+        signal: status.and_then(exit_signal),
+        finished: SystemTime::now(),
+        finished_at: Instant::now(),
+      }));
+    });
+
+    if let Some(wall_seconds) = job.limits.wall_seconds {
+      let job = job.clone();
+      tauri::async_runtime::spawn(async move {
+        if job.wait(Duration::from_secs_f64(wall_seconds)).await.is_none() {
+          job.kill_tree("KILL (wall-clock limit)");
+        }
+      });
+    }
+
+    jobs.insert(job.id.clone(), job.clone());
+    Ok(job)
+  }
+
+  pub(super) async fn get(&self, job_id: &str) -> ApiResult<Arc<Job>> {
+    self
+      .jobs
+      .lock()
+      .await
+      .get(job_id)
+      .cloned()
+      .ok_or_else(|| ApiError::status(StatusCode::NOT_FOUND, "Job not found"))
+  }
+
+  pub(super) async fn remove(&self, job_id: &str) -> Option<Arc<Job>> {
+    self.jobs.lock().await.remove(job_id)
+  }
+
+  /// Kills every running job and forgets all jobs, returning how many were
+  /// still running.
+  pub(super) async fn kill_all(&self, reason: &str) -> usize {
+    let jobs: Vec<Arc<Job>> = self.jobs.lock().await.drain().map(|(_, job)| job).collect();
+    jobs.iter().filter(|job| job.kill_tree(reason)).count()
+  }
+
+  /// Kills every running job but keeps the jobs, so their output and exit
+  /// status can still be read. Returns how many were killed.
+  pub(super) async fn kill_running(&self, reason: &str) -> usize {
+    let jobs: Vec<Arc<Job>> = self.jobs.lock().await.values().cloned().collect();
+    jobs.iter().filter(|job| job.kill_tree(reason)).count()
+  }
+}
+
+/// Writes a script body to a temporary file for its interpreter. Windows
+/// PowerShell reads BOM-less scripts in the ANSI code page, so PowerShell
+/// scripts get a UTF-8 BOM. On Unix the file is readable only by the user
+/// the job runs as.
+fn write_script(
+  body: &str,
+  interpreter: Interpreter,
+  run_as: Option<&RunAs>,
+) -> ApiResult<std::path::PathBuf> {
+  let path = std::env::temp_dir().join(format!(
+    "cyberdriver-{}.{}",
+    uuid::Uuid::new_v4().simple(),
+    interpreter.script_extension()
+  ));
+  let mut contents = Vec::with_capacity(body.len() + 3);
+  if interpreter.is_powershell() {
+    contents.extend_from_slice(b"\xEF\xBB\xBF");
+  }
+  contents.extend_from_slice(body.as_bytes());
+  let mut options = std::fs::OpenOptions::new();
+  options.write(true).create_new(true);
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+  let written = options.open(&path).and_then(|mut file| {
+    std::io::Write::write_all(&mut file, &contents)?;
+    match run_as {
+      Some(user) => user.give(&path),
+      None => Ok(()),
+    }
+  });
+  if let Err(err) = written {
+    let _ = std::fs::remove_file(&path);
+    return Err(ApiError::internal(&format!("Failed to write script file: {err}")));
+  }
+  Ok(path)
+}
+
+fn capture<R: AsyncRead + Unpin + Send + 'static>(
+  mut pipe: R,
+  stream: Arc<JobStream>,
+) -> tauri::async_runtime::JoinHandle<()> {
+  tauri::async_runtime::spawn(async move {
+    let mut chunk = vec![0u8; 8192];
+    loop {
+      match pipe.read(&mut chunk).await {
+        Ok(0) | Err(_) => break,
+        Ok(read) => stream.push(&chunk[..read]),
+      }
+    }
+    stream.close();
+  })
+}
+
+#[cfg(unix)]
+fn exit_signal(status: std::process::ExitStatus) -> Option<i32> {
+  use std::os::unix::process::ExitStatusExt;
+  status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: std::process::ExitStatus) -> Option<i32> {
+  None
+}
+
+/// A signal accepted by the process endpoints, by name (with or without
+/// `SIG`, any case) or by its POSIX number.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum SignalName {
+  Term,
+  Kill,
+  Int,
+  Hup,
+  Quit,
+  Stop,
+  Cont,
+  Usr1,
+  Usr2,
+}
+
+impl SignalName {
+  pub(super) fn parse(name: &str) -> Option<Self> {
+    let name = name.to_uppercase();
+    let signal = match name.strip_prefix("SIG").unwrap_or(&name) {
+      "TERM" | "15" => Self::Term,
+      "KILL" | "9" => Self::Kill,
+      "INT" | "2" => Self::Int,
+      "HUP" | "1" => Self::Hup,
+      "QUIT" | "3" => Self::Quit,
+      "STOP" => Self::Stop,
+      "CONT" => Self::Cont,
+      "USR1" => Self::Usr1,
+      "USR2" => Self::Usr2,
+      _ => return None,
+    };
+    Some(signal)
+  }
+
+  pub(super) fn to_sysinfo(self) -> sysinfo::Signal {
+    match self {
+      Self::Term => sysinfo::Signal::Term,
+      Self::Kill => sysinfo::Signal::Kill,
+      Self::Int => sysinfo::Signal::Interrupt,
+      Self::Hup => sysinfo::Signal::Hangup,
+      Self::Quit => sysinfo::Signal::Quit,
+      Self::Stop => sysinfo::Signal::Stop,
+      Self::Cont => sysinfo::Signal::Continue,
+      Self::Usr1 => sysinfo::Signal::User1,
+      Self::Usr2 => sysinfo::Signal::User2,
+    }
+  }
+
+  #[cfg(unix)]
+  fn number(self) -> i32 {
+    match self {
+      Self::Term => libc::SIGTERM,
+      Self::Kill => libc::SIGKILL,
+      Self::Int => libc::SIGINT,
+      Self::Hup => libc::SIGHUP,
+      Self::Quit => libc::SIGQUIT,
+      Self::Stop => libc::SIGSTOP,
+      Self::Cont => libc::SIGCONT,
+      Self::Usr1 => libc::SIGUSR1,
+      Self::Usr2 => libc::SIGUSR2,
+    }
+  }
+}
+
+/// Any other positive number is passed through as is.
+#[cfg(unix)]
+fn parse_signal(name: &str) -> Option<i32> {
+  if let Ok(number) = name.parse::<i32>() {
+    return (number > 0).then_some(number);
+  }
+  SignalName::parse(name).map(SignalName::number)
+}
+
+/// Windows has no signals; TERM, INT and KILL all terminate the process.
+#[cfg(not(unix))]
+fn parse_signal(name: &str) -> Option<i32> {
+  match SignalName::parse(name)? {
+    SignalName::Term | SignalName::Int | SignalName::Kill => Some(9),
+    _ => None,
+  }
+}
+
+/// Sends `signal` to the process, or to its whole process group with `tree`.
+#[cfg(unix)]
+fn send_signal(pid: u32, signal: i32, tree: bool) -> std::io::Result<()> {
+  let target = if tree { -(pid as i32) } else { pid as i32 };
+  // SAFETY: kill(2) has no memory-safety preconditions.
+  if unsafe { libc::kill(target, signal) } == 0 {
+    Ok(())
+  } else {
+    Err(std::io::Error::last_os_error())
+  }
+}
+
+#[cfg(not(unix))]
+fn send_signal(pid: u32, _signal: i32, tree: bool) -> std::io::Result<()> {
+  let mut cmd = std::process::Command::new("taskkill");
+  cmd.args(["/F", "/PID", &pid.to_string()]);
+  if tree {
+    cmd.arg("/T");
+  }
+  let output = cmd.output()?;
+  if output.status.success() {
+    Ok(())
+  } else {
+    Err(std::io::Error::other(
+      String::from_utf8_lossy(&output.stderr).trim().to_string(),
+    ))
+  }
+}
+
+/// Kills a process and every process in its group (or tree on Windows).
+pub(super) fn kill_process_tree(pid: u32) -> std::io::Result<()> {
+  let kill = parse_signal("KILL").unwrap_or(9);
+  send_signal(pid, kill, true)
+}
+
+fn unix_seconds(time: SystemTime) -> Option<f64> {
+  time
+    .duration_since(UNIX_EPOCH)
+    .ok()
+    .map(|duration| duration.as_secs_f64())
+}
+
+#[derive(Deserialize)]
+pub(super) struct ProcessStartPayload {
+  #[serde(default)]
+  command: String,
+  script: Option<String>,
+  shell: Option<String>,
+  working_directory: Option<String>,
+  #[serde(default)]
+  env: HashMap<String, String>,
+  stdin: Option<bool>,
+  input: Option<String>,
+  #[serde(default)]
+  limits: ResourceLimits,
+}
+
+/// Starts `command` (or a multi-line `script` body) in the background with
+/// the chosen `shell` and returns its job id immediately. Stdin stays open
+/// for `/stdin` writes unless `stdin: false`, or `input` is given, which is
+/// written and then closed. `limits` caps `cpu_seconds`, `memory_mb`,
+/// `open_files` and `wall_seconds`, after which the process group is
+/// killed. Jobs run as the configured shell user, if any.
+pub(super) async fn post_process_start(
+  State(state): State<ApiState>,
+  Json(payload): Json<ProcessStartPayload>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let (command, script) = command_or_script(payload.command, payload.script)?;
+  let stdin = match (payload.input, payload.stdin.unwrap_or(true)) {
+    (Some(input), _) => JobInput::Data(input.into_bytes()),
+    (None, true) => JobInput::Open,
+    (None, false) => JobInput::Null,
+  };
+  let interpreter = Interpreter::from_name(payload.shell.as_deref())?;
+  payload.limits.validate()?;
+  shell_policy::authorize(&state, "/computer/processes/start", &command, payload.shell.as_deref())
+    .await?;
+  let run_as = RunAs::configured(&state).await?;
+  let job = state
+    .jobs
+    .start(JobSpec {
+      command,
+      script,
+      interpreter,
+      working_directory: payload.working_directory,
+      env: payload.env,
+      stdin,
+      limits: payload.limits,
+      run_as,
+    })
+    .await?;
+  state.debug_logger.log(
+    "PROCESS",
+    "Job started",
+    &[("job_id", job.id.clone()), ("pid", format!("{:?}", job.pid))],
+  );
+  Ok(Json(job.to_json()))
+}
+
+/// Picks the inline `command` or the `script` body from a payload; exactly
+/// one must be given. Returns the text and whether it is a script.
+pub(super) fn command_or_script(
+  command: String,
+  script: Option<String>,
+) -> ApiResult<(String, bool)> {
+  match (command.is_empty(), script) {
+    (true, Some(script)) if !script.is_empty() => Ok((script, true)),
+    (false, None) => Ok((command, false)),
+    (false, Some(_)) => Err(ApiError::bad_request("Pass either 'command' or 'script', not both")),
+    (true, _) => Err(ApiError::bad_request("Missing 'command' field")),
+  }
+}
+
+pub(super) async fn get_processes(
+  State(state): State<ApiState>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let jobs: Vec<Arc<Job>> = state.jobs.jobs.lock().await.values().cloned().collect();
+  let mut jobs: Vec<_> = jobs.iter().map(|job| (job.started, job.to_json())).collect();
+  jobs.sort_by_key(|(started, _)| *started);
+  Ok(Json(serde_json::json!({
+    "jobs": jobs.into_iter().map(|(_, job)| job).collect::<Vec<_>>(),
+  })))
+}
+
+pub(super) async fn get_process(
+  State(state): State<ApiState>,
+  Path(job_id): Path<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+  Ok(Json(state.jobs.get(&job_id).await?.to_json()))
+}
+
+/// Kills a running job's process tree and forgets the job.
+pub(super) async fn delete_process(
+  State(state): State<ApiState>,
+  Path(job_id): Path<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let job = state
+    .jobs
+    .remove(&job_id)
+    .await
+    .ok_or_else(|| ApiError::status(StatusCode::NOT_FOUND, "Job not found"))?;
+  let killed = job.kill_tree("KILL");
+  Ok(Json(serde_json::json!({ "job_id": job_id, "removed": true, "killed": killed })))
+}
+
+#[derive(Deserialize)]
+pub(super) struct ProcessOutputQuery {
+  stdout_offset: Option<u64>,
+  stderr_offset: Option<u64>,
+  max_bytes: Option<usize>,
+}
+
+/// Returns output written since the given offsets. Pass the returned
+/// `stdout_offset`/`stderr_offset` back to continue; `*_skipped` counts
+/// bytes that were dropped from the retained buffer before being read.
+pub(super) async fn get_process_output(
+  State(state): State<ApiState>,
+  Path(job_id): Path<String>,
+  Query(query): Query<ProcessOutputQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let job = state.jobs.get(&job_id).await?;
+  let max = query
+    .max_bytes
+    .unwrap_or(DEFAULT_READ_BYTES)
+    .clamp(1, MAX_JOB_OUTPUT_BYTES);
+  // Read the exit state first so `running: false` implies the output is complete.
+  let exit = job.exit();
+  let stdout = job.stdout.read(query.stdout_offset.unwrap_or(0), max);
+  let stderr = job.stderr.read(query.stderr_offset.unwrap_or(0), max);
+  Ok(Json(serde_json::json!({
+    "job_id": job.id,
+    "running": exit.is_none(),
+    "exit_code": exit.as_ref().and_then(|exit| exit.code),
+    "stdout": String::from_utf8_lossy(&stdout.data),
+    "stderr": String::from_utf8_lossy(&stderr.data),
+    "stdout_offset": stdout.next_offset,
+    "stderr_offset": stderr.next_offset,
+    "stdout_skipped": stdout.skipped,
+    "stderr_skipped": stderr.skipped,
+    "stdout_eof": job.stdout.is_closed() && stdout.next_offset == job.stdout.written(),
+    "stderr_eof": job.stderr.is_closed() && stderr.next_offset == job.stderr.written(),
+  })))
+}
+
+#[derive(Deserialize)]
+pub(super) struct ProcessStdinPayload {
+  #[serde(default)]
+  data: String,
+  encoding: Option<String>,
+  #[serde(default)]
+  close: bool,
+}
+
+/// Writes `data` (UTF-8 text, or base64 with `encoding: "base64"`) to the
+/// job's stdin; `close: true` closes it afterwards to signal EOF.
+pub(super) async fn post_process_stdin(
+  State(state): State<ApiState>,
+  Path(job_id): Path<String>,
+  Json(payload): Json<ProcessStdinPayload>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let job = state.jobs.get(&job_id).await?;
+  let bytes = match payload.encoding.as_deref() {
+    Some("base64") => base64::engine::general_purpose::STANDARD
+      .decode(payload.data.as_bytes())
+      .map_err(|err| ApiError::bad_request(&format!("Invalid base64 data: {err}")))?,
+    Some("utf-8") | Some("utf8") | None => payload.data.into_bytes(),
+    Some(_) => return Err(ApiError::bad_request("encoding must be 'utf-8' or 'base64'")),
+  };
+  let mut stdin = job.stdin.lock().await;
+  let Some(pipe) = stdin.as_mut() else {
+    return Err(ApiError::status(StatusCode::CONFLICT, "Job stdin is closed"));
+  };
+  let written = async {
+    pipe.write_all(&bytes).await?;
+    pipe.flush().await
+  }
+  .await;
+  if let Err(err) = written {
+    *stdin = None;
+    return Err(ApiError::status(
+      StatusCode::CONFLICT,
+      &format!("Failed to write to stdin: {err}"),
+    ));
+  }
+  if payload.close {
+    *stdin = None;
+  }
+  Ok(Json(serde_json::json!({
+    "job_id": job.id,
+    "written": bytes.len(),
+    "stdin_open": stdin.is_some(),
+  })))
+}
+
+#[derive(Deserialize)]
+pub(super) struct ProcessSignalPayload {
+  signal: Option<String>,
+  tree: Option<bool>,
+}
+
+/// Sends a signal (default TERM) to the job's process group, or only to
+/// the top process with `tree: false`.
+pub(super) async fn post_process_signal(
+  State(state): State<ApiState>,
+  Path(job_id): Path<String>,
+  Json(payload): Json<ProcessSignalPayload>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let name = payload.signal.unwrap_or_else(|| "TERM".to_string());
+  signal_job(&state, &job_id, &name, payload.tree.unwrap_or(true)).await
+}
+
+/// Kills the job's whole process tree.
+pub(super) async fn post_process_kill(
+  State(state): State<ApiState>,
+  Path(job_id): Path<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+  signal_job(&state, &job_id, "KILL", true).await
+}
+
+async fn signal_job(
+  state: &ApiState,
+  job_id: &str,
+  name: &str,
+  tree: bool,
+) -> ApiResult<Json<serde_json::Value>> {
+  let job = state.jobs.get(job_id).await?;
+  let signal = parse_signal(name)
+    .ok_or_else(|| ApiError::bad_request(&format!("Unsupported signal '{name}'")))?;
+  let Some(pid) = job.pid else {
+    return Err(ApiError::status(StatusCode::CONFLICT, "Job has no process id"));
+  };
+  // Once the top process has exited its pid may be reused, so only the
+  // process group (which keeps the id while any member lives) is targeted.
+  if job.exit().is_some() && !(cfg!(unix) && tree) {
+    return Err(ApiError::status(StatusCode::CONFLICT, "Process has already exited"));
+  }
+  send_signal(pid, signal, tree).map_err(|err| {
+    ApiError::status(StatusCode::CONFLICT, &format!("Failed to signal process: {err}"))
+  })?;
+  if let Ok(mut last_signal) = job.last_signal.lock() {
+    *last_signal = Some(name.to_uppercase());
+  }
+  state.debug_logger.log(
+    "PROCESS",
+    "Job signalled",
+    &[("job_id", job.id.clone()), ("signal", name.to_uppercase())],
+  );
+  Ok(Json(job.to_json()))
+}
+
+#[derive(Deserialize)]
+pub(super) struct ProcessWaitPayload {
+  timeout: Option<f64>,
+}
+
+/// Blocks until the job finishes or `timeout` seconds pass; a timeout is
+/// reported as `running: true`, not as an error.
+pub(super) async fn post_process_wait(
+  State(state): State<ApiState>,
+  Path(job_id): Path<String>,
+  Json(payload): Json<ProcessWaitPayload>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let timeout = payload.timeout.unwrap_or(DEFAULT_WAIT_TIMEOUT_SECONDS);
+  if !(0.0..=MAX_WAIT_TIMEOUT_SECONDS).contains(&timeout) {
+    return Err(ApiError::bad_request("timeout must be between 0 and 600 seconds"));
+  }
+  let job = state.jobs.get(&job_id).await?;
+  job.wait(Duration::from_secs_f64(timeout)).await;
+  Ok(Json(job.to_json()))
+}
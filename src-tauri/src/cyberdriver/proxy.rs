@@ -0,0 +1,379 @@
+use std::time::Duration;
+
+use base64::Engine;
+use tauri_plugin_http::reqwest;
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::TcpStream,
+};
+use tokio_socks::{tcp::Socks5Stream, IntoTargetAddr};
+
+use crate::error::{CyberdriverError, Result};
+
+use super::CyberdriverSettings;
+
+/// Longest CONNECT reply header accepted from an HTTP proxy.
+const MAX_CONNECT_REPLY_BYTES: usize = 16 * 1024;
+/// How long reaching the proxy and its CONNECT or SOCKS handshake may take.
+const PROXY_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ProxyKind {
+  /// An HTTP proxy that tunnels with `CONNECT`.
+  Http,
+  /// SOCKS5 with the target resolved locally.
+  Socks5,
+  /// SOCKS5 with the target resolved by the proxy.
+  Socks5h,
+}
+
+#[derive(Clone, Debug)]
+struct ProxyServer {
+  kind: ProxyKind,
+  url: reqwest::Url,
+  host: String,
+  port: u16,
+  username: Option<String>,
+  password: Option<String>,
+}
+
+/// How outbound connections to the cloud reach it: directly, through the
+/// proxy configured in the settings, or through `HTTPS_PROXY`/`ALL_PROXY`
+/// from the environment. Hosts listed in `NO_PROXY` are always dialed
+/// directly. The local API is never proxied.
+#[derive(Clone, Debug)]
+pub struct ProxySettings {
+  configured: Option<ProxyServer>,
+  use_environment: bool,
+}
+
+impl ProxyServer {
+  fn parse(url: &str, username: Option<String>, password: Option<String>) -> Result<Self> {
+    let invalid = |reason: &str| {
+      CyberdriverError::RuntimeError(format!("Invalid proxy URL '{url}': {reason}"))
+    };
+    let parsed = reqwest::Url::parse(url).map_err(|err| invalid(&err.to_string()))?;
+    let (kind, default_port) = match parsed.scheme() {
+      "http" => (ProxyKind::Http, 8080),
+      "socks5" => (ProxyKind::Socks5, 1080),
+      "socks5h" => (ProxyKind::Socks5h, 1080),
+      _ => return Err(invalid("scheme must be http, socks5 or socks5h")),
+    };
+    let host = parsed
+      .host_str()
+      .ok_or_else(|| invalid("missing host"))?
+      .trim_matches(|c| c == '[' || c == ']')
+      .to_string();
+    let decode = |value: &str| {
+      percent_decode(value).ok_or_else(|| invalid("credentials are not valid UTF-8"))
+    };
+    let username = match username {
+      Some(username) => Some(username),
+      None if !parsed.username().is_empty() => Some(decode(parsed.username())?),
+      None => None,
+    };
+    let password = match password {
+      Some(password) => Some(password),
+      None => parsed.password().map(decode).transpose()?,
+    };
+    Ok(Self {
+      kind,
+      port: parsed.port().unwrap_or(default_port),
+      url: parsed,
+      host,
+      username,
+      password,
+    })
+  }
+
+  /// The first proxy set in the environment for HTTPS traffic. A value
+  /// that does not parse is an error rather than a silent direct dial.
+  fn from_environment() -> Result<Option<Self>> {
+    ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+      .iter()
+      .find_map(|name| {
+        let value = std::env::var(name).ok()?;
+        (!value.trim().is_empty()).then(|| Self::from_variable(name, &value))
+      })
+      .transpose()
+  }
+
+  /// Parses a proxy variable's value; a bare `host:port` means HTTP.
+  fn from_variable(name: &str, value: &str) -> Result<Self> {
+    let value = value.trim();
+    let url = if value.contains("://") {
+      value.to_string()
+    } else {
+      format!("http://{value}")
+    };
+    Self::parse(&url, None, None).map_err(|err| match err {
+      CyberdriverError::RuntimeError(reason) => {
+        CyberdriverError::RuntimeError(format!("{name} is not usable: {reason}"))
+      }
+      err => err,
+    })
+  }
+
+  fn label(&self) -> String {
+    let scheme = match self.kind {
+      ProxyKind::Http => "http",
+      ProxyKind::Socks5 => "socks5",
+      ProxyKind::Socks5h => "socks5h",
+    };
+    format!("{scheme}://{}:{}", self.host, self.port)
+  }
+
+  /// Connects to `host:port` through the proxy, giving up after
+  /// `PROXY_HANDSHAKE_TIMEOUT` so a proxy that stops answering cannot stall
+  /// the tunnel.
+  async fn connect(&self, host: &str, port: u16) -> std::result::Result<TcpStream, String> {
+    let handshake = async {
+      match self.kind {
+        ProxyKind::Http => {
+          let stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .map_err(|err| format!("could not reach proxy: {err}"))?;
+          self.http_connect(stream, host, port).await
+        }
+        ProxyKind::Socks5 => {
+          let target = tokio::net::lookup_host((host, port))
+            .await
+            .ok()
+            .and_then(|mut addresses| addresses.next())
+            .ok_or_else(|| format!("could not resolve {host}"))?;
+          self.socks5_connect(target).await
+        }
+        ProxyKind::Socks5h => self.socks5_connect((host, port)).await,
+      }
+    };
+    tokio::time::timeout(PROXY_HANDSHAKE_TIMEOUT, handshake)
+      .await
+      .unwrap_or_else(|_| Err(format!("no answer within {}s", PROXY_HANDSHAKE_TIMEOUT.as_secs())))
+  }
+
+  async fn socks5_connect<'t>(
+    &self,
+    target: impl IntoTargetAddr<'t>,
+  ) -> std::result::Result<TcpStream, String> {
+    let proxy = (self.host.as_str(), self.port);
+    let stream = match &self.username {
+      Some(username) => {
+        let password = self.password.as_deref().unwrap_or_default();
+        Socks5Stream::connect_with_password(proxy, target, username, password).await
+      }
+      None => Socks5Stream::connect(proxy, target).await,
+    };
+    stream
+      .map(Socks5Stream::into_inner)
+      .map_err(|err| format!("SOCKS5 handshake failed: {err}"))
+  }
+
+  /// Asks an HTTP proxy to open a tunnel to `host:port`.
+  async fn http_connect(
+    &self,
+    mut stream: TcpStream,
+    host: &str,
+    port: u16,
+  ) -> std::result::Result<TcpStream, String> {
+    let target = if host.contains(':') {
+      format!("[{host}]:{port}")
+    } else {
+      format!("{host}:{port}")
+    };
+    let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+    if let Some(username) = &self.username {
+      let credentials = format!("{username}:{}", self.password.as_deref().unwrap_or_default());
+      let token = base64::engine::general_purpose::STANDARD.encode(credentials);
+      request.push_str(&format!("Proxy-Authorization: Basic {token}\r\n"));
+    }
+    request.push_str("\r\n");
+    stream
+      .write_all(request.as_bytes())
+      .await
+      .map_err(|err| format!("CONNECT request failed: {err}"))?;
+
+    // Read the reply one byte at a time so nothing past the header, which
+    // already belongs to the tunnelled connection, is consumed.
+    let mut reply = Vec::new();
+    while !reply.ends_with(b"\r\n\r\n") {
+      if reply.len() >= MAX_CONNECT_REPLY_BYTES {
+        return Err("CONNECT reply is too long".to_string());
+      }
+      let byte = stream
+        .read_u8()
+        .await
+        .map_err(|err| format!("proxy closed the connection during CONNECT: {err}"))?;
+      reply.push(byte);
+    }
+    let reply = String::from_utf8_lossy(&reply);
+    let status_line = reply.lines().next().unwrap_or_default().trim().to_string();
+    let status = status_line
+      .split_whitespace()
+      .nth(1)
+      .and_then(|code| code.parse::<u16>().ok());
+    match status {
+      Some(200..=299) => Ok(stream),
+      Some(407) if self.username.is_some() => {
+        Err("proxy rejected the credentials (407 Proxy Authentication Required)".to_string())
+      }
+      Some(407) => {
+        Err("proxy requires authentication (407 Proxy Authentication Required)".to_string())
+      }
+      _ => Err(format!("proxy refused CONNECT to {target}: {status_line}")),
+    }
+  }
+}
+
+impl ProxySettings {
+  pub fn from_settings(settings: &CyberdriverSettings) -> Result<Self> {
+    let url = settings.proxy_url.trim();
+    let non_empty = |value: &str| (!value.is_empty()).then(|| value.to_string());
+    let configured = if url.is_empty() {
+      None
+    } else {
+      Some(ProxyServer::parse(
+        url,
+        non_empty(settings.proxy_username.trim()),
+        non_empty(&settings.proxy_password),
+      )?)
+    };
+    Ok(Self {
+      configured,
+      use_environment: settings.proxy_use_environment,
+    })
+  }
+
+  fn server_for(&self, host: &str) -> Result<Option<ProxyServer>> {
+    if bypasses_proxy(host) {
+      return Ok(None);
+    }
+    match &self.configured {
+      Some(server) => Ok(Some(server.clone())),
+      None if self.use_environment => ProxyServer::from_environment(),
+      None => Ok(None),
+    }
+  }
+
+  /// Opens a TCP connection to `host:port`, through the proxy if one
+  /// applies. Proxy failures, including an unusable proxy variable, name
+  /// the proxy, so they are recognisable in the tunnel's last error.
+  pub async fn connect(&self, host: &str, port: u16) -> Result<TcpStream> {
+    match self.server_for(host)? {
+      Some(server) => server.connect(host, port).await.map_err(|reason| {
+        CyberdriverError::RuntimeError(format!("Proxy {} error: {reason}", server.label()))
+      }),
+      None => TcpStream::connect((host, port))
+        .await
+        .map_err(|err| CyberdriverError::RuntimeError(format!("Connection failed: {err}"))),
+    }
+  }
+
+  /// An HTTP client for cloud and GitHub requests that uses the same proxy.
+  /// With no configured proxy, reqwest applies the environment's proxy
+  /// variables itself unless that is turned off.
+  pub fn http_client(&self) -> Result<reqwest::Client> {
+    let builder = reqwest::Client::builder();
+    let builder = match &self.configured {
+      Some(server) => {
+        let mut proxy = reqwest::Proxy::all(server.url.clone())?;
+        if let Some(username) = &server.username {
+          proxy = proxy.basic_auth(username, server.password.as_deref().unwrap_or_default());
+        }
+        builder.proxy(proxy.no_proxy(reqwest::NoProxy::from_env()))
+      }
+      None if self.use_environment => builder,
+      None => builder.no_proxy(),
+    };
+    Ok(builder.build()?)
+  }
+}
+
+/// Whether `NO_PROXY` exempts `host`: `*`, an exact match, or a domain
+/// suffix (`example.com` and `.example.com` both cover `api.example.com`).
+fn bypasses_proxy(host: &str) -> bool {
+  ["NO_PROXY", "no_proxy"]
+    .iter()
+    .find_map(|name| std::env::var(name).ok())
+    .is_some_and(|list| no_proxy_covers(&list, host))
+}
+
+fn no_proxy_covers(list: &str, host: &str) -> bool {
+  let host = host.to_lowercase();
+  list
+    .split(',')
+    .map(|entry| entry.trim().to_lowercase())
+    .filter(|entry| !entry.is_empty())
+    .any(|entry| {
+      let domain = entry.trim_start_matches('.');
+      entry == "*" || host == domain || host.ends_with(&format!(".{domain}"))
+    })
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+  let bytes = value.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut index = 0;
+  while index < bytes.len() {
+    let byte = bytes[index];
+    let escaped = (byte == b'%')
+      .then(|| value.get(index + 1..index + 3))
+      .flatten()
+      .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+    match escaped {
+      Some(byte) => {
+        decoded.push(byte);
+        index += 3;
+      }
+      None => {
+        decoded.push(byte);
+        index += 1;
+      }
+    }
+  }
+  String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn no_proxy_matches_hosts_and_domain_suffixes() {
+    let list = "localhost, .internal.example.com,Example.org";
+    assert!(no_proxy_covers(list, "localhost"));
+    assert!(no_proxy_covers(list, "api.internal.example.com"));
+    assert!(no_proxy_covers(list, "internal.example.com"));
+    assert!(no_proxy_covers(list, "example.org"));
+    assert!(no_proxy_covers(list, "API.EXAMPLE.ORG"));
+    assert!(!no_proxy_covers(list, "notexample.org"));
+    assert!(!no_proxy_covers(list, "example.com"));
+    assert!(!no_proxy_covers("", "localhost"));
+    assert!(!no_proxy_covers(" , ", "localhost"));
+    assert!(no_proxy_covers("*", "anything.example.net"));
+  }
+
+  #[test]
+  fn reads_proxy_variables() {
+    let server = ProxyServer::from_variable("HTTPS_PROXY", " proxy.example.com:3128 ").unwrap();
+    assert_eq!(server.kind, ProxyKind::Http);
+    assert_eq!((server.host.as_str(), server.port), ("proxy.example.com", 3128));
+    let server = ProxyServer::from_variable("ALL_PROXY", "socks5h://user:p%40ss@[::1]").unwrap();
+    assert_eq!(server.kind, ProxyKind::Socks5h);
+    assert_eq!((server.host.as_str(), server.port), ("::1", 1080));
+    assert_eq!(server.password.as_deref(), Some("p@ss"));
+
+    let err = ProxyServer::from_variable("HTTPS_PROXY", "ftp://proxy.example.com").unwrap_err();
+    assert!(err.to_string().contains("HTTPS_PROXY"), "{err}");
+    assert!(ProxyServer::from_variable("HTTPS_PROXY", "http://").is_err());
+  }
+
+  #[test]
+  fn percent_decodes_credentials() {
+    assert_eq!(percent_decode("p%40ss%3Aword").as_deref(), Some("p@ss:word"));
+    assert_eq!(percent_decode("plain").as_deref(), Some("plain"));
+    assert_eq!(percent_decode("%E2%9C%93").as_deref(), Some("\u{2713}"));
+    assert_eq!(percent_decode("100%").as_deref(), Some("100%"));
+    assert_eq!(percent_decode("%zz%4").as_deref(), Some("%zz%4"));
+    assert_eq!(percent_decode("%ff"), None);
+  }
+}
@@ -0,0 +1,315 @@
+use std::{collections::HashMap, time::Duration};
+
+use axum::{extract::State, Json};
+use encoding_rs::Encoding;
+use serde::Deserialize;
+
+use crate::error::CyberdriverError;
+
+use super::{
+  api::{ApiError, ApiResult, ApiState},
+  process_limits::{ResourceLimits, RunAs},
+  processes::{command_or_script, Interpreter, JobInput, JobSpec, KILL_GRACE},
+  shell_policy,
+};
+
+pub(super) async fn post_powershell_simple() -> ApiResult<Json<serde_json::Value>> {
+  let output = if cfg!(windows) {
+    std::process::Command::new("powershell")
+      .args(["-NoProfile", "-Command", "Write-Output 'Hello World'"])
+      .output()
+  } else {
+    std::process::Command::new("/bin/sh")
+      .args(["-c", "printf 'Hello World'"])
+      .output()
+  }
+  .map_err(|err| ApiError::internal(&err.to_string()))?;
+  Ok(Json(serde_json::json!({
+    "returncode": output.status.code().unwrap_or(0),
+    "stdout": truncate_output(String::from_utf8_lossy(&output.stdout).to_string()),
+    "stderr": truncate_output(String::from_utf8_lossy(&output.stderr).to_string()),
+  })))
+}
+
+pub(super) async fn post_powershell_test() -> ApiResult<Json<serde_json::Value>> {
+  let output = if cfg!(windows) {
+    std::process::Command::new("powershell")
+      .args(["-NoLogo", "-NoProfile", "-NonInteractive", "-ExecutionPolicy", "Bypass", "-Command"])
+      .arg("Write-Output \"Hello from PowerShell\"")
+      .output()
+  } else {
+    std::process::Command::new("/bin/sh")
+      .args(["-c", "printf 'Hello from shell'"])
+      .output()
+  }
+  .map_err(|err| ApiError::internal(&err.to_string()))?;
+  Ok(Json(serde_json::json!({
+    "returncode": output.status.code().unwrap_or(0),
+    "stdout": truncate_output(String::from_utf8_lossy(&output.stdout).to_string()),
+    "stderr": truncate_output(String::from_utf8_lossy(&output.stderr).to_string()),
+  })))
+}
+
+#[derive(Deserialize)]
+pub(super) struct PowerShellExecPayload {
+  #[serde(default)]
+  command: String,
+  script: Option<String>,
+  shell: Option<String>,
+  #[serde(default)]
+  env: HashMap<String, String>,
+  stdin: Option<String>,
+  encoding: Option<String>,
+  same_session: Option<bool>,
+  working_directory: Option<String>,
+  session_id: Option<String>,
+  timeout: Option<f64>,
+  #[serde(default)]
+  limits: ResourceLimits,
+  #[serde(default)]
+  kill_on_timeout: bool,
+}
+
+/// Runs a command, or a multi-line `script` body that is passed to the
+/// interpreter as a file so it needs no quoting. `shell` picks sh, bash,
+/// zsh, powershell, pwsh or python; `env` adds variables, `stdin` is fed to
+/// the process and `encoding` names the charset to decode output with
+/// (UTF-8 by default). With a `session_id` naming a live session, or with
+/// `same_session: true`, the command runs in that persistent shell (created
+/// on demand) so `cd`, variables and functions carry over between calls;
+/// otherwise it runs in a fresh one-shot shell. One-shot commands accept
+/// `limits` (`cpu_seconds`, `memory_mb`, `open_files`, `wall_seconds`), and
+/// with `kill_on_timeout: true` their process group is killed at the
+/// timeout instead of being left running as a job. Commands run as the
+/// configured shell user, if any.
+pub(super) async fn post_powershell_exec(
+  State(state): State<ApiState>,
+  Json(payload): Json<PowerShellExecPayload>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let (command, script) = command_or_script(payload.command, payload.script)?;
+  let encoding = match payload.encoding.as_deref() {
+    Some(label) => Some(
+      Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| ApiError::bad_request(&format!("Unknown encoding '{label}'")))?,
+    ),
+    None => None,
+  };
+  let timeout = payload.timeout.unwrap_or(30.0);
+  let working_directory = payload.working_directory.clone();
+  payload.limits.validate()?;
+  shell_policy::authorize(
+    &state,
+    "/computer/shell/powershell/exec",
+    &command,
+    payload.shell.as_deref(),
+  )
+  .await?;
+  let run_as = RunAs::configured(&state).await?;
+
+  let existing = match payload.session_id.as_deref() {
+    Some(session_id) => state.shell_sessions.exists(session_id).await,
+    None => false,
+  };
+  let mut job_id = None;
+  let (session_id, result) = if existing || payload.same_session == Some(true) {
+    if !payload.env.is_empty() || payload.stdin.is_some() || !payload.limits.is_empty() {
+      return Err(ApiError::bad_request(
+        "'env', 'stdin' and 'limits' are not supported in persistent sessions",
+      ));
+    }
+    let session_id = state
+      .shell_sessions
+      .create(payload.session_id, payload.shell.as_deref(), None, run_as.as_ref())
+      .await
+      .map_err(|err| match err {
+        CyberdriverError::InvalidPayload(message) => ApiError::bad_request(&message),
+        err => ApiError::internal(&err.to_string()),
+      })?;
+    let result = state
+      .shell_sessions
+      .exec(&session_id, &command, working_directory.as_deref(), timeout)
+      .await;
+    (session_id, result)
+  } else {
+    let session_id = payload.session_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let spec = JobSpec {
+      command,
+      script,
+      interpreter: Interpreter::from_name(payload.shell.as_deref())?,
+      working_directory,
+      env: payload.env,
+      stdin: match payload.stdin {
+        Some(input) => JobInput::Data(input.into_bytes()),
+        None => JobInput::Null,
+      },
+      limits: payload.limits,
+      run_as,
+    };
+    let (result, background_job) =
+      execute_shell_command(&state, spec, timeout, payload.kill_on_timeout).await?;
+    job_id = background_job;
+    (session_id, Ok(result))
+  };
+
+  match result {
+    Ok(result) => Ok(Json(serde_json::json!({
+      "stdout": truncate_output(decode_output(&result.stdout, encoding)),
+      "stderr": truncate_output(decode_output(&result.stderr, encoding)),
+      "exit_code": result.exit_code,
+      "session_id": session_id,
+      "timeout_reached": result.timeout_reached,
+      "job_id": job_id,
+    }))),
+    Err(err) => Err(ApiError::internal(&err.to_string())),
+  }
+}
+
+#[derive(Deserialize)]
+pub(super) struct PowerShellSessionPayload {
+  action: String,
+  session_id: Option<String>,
+  shell: Option<String>,
+  working_directory: Option<String>,
+}
+
+/// Creates or destroys a persistent shell session. `create` accepts an
+/// optional `shell` (sh, bash, zsh, powershell, pwsh) and starting
+/// `working_directory`; the shell runs as the configured shell user, if any.
+pub(super) async fn post_powershell_session(
+  State(state): State<ApiState>,
+  Json(payload): Json<PowerShellSessionPayload>,
+) -> ApiResult<Json<serde_json::Value>> {
+  match payload.action.as_str() {
+    "create" => {
+      if let Some(session_id) = payload.session_id.as_deref() {
+        if state.shell_sessions.exists(session_id).await {
+          return Err(ApiError::bad_request("Session already exists"));
+        }
+      }
+      let run_as = RunAs::configured(&state).await?;
+      let session_id = state
+        .shell_sessions
+        .create(
+          payload.session_id,
+          payload.shell.as_deref(),
+          payload.working_directory.as_deref(),
+          run_as.as_ref(),
+        )
+        .await
+        .map_err(|err| match err {
+          CyberdriverError::InvalidPayload(message) => ApiError::bad_request(&message),
+          err => ApiError::internal(&err.to_string()),
+        })?;
+      Ok(Json(serde_json::json!({
+        "session_id": session_id,
+        "message": "Session created",
+      })))
+    }
+    "destroy" => {
+      let Some(session_id) = payload.session_id.as_deref() else {
+        return Err(ApiError::bad_request("Missing 'session_id' field"));
+      };
+      let destroyed = state.shell_sessions.destroy(session_id).await;
+      Ok(Json(serde_json::json!({
+        "session_id": session_id,
+        "destroyed": destroyed,
+        "message": if destroyed { "Session destroyed" } else { "Session not found" },
+      })))
+    }
+    _ => Err(ApiError::bad_request("Invalid action. Must be 'create' or 'destroy'")),
+  }
+}
+
+pub(super) async fn get_shell_sessions(
+  State(state): State<ApiState>,
+) -> ApiResult<Json<serde_json::Value>> {
+  Ok(Json(serde_json::json!({ "sessions": state.shell_sessions.list().await })))
+}
+
+#[derive(Debug)]
+pub(super) struct CommandResult {
+  pub(super) stdout: Vec<u8>,
+  pub(super) stderr: Vec<u8>,
+  pub(super) exit_code: i32,
+  pub(super) timeout_reached: bool,
+}
+
+/// Runs `spec` once as a background job and waits up to `timeout` for it.
+/// A command that is still running afterwards keeps running as that job,
+/// whose id is returned so its output can still be collected, unless
+/// `kill_on_timeout` is set, in which case its process group is killed.
+async fn execute_shell_command(
+  state: &ApiState,
+  spec: JobSpec,
+  timeout: f64,
+  kill_on_timeout: bool,
+) -> ApiResult<(CommandResult, Option<String>)> {
+  let job = state.jobs.start(spec).await?;
+  let mut exit = job.wait(Duration::from_secs_f64(timeout.max(1.0))).await;
+  if exit.is_none() && kill_on_timeout {
+    job.kill_tree("KILL (timeout)");
+    exit = job.wait(KILL_GRACE).await;
+    state.jobs.remove(&job.id).await;
+    let stdout = job.stdout.read(0, usize::MAX);
+    let mut stderr = job.stderr.read(0, usize::MAX).data;
+    stderr.extend_from_slice(
+      format!("\nCommand timeout reached after {timeout} seconds. Process tree was killed.")
+        .as_bytes(),
+    );
+    return Ok((
+      CommandResult {
+        stdout: stdout.data,
+        stderr,
+        exit_code: exit.and_then(|exit| exit.code).unwrap_or(-1),
+        timeout_reached: true,
+      },
+      None,
+    ));
+  }
+  match exit {
+    Some(exit) => {
+      state.jobs.remove(&job.id).await;
+      let stdout = job.stdout.read(0, usize::MAX);
+      let stderr = job.stderr.read(0, usize::MAX);
+      Ok((
+        CommandResult {
+          stdout: stdout.data,
+          stderr: stderr.data,
+          exit_code: exit.code.unwrap_or(-1),
+          timeout_reached: false,
+        },
+        None,
+      ))
+    }
+    None => Ok((
+      CommandResult {
+        stdout: Vec::new(),
+        stderr: format!(
+          "Command timeout reached after {timeout} seconds. Process continues in background as job {}.",
+          job.id
+        )
+        .into_bytes(),
+        exit_code: 0,
+        timeout_reached: true,
+      },
+      Some(job.id.clone()),
+    )),
+  }
+}
+
+fn decode_output(bytes: &[u8], encoding: Option<&'static Encoding>) -> String {
+  match encoding {
+    Some(encoding) => encoding.decode(bytes).0.into_owned(),
+    None => String::from_utf8_lossy(bytes).to_string(),
+  }
+}
+
+fn truncate_output(output: String) -> String {
+  let max = 15_000;
+  if output.len() <= max {
+    return output;
+  }
+  let head = &output[..max / 2];
+  let tail = &output[output.len() - max / 2..];
+  format!("{head}\n... (truncated) ...\n{tail}")
+}
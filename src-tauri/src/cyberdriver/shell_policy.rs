@@ -0,0 +1,450 @@
+use std::{
+  path::Path,
+  sync::{Mutex, PoisonError},
+  time::Duration,
+};
+
+use axum::http::StatusCode;
+use regex::Regex;
+use tauri::{AppHandle, Manager, WebviewUrl, WebviewWindowBuilder, WindowEvent};
+use tokio::sync::oneshot;
+
+use super::{
+  api::{ApiError, ApiResult, ApiState},
+  CyberdriverSettings,
+};
+
+/// How long a command waits for the local user to answer the approval
+/// dialog. The tunnel adds it to the request timeout of gated endpoints.
+pub(super) const APPROVAL_TIMEOUT: Duration = Duration::from_secs(25);
+const MAX_DIALOG_COMMAND_CHARS: usize = 600;
+const MAX_AUDIT_COMMAND_CHARS: usize = 2000;
+
+const APPROVAL_WINDOW: &str = "shell-approval";
+/// Characters that leave a program name to globbing or expansion, so it
+/// cannot be matched against executable rules. Commas and braces come from
+/// brace expansion: `{rm,-rf,x}` runs `rm`.
+const UNRESOLVED_CHARS: &[char] = &['*', '?', '[', ']', '$', '%', ',', '{', '}'];
+
+/// The approval the local user is being asked for. Only one is shown at a
+/// time; commands needing approval meanwhile are denied straight away.
+static PENDING_APPROVAL: Mutex<Option<PendingApproval>> = Mutex::new(None);
+
+struct PendingApproval {
+  id: String,
+  answer: oneshot::Sender<bool>,
+}
+
+/// Holds the approval slot for one command. Dropping it, also when the
+/// request is abandoned mid-wait, frees the slot and closes the window.
+struct ApprovalSlot<'a> {
+  id: String,
+  app_handle: &'a AppHandle,
+}
+
+impl<'a> ApprovalSlot<'a> {
+  /// Takes the slot, or returns `None` while another command holds it.
+  fn claim(app_handle: &'a AppHandle, answer: oneshot::Sender<bool>) -> Option<Self> {
+    let mut pending = PENDING_APPROVAL.lock().unwrap_or_else(PoisonError::into_inner);
+    if pending.is_some() {
+      return None;
+    }
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    *pending = Some(PendingApproval { id: id.clone(), answer });
+    Some(Self { id, app_handle })
+  }
+}
+
+impl Drop for ApprovalSlot<'_> {
+  fn drop(&mut self) {
+    {
+      let mut pending = PENDING_APPROVAL.lock().unwrap_or_else(PoisonError::into_inner);
+      if pending.as_ref().is_some_and(|pending| pending.id == self.id) {
+        *pending = None;
+      }
+    }
+    if let Some(window) = self.app_handle.get_webview_window(APPROVAL_WINDOW) {
+      let _ = window.destroy();
+    }
+  }
+}
+
+/// Shell keywords skipped when looking for the program a segment runs.
+const SHELL_KEYWORDS: &[&str] = &[
+  "if", "then", "else", "elif", "fi", "do", "done", "while", "until", "case", "esac", "function",
+  "time", "!", "exec", "command", "builtin",
+];
+
+/// Decides which remote shell commands may run, from the settings.
+///
+/// Rules are executable names (`git`) or regexes written as `/pattern/`,
+/// which are matched against the whole command. A denied rule always wins.
+/// A command is allowed when an allowed regex matches it or every program
+/// it runs is an allowed executable; anything else gets the unlisted
+/// action. Executable detection is best effort: quotes and backslashes are
+/// removed from program names, and a name that still depends on globbing or
+/// expansion (`/bin/r?`, `$cmd`) is never allowed by name and needs the
+/// local user's approval. Allowing an interpreter such as `bash` or
+/// `python` allows whatever it is later fed on stdin.
+pub(super) struct ShellPolicy {
+  allowed: Vec<CommandRule>,
+  denied: Vec<CommandRule>,
+  unlisted: UnlistedAction,
+}
+
+enum CommandRule {
+  Executable(String),
+  Pattern(Regex),
+  /// A regex that failed to compile. It matches everything when denying and
+  /// nothing when allowing, so a typo never opens the policy up.
+  Invalid(String),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum UnlistedAction {
+  Allow,
+  Deny,
+  Ask,
+}
+
+enum Verdict {
+  Allowed(String),
+  Denied(String),
+  Ask,
+}
+
+impl CommandRule {
+  fn parse(rule: &str) -> Option<Self> {
+    let rule = rule.trim();
+    if rule.is_empty() {
+      return None;
+    }
+    let pattern = rule
+      .strip_prefix('/')
+      .and_then(|rule| rule.strip_suffix('/'))
+      .filter(|pattern| !pattern.is_empty());
+    Some(match pattern {
+      Some(pattern) => match Regex::new(pattern) {
+        Ok(regex) => Self::Pattern(regex),
+        Err(_) => Self::Invalid(rule.to_string()),
+      },
+      None => Self::Executable(normalize_executable(rule)),
+    })
+  }
+
+  fn label(&self) -> String {
+    match self {
+      Self::Executable(name) => name.clone(),
+      Self::Pattern(regex) => format!("/{}/", regex.as_str()),
+      Self::Invalid(rule) => format!("{rule} (invalid regex)"),
+    }
+  }
+}
+
+impl ShellPolicy {
+  pub(super) fn from_settings(settings: &CyberdriverSettings) -> Self {
+    let parse = |rules: &[String]| {
+      rules
+        .iter()
+        .filter_map(|rule| CommandRule::parse(rule))
+        .collect()
+    };
+    Self {
+      allowed: parse(&settings.shell_allowed_commands),
+      denied: parse(&settings.shell_denied_commands),
+      unlisted: match settings.shell_unlisted_action.as_str() {
+        "deny" => UnlistedAction::Deny,
+        "ask" => UnlistedAction::Ask,
+        _ => UnlistedAction::Allow,
+      },
+    }
+  }
+
+  pub(super) async fn load(state: &ApiState) -> Self {
+    Self::from_settings(&*state.settings.lock().await)
+  }
+
+  fn evaluate(&self, command: &str, executables: &[String]) -> Verdict {
+    for rule in &self.denied {
+      let denied = match rule {
+        CommandRule::Executable(name) => executables.contains(name),
+        CommandRule::Pattern(regex) => regex.is_match(command),
+        CommandRule::Invalid(_) => true,
+      };
+      if denied {
+        return Verdict::Denied(format!("matches denied rule {}", rule.label()));
+      }
+    }
+    if executables.iter().any(|name| name.contains(UNRESOLVED_CHARS)) {
+      return match self.unlisted {
+        UnlistedAction::Deny => {
+          Verdict::Denied("program name depends on globbing or expansion".to_string())
+        }
+        UnlistedAction::Allow | UnlistedAction::Ask => Verdict::Ask,
+      };
+    }
+    if let Some(rule) = self.allowed.iter().find(|rule| match rule {
+      CommandRule::Pattern(regex) => regex.is_match(command),
+      _ => false,
+    }) {
+      return Verdict::Allowed(format!("matches allowed rule {}", rule.label()));
+    }
+    let listed = |name: &String| {
+      self
+        .allowed
+        .iter()
+        .any(|rule| matches!(rule, CommandRule::Executable(allowed) if allowed == name))
+    };
+    if !executables.is_empty() && executables.iter().all(listed) {
+      return Verdict::Allowed("all executables are allowed".to_string());
+    }
+    match self.unlisted {
+      UnlistedAction::Allow => Verdict::Allowed("unlisted commands are allowed".to_string()),
+      UnlistedAction::Deny => Verdict::Denied("command is not on the allow list".to_string()),
+      UnlistedAction::Ask => Verdict::Ask,
+    }
+  }
+}
+
+/// Checks a command against the shell policy before it runs, asking the
+/// local user when the policy says so. Every decision is written to the
+/// audit log; a refusal becomes a 403.
+pub(super) async fn authorize(
+  state: &ApiState,
+  endpoint: &str,
+  command: &str,
+  shell: Option<&str>,
+) -> ApiResult<()> {
+  let policy = ShellPolicy::load(state).await;
+  let executables = match shell.map(str::to_lowercase).as_deref() {
+    Some("python") | Some("python3") => vec!["python".to_string()],
+    _ => command_executables(command),
+  };
+  let (allowed, reason) = match policy.evaluate(command, &executables) {
+    Verdict::Allowed(reason) => (true, reason),
+    Verdict::Denied(reason) => (false, reason),
+    Verdict::Ask => ask_local_user(state, command).await,
+  };
+  state.debug_logger.audit(
+    "SHELL_POLICY",
+    if allowed { "Command allowed" } else { "Command denied" },
+    &[
+      ("endpoint", endpoint.to_string()),
+      ("reason", format!("{reason:?}")),
+      ("executables", executables.join(",")),
+      ("command", format!("{:?}", truncate_chars(command, MAX_AUDIT_COMMAND_CHARS))),
+    ],
+  );
+  if allowed {
+    Ok(())
+  } else {
+    Err(ApiError::status(
+      StatusCode::FORBIDDEN,
+      &format!("Command denied by shell policy: {reason}"),
+    ))
+  }
+}
+
+/// Shows the approval window and waits for the local user's answer. The
+/// window is closed once the command is decided, also on timeout or when the
+/// request goes away; closing it counts as a refusal.
+async fn ask_local_user(state: &ApiState, command: &str) -> (bool, String) {
+  let (sender, receiver) = oneshot::channel();
+  let Some(slot) = ApprovalSlot::claim(&state.app_handle, sender) else {
+    return (false, "another command is already waiting for approval".to_string());
+  };
+  let id = slot.id.clone();
+  let seconds = APPROVAL_TIMEOUT.as_secs().to_string();
+  let shown = truncate_chars(command, MAX_DIALOG_COMMAND_CHARS);
+  let query = serde_urlencoded::to_string([
+    ("id", id.as_str()),
+    ("command", shown.as_str()),
+    ("seconds", seconds.as_str()),
+  ])
+  .unwrap_or_default();
+  let window = WebviewWindowBuilder::new(
+    &state.app_handle,
+    APPROVAL_WINDOW,
+    WebviewUrl::App(format!("windows/shell-approval.html?{query}").into()),
+  )
+  .title("Cyberdriver: allow remote command?")
+  .inner_size(480.0, 320.0)
+  .resizable(false)
+  .always_on_top(true)
+  .focused(true)
+  .center()
+  .build();
+  let answer = match window {
+    Ok(window) => {
+      let closed_id = id.clone();
+      window.on_window_event(move |event| {
+        if matches!(event, WindowEvent::CloseRequested { .. } | WindowEvent::Destroyed) {
+          answer_approval(&closed_id, false);
+        }
+      });
+      tokio::time::timeout(APPROVAL_TIMEOUT, receiver).await
+    }
+    Err(err) => {
+      state.debug_logger.error("SHELL_POLICY", &format!("Failed to show approval window: {err}"));
+      Ok(Ok(false))
+    }
+  };
+  drop(slot);
+  match answer {
+    Ok(Ok(true)) => (true, "approved by the local user".to_string()),
+    Ok(_) => (false, "not approved by the local user".to_string()),
+    Err(_) => (
+      false,
+      format!("no answer from the local user within {seconds} seconds"),
+    ),
+  }
+}
+
+/// Hands the local user's answer to the command it was asked for; answers
+/// for a command that has already been decided are ignored.
+pub(super) fn answer_approval(id: &str, approved: bool) {
+  let mut pending = PENDING_APPROVAL.lock().unwrap_or_else(PoisonError::into_inner);
+  if pending.as_ref().is_some_and(|pending| pending.id == id) {
+    if let Some(pending) = pending.take() {
+      let _ = pending.answer.send(approved);
+    }
+  }
+}
+
+/// Best-effort list of the programs a command line runs: the first word of
+/// every line, pipeline stage, command list entry, subshell and command
+/// substitution, skipping variable assignments and shell keywords.
+fn command_executables(command: &str) -> Vec<String> {
+  let mut names: Vec<String> = Vec::new();
+  let separators = |c: char| matches!(c, '\n' | ';' | '&' | '|' | '(' | ')' | '`' | '{' | '}');
+  for segment in command.split(separators) {
+    // Pieces of redirections such as `&>file` or `2>&1`.
+    let trimmed = segment.trim_start();
+    if trimmed.starts_with(['<', '>']) || trimmed.split_whitespace().next().is_some_and(is_number) {
+      continue;
+    }
+    let word = segment
+      .split_whitespace()
+      .find(|word| !is_assignment(word) && !SHELL_KEYWORDS.contains(word));
+    let Some(word) = word else {
+      continue;
+    };
+    if word.starts_with('#') {
+      continue;
+    }
+    let name = normalize_executable(&unquote(word.trim_start_matches('@')));
+    if !name.is_empty() && !names.contains(&name) {
+      names.push(name);
+    }
+  }
+  names
+}
+
+/// Removes shell quoting from a word, so `\rm`, `r''m` and `"r"m` all read
+/// `rm`. Quote characters are dropped; a backslash keeps the character after
+/// it, except on Windows where it separates paths and cmd escapes with `^`.
+fn unquote(word: &str) -> String {
+  let mut unquoted = String::with_capacity(word.len());
+  let mut chars = word.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '\'' | '"' => {}
+      '^' if cfg!(windows) => unquoted.extend(chars.next()),
+      '\\' if !cfg!(windows) => unquoted.extend(chars.next()),
+      c => unquoted.push(c),
+    }
+  }
+  unquoted
+}
+
+fn is_number(word: &str) -> bool {
+  word.chars().all(|c| c.is_ascii_digit())
+}
+
+fn is_assignment(word: &str) -> bool {
+  word
+    .split_once('=')
+    .is_some_and(|(name, _)| {
+      !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+    })
+}
+
+/// Reduces a program path to its lowercase file name without a Windows
+/// executable extension, so `/usr/bin/Git` and `git.exe` both become `git`.
+fn normalize_executable(word: &str) -> String {
+  let name = Path::new(word)
+    .file_name()
+    .map(|name| name.to_string_lossy().to_lowercase())
+    .unwrap_or_default();
+  for extension in [".exe", ".cmd", ".bat", ".com", ".ps1"] {
+    if let Some(stem) = name.strip_suffix(extension) {
+      return stem.to_string();
+    }
+  }
+  name
+}
+
+fn truncate_chars(text: &str, max: usize) -> String {
+  match text.char_indices().nth(max) {
+    Some((end, _)) => format!("{}…", &text[..end]),
+    None => text.to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn names(command: &str) -> Vec<String> {
+    command_executables(command)
+  }
+
+  #[test]
+  fn finds_every_program_a_command_line_runs() {
+    assert_eq!(names("ls -la"), ["ls"]);
+    assert_eq!(
+      names("FOO=1 BAR=2 /usr/bin/Git status | grep x && echo done; rm -rf /tmp/x"),
+      ["git", "grep", "echo", "rm"],
+    );
+    assert_eq!(names("echo $(whoami) `id`"), ["echo", "whoami", "id"]);
+    assert_eq!(names("(cd /tmp && make) &"), ["cd", "make"]);
+    assert_eq!(names("python3 a.py\npython3 b.py"), ["python3"]);
+  }
+
+  #[test]
+  fn skips_keywords_redirections_and_comments() {
+    assert_eq!(names("if true; then curl http://x; fi"), ["true", "curl"]);
+    assert_eq!(names("ls 2>&1 | tee out.log"), ["ls", "tee"]);
+    assert_eq!(names("make &>build.log"), ["make"]);
+    assert_eq!(names("# rm -rf /"), Vec::<String>::new());
+  }
+
+  #[test]
+  fn normalizes_quoted_and_windows_names() {
+    assert_eq!(names("r''m -rf x"), ["rm"]);
+    assert_eq!(names("\"r\"m -rf x"), ["rm"]);
+    assert_eq!(names("@Git.EXE status"), ["git"]);
+    assert_eq!(names("deploy.ps1 -Force"), ["deploy"]);
+  }
+
+  #[test]
+  fn leaves_brace_expanded_names_unresolved() {
+    let policy = ShellPolicy {
+      denied: vec![CommandRule::Executable("rm".to_string())],
+      allowed: Vec::new(),
+      unlisted: UnlistedAction::Deny,
+    };
+    for command in ["{rm,-rf,x}", "r{m,m} -rf x"] {
+      let executables = names(command);
+      assert!(executables.iter().any(|name| name.contains(UNRESOLVED_CHARS)), "{command}");
+      assert!(matches!(policy.evaluate(command, &executables), Verdict::Denied(_)), "{command}");
+    }
+  }
+
+  #[cfg(not(windows))]
+  #[test]
+  fn treats_backslash_as_an_escape() {
+    assert_eq!(names("\\rm -rf x"), ["rm"]);
+    assert_eq!(names("r\\m -rf x"), ["rm"]);
+  }
+}
@@ -0,0 +1,539 @@
+use std::{
+  collections::HashMap,
+  process::Stdio,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Weak,
+  },
+  time::{Duration, Instant},
+};
+
+use base64::Engine;
+use tokio::{
+  io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+  process::{Child, ChildStdin},
+  sync::{Mutex, Notify},
+};
+
+use crate::error::{CyberdriverError, Result};
+
+use super::{
+  process_limits::{self, ResourceLimits, RunAs},
+  processes::kill_process_tree,
+  shell::CommandResult,
+};
+
+/// Sessions unused for this long are killed by the sweeper.
+const SESSION_IDLE_TTL: Duration = Duration::from_secs(30 * 60);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_SESSIONS: usize = 32;
+/// Per-pipe output retained for the running command; older bytes are
+/// discarded first so the exit marker at the end is still found.
+const MAX_SESSION_OUTPUT_BYTES: usize = 16 * 1024 * 1024;
+
+/// Long-lived shell processes keyed by session id. Each exec writes the
+/// command to the shell's stdin followed by a marker carrying the exit code,
+/// so working directory, variables and other shell state carry over.
+pub struct ShellSessions {
+  sessions: Mutex<HashMap<String, Arc<ShellSession>>>,
+}
+
+struct ShellSession {
+  shell: SessionShell,
+  pid: Option<u32>,
+  child: Mutex<Child>,
+  stdin: Mutex<ChildStdin>,
+  stdout: Arc<OutputBuffer>,
+  stderr: Arc<OutputBuffer>,
+  created: Instant,
+  last_used: std::sync::Mutex<Instant>,
+  busy: AtomicBool,
+  /// Set when a command was abandoned mid-run; the shell's state is unknown
+  /// and the session is removed instead of being reused.
+  poisoned: AtomicBool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SessionShell {
+  Posix(&'static str),
+  PowerShell(&'static str),
+}
+
+impl SessionShell {
+  fn from_name(name: Option<&str>) -> Result<Self> {
+    let shell = match name.map(|name| name.to_lowercase()) {
+      None if cfg!(windows) => Self::PowerShell("powershell"),
+      None if std::path::Path::new("/bin/bash").exists() => Self::Posix("/bin/bash"),
+      None => Self::Posix("/bin/sh"),
+      Some(name) => match name.as_str() {
+        "sh" => Self::Posix("sh"),
+        "bash" => Self::Posix("bash"),
+        "zsh" => Self::Posix("zsh"),
+        "powershell" => Self::PowerShell("powershell"),
+        "pwsh" => Self::PowerShell("pwsh"),
+        _ => {
+          return Err(CyberdriverError::InvalidPayload(format!(
+            "Unsupported session shell '{name}'"
+          )));
+        }
+      },
+    };
+    Ok(shell)
+  }
+
+  fn program(self) -> &'static str {
+    match self {
+      Self::Posix(program) | Self::PowerShell(program) => program,
+    }
+  }
+
+  fn command(self) -> tokio::process::Command {
+    let mut cmd = tokio::process::Command::new(self.program());
+    if let Self::PowerShell(_) = self {
+      cmd.args([
+        "-NoLogo",
+        "-NoProfile",
+        "-NonInteractive",
+        "-ExecutionPolicy",
+        "Bypass",
+        "-Command",
+        "-",
+      ]);
+    }
+    cmd
+  }
+
+  /// Wraps `command` so the shell runs it with stdin detached and then
+  /// prints `marker` with the exit status on stdout and stderr.
+  fn script(self, command: &str, working_dir: Option<&str>, marker: &str) -> String {
+    match self {
+      Self::Posix(_) => {
+        let cd = working_dir
+          .map(|dir| format!("cd {} && ", posix_quote(dir)))
+          .unwrap_or_default();
+        format!(
+          "{cd}eval {} </dev/null; __cd_status=$?; printf '\\n{marker}%s__\\n' \"$__cd_status\"; printf '\\n{marker}__\\n' >&2\n",
+          posix_quote(command)
+        )
+      }
+      Self::PowerShell(_) => {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(command.as_bytes());
+        let cd = working_dir
+          .map(|dir| format!("Set-Location -LiteralPath '{}'; ", dir.replace('\'', "''")))
+          .unwrap_or_default();
+        format!(
+          "{cd}$global:LASTEXITCODE = 0; $__cd_ok = $true; try {{ Invoke-Expression ([Text.Encoding]::UTF8.GetString([Convert]::FromBase64String('{encoded}'))) | Out-String -Stream | Write-Output; $__cd_ok = $? }} catch {{ [Console]::Error.WriteLine($_); $__cd_ok = $false }}; $__cd_code = if ($LASTEXITCODE) {{ $LASTEXITCODE }} elseif ($__cd_ok) {{ 0 }} else {{ 1 }}; [Console]::Out.WriteLine(\"`n{marker}$($__cd_code)__\"); [Console]::Error.WriteLine(\"`n{marker}__\")\n\n"
+        )
+      }
+    }
+  }
+}
+
+fn posix_quote(value: &str) -> String {
+  format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Output collected from one of a session's pipes.
+struct OutputBuffer {
+  data: std::sync::Mutex<PendingOutput>,
+  notify: Notify,
+  closed: AtomicBool,
+}
+
+/// Unread output. `start` counts the bytes discarded since the last command
+/// finished, so scan positions stay valid after old output is dropped.
+#[derive(Default)]
+struct PendingOutput {
+  bytes: Vec<u8>,
+  start: usize,
+}
+
+impl OutputBuffer {
+  fn new() -> Arc<Self> {
+    Arc::new(Self {
+      data: std::sync::Mutex::new(PendingOutput::default()),
+      notify: Notify::new(),
+      closed: AtomicBool::new(false),
+    })
+  }
+
+  fn spawn_reader<R: AsyncRead + Unpin + Send + 'static>(self: &Arc<Self>, mut reader: R) {
+    let buffer = self.clone();
+    tauri::async_runtime::spawn(async move {
+      let mut chunk = vec![0u8; 8192];
+      loop {
+        match reader.read(&mut chunk).await {
+          Ok(0) | Err(_) => break,
+          Ok(read) => {
+            if let Ok(mut data) = buffer.data.lock() {
+              data.bytes.extend_from_slice(&chunk[..read]);
+              let excess = data.bytes.len().saturating_sub(MAX_SESSION_OUTPUT_BYTES);
+              if excess > 0 {
+                data.bytes.drain(..excess);
+                data.start += excess;
+              }
+            }
+            buffer.notify.notify_waiters();
+          }
+        }
+      }
+      buffer.closed.store(true, Ordering::SeqCst);
+      buffer.notify.notify_waiters();
+    });
+  }
+
+  /// Waits until `find` locates the end of a command's output in the buffer,
+  /// then removes and returns everything up to it. `find` is given where to
+  /// resume scanning and, when nothing is found, returns where to resume
+  /// next time, so each byte is scanned about once.
+  async fn take_until<F>(
+    &self,
+    deadline: tokio::time::Instant,
+    find: F,
+  ) -> Option<(Vec<u8>, String)>
+  where
+    F: Fn(&[u8], usize) -> std::result::Result<(usize, usize, String), usize>,
+  {
+    // Absolute position in the command's output, including dropped bytes.
+    let mut checked = 0;
+    loop {
+      let notified = self.notify.notified();
+      if let Ok(mut data) = self.data.lock() {
+        let from = checked.saturating_sub(data.start);
+        match find(&data.bytes, from) {
+          Ok((output_end, marker_end, tail)) => {
+            let mut output = Vec::new();
+            if data.start > 0 {
+              output.extend_from_slice(
+                format!("[{} bytes of earlier output were discarded]\n", data.start).as_bytes(),
+              );
+            }
+            output.extend_from_slice(&data.bytes[..output_end]);
+            data.bytes.drain(..marker_end);
+            data.start = 0;
+            return Some((output, tail));
+          }
+          Err(resume) => checked = data.start + resume,
+        }
+      }
+      if self.closed.load(Ordering::SeqCst) {
+        return None;
+      }
+      if tokio::time::timeout_at(deadline, notified).await.is_err() {
+        return None;
+      }
+    }
+  }
+}
+
+/// Finds `\n{marker}{tail}__\n` at or after `from` and returns (output end,
+/// marker end, tail), or the position to resume from once more output arrives.
+fn find_marker(
+  data: &[u8],
+  from: usize,
+  marker: &str,
+) -> std::result::Result<(usize, usize, String), usize> {
+  let needle = format!("\n{marker}");
+  let from = from.min(data.len());
+  let Some(found) = data[from..]
+    .windows(needle.len())
+    .position(|window| window == needle.as_bytes())
+  else {
+    // The needle may be cut off at the end; rescan only that part.
+    return Err(data.len().saturating_sub(needle.len() - 1).max(from));
+  };
+  let start = from + found;
+  let rest = &data[start + needle.len()..];
+  let Some(close) = rest.windows(3).position(|window| window == b"__\n") else {
+    return Err(start);
+  };
+  let tail = String::from_utf8_lossy(&rest[..close]).trim().to_string();
+  Ok((start, start + needle.len() + close + 3, tail))
+}
+
+impl ShellSessions {
+  pub fn new() -> Arc<Self> {
+    let sessions = Arc::new(Self {
+      sessions: Mutex::new(HashMap::new()),
+    });
+    let weak = Arc::downgrade(&sessions);
+    tauri::async_runtime::spawn(Self::sweep(weak));
+    sessions
+  }
+
+  async fn sweep(sessions: Weak<Self>) {
+    loop {
+      tokio::time::sleep(SWEEP_INTERVAL).await;
+      let Some(sessions) = sessions.upgrade() else {
+        return;
+      };
+      let expired: Vec<Arc<ShellSession>> = {
+        let mut map = sessions.sessions.lock().await;
+        let ids: Vec<String> = map
+          .iter()
+          .filter(|(_, session)| {
+            session.poisoned.load(Ordering::SeqCst)
+              || (!session.busy.load(Ordering::SeqCst) && session.idle() > SESSION_IDLE_TTL)
+          })
+          .map(|(id, _)| id.clone())
+          .collect();
+        ids.iter().filter_map(|id| map.remove(id)).collect()
+      };
+      for session in expired {
+        session.kill().await;
+      }
+    }
+  }
+
+  pub async fn create(
+    &self,
+    session_id: Option<String>,
+    shell: Option<&str>,
+    working_dir: Option<&str>,
+    run_as: Option<&RunAs>,
+  ) -> Result<String> {
+    let shell = SessionShell::from_name(shell)?;
+    let session_id = session_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let mut sessions = self.sessions.lock().await;
+    if sessions.contains_key(&session_id) {
+      return Ok(session_id);
+    }
+    if sessions.len() >= MAX_SESSIONS {
+      return Err(CyberdriverError::RuntimeError(format!(
+        "Too many shell sessions (max {MAX_SESSIONS}); destroy one first"
+      )));
+    }
+    let session = ShellSession::spawn(shell, working_dir, run_as)?;
+    sessions.insert(session_id.clone(), Arc::new(session));
+    Ok(session_id)
+  }
+
+  pub async fn exists(&self, session_id: &str) -> bool {
+    self.sessions.lock().await.contains_key(session_id)
+  }
+
+  pub async fn destroy(&self, session_id: &str) -> bool {
+    let session = self.sessions.lock().await.remove(session_id);
+    match session {
+      Some(session) => {
+        session.kill().await;
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Kills and removes every session, returning how many there were.
+  pub async fn destroy_all(&self) -> usize {
+    let sessions: Vec<Arc<ShellSession>> =
+      self.sessions.lock().await.drain().map(|(_, session)| session).collect();
+    for session in &sessions {
+      session.kill().await;
+    }
+    sessions.len()
+  }
+
+  pub async fn list(&self) -> Vec<serde_json::Value> {
+    let sessions = self.sessions.lock().await;
+    let mut list: Vec<_> = sessions
+      .iter()
+      .map(|(id, session)| {
+        serde_json::json!({
+          "session_id": id,
+          "shell": session.shell.program(),
+          "pid": session.pid,
+          "age_seconds": session.created.elapsed().as_secs_f64(),
+          "idle_seconds": session.idle().as_secs_f64(),
+          "busy": session.busy.load(Ordering::SeqCst),
+        })
+      })
+      .collect();
+    list.sort_by(|a, b| a["session_id"].as_str().cmp(&b["session_id"].as_str()));
+    list
+  }
+
+  /// Runs `command` in the session. Commands in one session run one at a
+  /// time. A command that outlives `timeout` cannot be interrupted without
+  /// losing the shell's state, so the session is killed and removed.
+  pub async fn exec(
+    &self,
+    session_id: &str,
+    command: &str,
+    working_dir: Option<&str>,
+    timeout: f64,
+  ) -> Result<CommandResult> {
+    let session = self
+      .sessions
+      .lock()
+      .await
+      .get(session_id)
+      .cloned()
+      .ok_or_else(|| CyberdriverError::InvalidPayload(format!("Unknown session '{session_id}'")))?;
+    if session.poisoned.load(Ordering::SeqCst) {
+      self.destroy(session_id).await;
+      return Err(CyberdriverError::RuntimeError(
+        "Shell session was terminated after an abandoned command".into(),
+      ));
+    }
+    let result = session.exec(command, working_dir, timeout).await;
+    if !matches!(result, Ok(ref result) if !result.timeout_reached) {
+      self.destroy(session_id).await;
+    }
+    result
+  }
+}
+
+impl ShellSession {
+  fn spawn(shell: SessionShell, working_dir: Option<&str>, run_as: Option<&RunAs>) -> Result<Self> {
+    let mut cmd = shell.command();
+    if let Some(dir) = working_dir {
+      cmd.current_dir(dir);
+    }
+    process_limits::apply(&mut cmd, &ResourceLimits::default(), run_as);
+    // Its own process group, so a kill also reaches the commands it started.
+    #[cfg(unix)]
+    cmd.process_group(0);
+    let mut child = cmd
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .kill_on_drop(true)
+      .spawn()
+      .map_err(|err| {
+        CyberdriverError::RuntimeError(format!("Failed to start {}: {err}", shell.program()))
+      })?;
+    let stdin = child
+      .stdin
+      .take()
+      .ok_or_else(|| CyberdriverError::RuntimeError("Missing stdin".into()))?;
+    let stdout = OutputBuffer::new();
+    let stderr = OutputBuffer::new();
+    if let Some(pipe) = child.stdout.take() {
+      stdout.spawn_reader(pipe);
+    }
+    if let Some(pipe) = child.stderr.take() {
+      stderr.spawn_reader(pipe);
+    }
+    Ok(Self {
+      shell,
+      pid: child.id(),
+      child: Mutex::new(child),
+      stdin: Mutex::new(stdin),
+      stdout,
+      stderr,
+      created: Instant::now(),
+      last_used: std::sync::Mutex::new(Instant::now()),
+      busy: AtomicBool::new(false),
+      poisoned: AtomicBool::new(false),
+    })
+  }
+
+  fn idle(&self) -> Duration {
+    self
+      .last_used
+      .lock()
+      .map(|last_used| last_used.elapsed())
+      .unwrap_or_default()
+  }
+
+  async fn exec(
+    &self,
+    command: &str,
+    working_dir: Option<&str>,
+    timeout: f64,
+  ) -> Result<CommandResult> {
+    let mut stdin = self.stdin.lock().await;
+    let mut running = Running::start(self);
+    let marker = format!("__CYBERDRIVER_{}_", uuid::Uuid::new_v4().simple());
+    let script = self.shell.script(command, working_dir, &marker);
+    let written = async {
+      stdin.write_all(script.as_bytes()).await?;
+      stdin.flush().await
+    }
+    .await;
+    if let Err(err) = written {
+      running.finish();
+      return Err(CyberdriverError::RuntimeError(format!("Shell session has exited: {err}")));
+    }
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs_f64(timeout.max(1.0));
+    let stdout = self
+      .stdout
+      .take_until(deadline, |data, from| find_marker(data, from, &marker))
+      .await;
+    let stderr = match stdout {
+      Some(_) => {
+        self
+          .stderr
+          .take_until(deadline, |data, from| find_marker(data, from, &marker))
+          .await
+      }
+      None => None,
+    };
+    running.finish();
+    if let Ok(mut last_used) = self.last_used.lock() {
+      *last_used = Instant::now();
+    }
+
+    match (stdout, stderr) {
+      (Some((stdout, code)), Some((stderr, _))) => Ok(CommandResult {
+        stdout,
+        stderr,
+        exit_code: code.parse().unwrap_or(-1),
+        timeout_reached: false,
+      }),
+      _ if self.stdout.closed.load(Ordering::SeqCst) => Err(CyberdriverError::RuntimeError(
+        "Shell session has exited".into(),
+      )),
+      _ => Ok(CommandResult {
+        stdout: Vec::new(),
+        stderr: format!(
+          "Command timeout reached after {timeout} seconds. The session was terminated."
+        )
+        .into_bytes(),
+        exit_code: -1,
+        timeout_reached: true,
+      }),
+    }
+  }
+
+  fn kill_tree(&self) {
+    if let Some(pid) = self.pid {
+      let _ = kill_process_tree(pid);
+    }
+  }
+
+  async fn kill(&self) {
+    self.kill_tree();
+    let _ = self.child.lock().await.kill().await;
+  }
+}
+
+/// Marks a session busy for one command. If the command's future is dropped
+/// before it finishes (the client went away), the shell is mid-command with
+/// unread output, so the session is killed and poisoned rather than reused.
+struct Running<'a> {
+  session: &'a ShellSession,
+  finished: bool,
+}
+
+impl<'a> Running<'a> {
+  fn start(session: &'a ShellSession) -> Self {
+    session.busy.store(true, Ordering::SeqCst);
+    Self { session, finished: false }
+  }
+
+  fn finish(&mut self) {
+    self.finished = true;
+  }
+}
+
+impl Drop for Running<'_> {
+  fn drop(&mut self) {
+    if !self.finished {
+      self.session.poisoned.store(true, Ordering::SeqCst);
+      self.session.kill_tree();
+    }
+    self.session.busy.store(false, Ordering::SeqCst);
+  }
+}
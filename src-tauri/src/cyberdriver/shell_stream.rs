@@ -0,0 +1,284 @@
+use std::{collections::HashMap, time::Duration};
+
+use axum::{
+  body::Body,
+  extract::State,
+  http::{header, HeaderMap},
+  response::{IntoResponse, Response},
+  Json,
+};
+use bytes::Bytes;
+use serde::Deserialize;
+use tokio::{sync::mpsc, time::Instant};
+
+use super::{
+  api::{ApiError, ApiResult, ApiState},
+  process_limits::{ResourceLimits, RunAs},
+  processes::{command_or_script, Interpreter, JobInput, JobSpec, JobStream, KILL_GRACE},
+  shell_policy,
+};
+
+const STREAM_READ_BYTES: usize = 64 * 1024;
+/// A line still missing its newline after this long is sent as-is, so
+/// prompts and progress bars show up.
+const PARTIAL_LINE_FLUSH: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy)]
+enum StreamFormat {
+  Ndjson,
+  Sse,
+}
+
+impl StreamFormat {
+  fn content_type(self) -> &'static str {
+    match self {
+      Self::Ndjson => "application/x-ndjson",
+      Self::Sse => "text/event-stream",
+    }
+  }
+
+  fn encode(self, event: &serde_json::Value) -> Bytes {
+    let json = event.to_string();
+    match self {
+      Self::Ndjson => Bytes::from(format!("{json}\n")),
+      Self::Sse => {
+        let name = event["type"].as_str().unwrap_or("message");
+        Bytes::from(format!("event: {name}\ndata: {json}\n\n"))
+      }
+    }
+  }
+}
+
+#[derive(Deserialize)]
+pub(super) struct ShellStreamPayload {
+  #[serde(default)]
+  command: String,
+  script: Option<String>,
+  shell: Option<String>,
+  #[serde(default)]
+  env: HashMap<String, String>,
+  stdin: Option<String>,
+  working_directory: Option<String>,
+  timeout: Option<f64>,
+  format: Option<String>,
+  #[serde(default)]
+  limits: ResourceLimits,
+  #[serde(default)]
+  kill_on_timeout: bool,
+}
+
+/// Runs a one-shot command like `/computer/shell/powershell/exec` (with the
+/// same `script`, `shell`, `env` and `stdin` options) but streams its
+/// output as NDJSON (or server-sent events with `format: "sse"` or an
+/// `Accept: text/event-stream` header). Events are `start` (with the job id),
+/// `stdout`/`stderr` carrying one line each in `data`, and a final `exit`.
+/// Every event carries `stdout_offset` and `stderr_offset`, the output
+/// positions reported so far. Output is not truncated. A command still
+/// running at the timeout, or when the client goes away, keeps running as
+/// its `/computer/processes` job unless `kill_on_timeout` is set; `limits`
+/// work as for exec.
+///
+/// Over the tunnel, a stream that has run for 30 seconds is ended early
+/// when other responses are waiting behind it, without an `exit` event.
+/// The job keeps running; read the rest from
+/// `/computer/processes/:id/output` with the last event's offsets.
+pub(super) async fn post_powershell_exec_stream(
+  State(state): State<ApiState>,
+  headers: HeaderMap,
+  Json(payload): Json<ShellStreamPayload>,
+) -> ApiResult<Response> {
+  let (command, script) = command_or_script(payload.command, payload.script)?;
+  let accepts_sse = headers
+    .get(header::ACCEPT)
+    .and_then(|accept| accept.to_str().ok())
+    .is_some_and(|accept| accept.contains("text/event-stream"));
+  let format = match payload.format.as_deref() {
+    Some("ndjson") => StreamFormat::Ndjson,
+    Some("sse") => StreamFormat::Sse,
+    None if accepts_sse => StreamFormat::Sse,
+    None => StreamFormat::Ndjson,
+    Some(_) => return Err(ApiError::bad_request("format must be 'ndjson' or 'sse'")),
+  };
+  let timeout = payload.timeout.unwrap_or(30.0).max(1.0);
+  let interpreter = Interpreter::from_name(payload.shell.as_deref())?;
+  payload.limits.validate()?;
+  shell_policy::authorize(
+    &state,
+    "/computer/shell/powershell/exec/stream",
+    &command,
+    payload.shell.as_deref(),
+  )
+  .await?;
+  let run_as = RunAs::configured(&state).await?;
+  let kill_on_timeout = payload.kill_on_timeout;
+
+  let job = state
+    .jobs
+    .start(JobSpec {
+      command,
+      script,
+      interpreter,
+      working_directory: payload.working_directory,
+      env: payload.env,
+      stdin: match payload.stdin {
+        Some(input) => JobInput::Data(input.into_bytes()),
+        None => JobInput::Null,
+      },
+      limits: payload.limits,
+      run_as,
+    })
+    .await?;
+
+  let (sender, receiver) = mpsc::channel::<Bytes>(64);
+  tauri::async_runtime::spawn(async move {
+    // Offsets of the output reported so far, added to every event.
+    let mut offsets = (0u64, 0u64);
+    let mut emit = |mut event: serde_json::Value| {
+      if let Some(offset) = event["stdout_offset"].as_u64() {
+        offsets.0 = offset;
+      }
+      if let Some(offset) = event["stderr_offset"].as_u64() {
+        offsets.1 = offset;
+      }
+      event["stdout_offset"] = offsets.0.into();
+      event["stderr_offset"] = offsets.1.into();
+      sender.send(format.encode(&event))
+    };
+    let start = serde_json::json!({ "type": "start", "job_id": job.id, "pid": job.pid });
+    if emit(start).await.is_err() {
+      return;
+    }
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs_f64(timeout);
+    let mut stdout = LineReader::new("stdout");
+    let mut stderr = LineReader::new("stderr");
+    loop {
+      // Checked before draining: once a job reports its exit, its output is complete.
+      let mut exit = job.exit();
+      let timed_out = exit.is_none() && Instant::now() >= deadline;
+      if timed_out && kill_on_timeout {
+        job.kill_tree("KILL (timeout)");
+        exit = job.wait(KILL_GRACE).await;
+      }
+      let finished = exit.is_some() || timed_out;
+      let mut events = stdout.drain(&job.stdout, finished);
+      events.extend(stderr.drain(&job.stderr, finished));
+      for event in events {
+        if emit(event).await.is_err() {
+          return;
+        }
+      }
+      if finished {
+        let exit = serde_json::json!({
+          "type": "exit",
+          "exit_code": exit.as_ref().and_then(|exit| exit.code),
+          "timeout_reached": timed_out,
+          "job_id": exit.is_none().then(|| job.id.clone()),
+          "elapsed": started.elapsed().as_secs_f64(),
+        });
+        let _ = emit(exit).await;
+        break;
+      }
+      let wake = stdout.next_wake(deadline).min(stderr.next_wake(deadline));
+      tokio::select! {
+        _ = job.stdout.wait_past(stdout.offset, wake) => {}
+        _ = job.stderr.wait_past(stderr.offset, wake) => {}
+        _ = job.wait(wake.saturating_duration_since(Instant::now())) => {}
+        _ = sender.closed() => return,
+      }
+    }
+    // A finished job was fully reported on the stream; one that timed out
+    // stays listed so it can still be reached by id.
+    if job.exit().is_some() {
+      state.jobs.remove(&job.id).await;
+    }
+  });
+
+  let stream = futures_util::stream::unfold(receiver, |mut receiver| async move {
+    receiver
+      .recv()
+      .await
+      .map(|chunk| (Ok::<_, std::io::Error>(chunk), receiver))
+  });
+  Ok(
+    (
+      [
+        (header::CONTENT_TYPE, format.content_type()),
+        (header::CACHE_CONTROL, "no-cache"),
+      ],
+      Body::from_stream(stream),
+    )
+      .into_response(),
+  )
+}
+
+/// Splits one output stream into line events.
+struct LineReader {
+  name: &'static str,
+  offset: u64,
+  pending: Vec<u8>,
+  pending_since: Option<Instant>,
+}
+
+impl LineReader {
+  fn new(name: &'static str) -> Self {
+    Self {
+      name,
+      offset: 0,
+      pending: Vec::new(),
+      pending_since: None,
+    }
+  }
+
+  /// Returns events for the complete lines read so far, plus the partial
+  /// last line if it has waited long enough or `flush` is set.
+  fn drain(&mut self, stream: &JobStream, flush: bool) -> Vec<serde_json::Value> {
+    loop {
+      let chunk = stream.read(self.offset, STREAM_READ_BYTES);
+      if chunk.data.is_empty() {
+        break;
+      }
+      self.offset = chunk.next_offset;
+      if self.pending.is_empty() {
+        self.pending_since = Some(Instant::now());
+      }
+      self.pending.extend_from_slice(&chunk.data);
+    }
+
+    let mut events = Vec::new();
+    while let Some(end) = self.pending.iter().position(|byte| *byte == b'\n') {
+      let line: Vec<u8> = self.pending.drain(..=end).collect();
+      events.push(self.event(&line, self.offset - self.pending.len() as u64));
+      self.pending_since = (!self.pending.is_empty()).then(Instant::now);
+    }
+    let stale = self
+      .pending_since
+      .is_some_and(|since| since.elapsed() >= PARTIAL_LINE_FLUSH);
+    if !self.pending.is_empty() && (flush || stale) {
+      let line = std::mem::take(&mut self.pending);
+      events.push(self.event(&line, self.offset));
+      self.pending_since = None;
+    }
+    events
+  }
+
+  /// When the partial line should be flushed, capped at `deadline`.
+  fn next_wake(&self, deadline: Instant) -> Instant {
+    self
+      .pending_since
+      .map(|since| since + PARTIAL_LINE_FLUSH)
+      .unwrap_or(deadline)
+      .min(deadline)
+  }
+
+  /// `data` keeps the line's newline (absent for a flushed partial line), so
+  /// concatenating events reproduces the output exactly. `end` is the
+  /// stream offset just past the line.
+  fn event(&self, line: &[u8], end: u64) -> serde_json::Value {
+    serde_json::json!({
+      "type": self.name,
+      "data": String::from_utf8_lossy(line),
+      format!("{}_offset", self.name): end,
+    })
+  }
+}
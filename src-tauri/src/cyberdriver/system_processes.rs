@@ -0,0 +1,194 @@
+use axum::{
+  extract::{Path, Query, State},
+  http::StatusCode,
+  Json,
+};
+use serde::Deserialize;
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind, Users};
+
+use super::{
+  api::{ApiError, ApiResult, ApiState},
+  fs::blocking,
+  processes::SignalName,
+};
+
+const DEFAULT_PROCESS_LIMIT: usize = 200;
+const MAX_PROCESS_LIMIT: usize = 5000;
+
+#[derive(Deserialize)]
+pub(super) struct SystemProcessesQuery {
+  name: Option<String>,
+  user: Option<String>,
+  pid: Option<u32>,
+  sort: Option<String>,
+  limit: Option<usize>,
+}
+
+/// Lists processes running on the machine, optionally filtered by `name`
+/// (case-insensitive substring of the name or executable path), `user` or
+/// `pid`. Sorted by `cpu` (the default), `memory`, `pid`, `name` or
+/// `start_time`; `cpu_percent` is measured over a short sampling interval and
+/// can exceed 100 on multi-core machines.
+pub(super) async fn get_system_processes(
+  Query(query): Query<SystemProcessesQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let sort = query.sort.clone().unwrap_or_else(|| "cpu".to_string());
+  if !["cpu", "memory", "pid", "name", "start_time"].contains(&sort.as_str()) {
+    return Err(ApiError::bad_request(
+      "sort must be 'cpu', 'memory', 'pid', 'name' or 'start_time'",
+    ));
+  }
+  let limit = query.limit.unwrap_or(DEFAULT_PROCESS_LIMIT).clamp(1, MAX_PROCESS_LIMIT);
+
+  blocking(move || {
+    let system = sample_processes();
+    let users = Users::new_with_refreshed_list();
+    let name_filter = query.name.as_deref().map(str::to_lowercase);
+    let mut processes: Vec<_> = system
+      .processes()
+      .values()
+      .filter(|process| query.pid.is_none_or(|pid| process.pid().as_u32() == pid))
+      .filter(|process| {
+        name_filter.as_deref().is_none_or(|filter| {
+          process.name().to_string_lossy().to_lowercase().contains(filter)
+            || process
+              .exe()
+              .is_some_and(|exe| exe.to_string_lossy().to_lowercase().contains(filter))
+        })
+      })
+      .map(|process| {
+        let user = process
+          .user_id()
+          .and_then(|uid| users.get_user_by_id(uid))
+          .map(|user| user.name().to_string());
+        (process, user)
+      })
+      .filter(|(_, user)| {
+        query
+          .user
+          .as_deref()
+          .is_none_or(|wanted| user.as_ref().is_some_and(|user| user.eq_ignore_ascii_case(wanted)))
+      })
+      .collect();
+
+    match sort.as_str() {
+      "memory" => processes.sort_by(|(a, _), (b, _)| b.memory().cmp(&a.memory())),
+      "pid" => processes.sort_by_key(|(process, _)| process.pid()),
+      "name" => processes
+        .sort_by_cached_key(|(process, _)| process.name().to_string_lossy().to_lowercase()),
+      "start_time" => processes.sort_by(|(a, _), (b, _)| b.start_time().cmp(&a.start_time())),
+      _ => processes.sort_by(|(a, _), (b, _)| b.cpu_usage().total_cmp(&a.cpu_usage())),
+    }
+
+    let total_memory = system.total_memory();
+    let matched = processes.len();
+    let processes: Vec<_> = processes
+      .into_iter()
+      .take(limit)
+      .map(|(process, user)| {
+        let cmd: Vec<String> = process
+          .cmd()
+          .iter()
+          .map(|arg| arg.to_string_lossy().to_string())
+          .collect();
+        serde_json::json!({
+          "pid": process.pid().as_u32(),
+          "parent_pid": process.parent().map(|pid| pid.as_u32()),
+          "name": process.name().to_string_lossy(),
+          "exe": process.exe().map(|exe| exe.to_string_lossy().to_string()),
+          "cmdline": cmd.join(" "),
+          "cmd": cmd,
+          "user": user,
+          "status": process.status().to_string(),
+          "cpu_percent": process.cpu_usage(),
+          "memory_bytes": process.memory(),
+          "memory_percent": (total_memory > 0)
+            .then(|| process.memory() as f64 * 100.0 / total_memory as f64),
+          "start_time": process.start_time(),
+          "run_time_seconds": process.run_time(),
+        })
+      })
+      .collect();
+    Ok(Json(serde_json::json!({
+      "processes": processes,
+      "matched": matched,
+      "truncated": matched > limit,
+    })))
+  })
+  .await
+}
+
+#[derive(Deserialize)]
+pub(super) struct SystemProcessKillPayload {
+  signal: Option<String>,
+}
+
+/// Sends `signal` (default KILL) to any process on the machine. Windows only
+/// supports KILL. Cyberdriver's own process is refused.
+pub(super) async fn post_system_process_kill(
+  State(state): State<ApiState>,
+  Path(pid): Path<u32>,
+  Json(payload): Json<SystemProcessKillPayload>,
+) -> ApiResult<Json<serde_json::Value>> {
+  let name = payload.signal.unwrap_or_else(|| "KILL".to_string()).to_uppercase();
+  let signal = SignalName::parse(&name)
+    .map(SignalName::to_sysinfo)
+    .ok_or_else(|| ApiError::bad_request(&format!("Unsupported signal '{name}'")))?;
+  if pid == std::process::id() {
+    return Err(ApiError::status(StatusCode::FORBIDDEN, "Refusing to signal Cyberdriver itself"));
+  }
+
+  let signal_name = name.clone();
+  let (process_name, sent) = blocking(move || {
+    let mut system = System::new();
+    let target = Pid::from_u32(pid);
+    system.refresh_processes(ProcessesToUpdate::Some(&[target]), true);
+    let process = system
+      .process(target)
+      .ok_or_else(|| ApiError::status(StatusCode::NOT_FOUND, "Process not found"))?;
+    let sent = process.kill_with(signal).ok_or_else(|| {
+      ApiError::bad_request(&format!("Signal '{signal_name}' is not supported on this platform"))
+    })?;
+    Ok((process.name().to_string_lossy().to_string(), sent))
+  })
+  .await?;
+
+  state.debug_logger.audit(
+    "PROCESS",
+    if sent { "System process signalled" } else { "System process signal failed" },
+    &[
+      ("pid", pid.to_string()),
+      ("name", process_name.clone()),
+      ("signal", name.clone()),
+    ],
+  );
+  if !sent {
+    return Err(ApiError::status(
+      StatusCode::FORBIDDEN,
+      &format!("Failed to signal process {pid}; it may belong to another user"),
+    ));
+  }
+  Ok(Json(serde_json::json!({
+    "pid": pid,
+    "name": process_name,
+    "signal": name,
+    "sent": true,
+  })))
+}
+
+/// Refreshes every process twice, a CPU sampling interval apart, so CPU
+/// usage is meaningful.
+fn sample_processes() -> System {
+  let refresh = ProcessRefreshKind::nothing()
+    .with_cpu()
+    .with_memory()
+    .with_exe(UpdateKind::OnlyIfNotSet)
+    .with_cmd(UpdateKind::OnlyIfNotSet)
+    .with_user(UpdateKind::OnlyIfNotSet);
+  let mut system = System::new();
+  system.refresh_memory();
+  system.refresh_processes_specifics(ProcessesToUpdate::All, true, refresh);
+  std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+  system.refresh_processes_specifics(ProcessesToUpdate::All, true, refresh);
+  system
+}
@@ -1,20 +1,39 @@
-use std::{collections::HashMap, time::{Duration, Instant}};
+use std::{
+  collections::{HashMap, VecDeque},
+  path::PathBuf,
+  sync::Arc,
+  time::{Duration, Instant},
+};
 
+use axum::{
+  body::{Body, HttpBody},
+  Router,
+};
 use futures_util::{Sink, SinkExt, StreamExt};
 use http::{HeaderMap, HeaderValue};
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
-use tokio_util::sync::CancellationToken;
+use tokio::{
+  io::AsyncWriteExt,
+  sync::{mpsc, Mutex, Semaphore},
+  task::JoinSet,
+};
+use tokio_util::io::ReaderStream;
+use tokio_util::{sync::CancellationToken, task::AbortOnDropHandle};
+use tower::ServiceExt;
 use tungstenite::{client::IntoClientRequest, protocol::WebSocketConfig, Error as WsError, Message};
-use tauri_plugin_http::reqwest;
 use rand::random;
 
 use crate::error::{CyberdriverError, Result};
 
 use super::{
   config::{Config, ConnectionInfo},
+  idempotency::{Claim, IdempotencyCache},
   keepalive::KeepAliveManager,
   logger::DebugLogger,
+  proxy::ProxySettings,
+  shell_policy,
+  tunnel_compression::{self, Compressor, ContentEncoding},
+  tunnel_tls::TunnelTls,
 };
 
 #[derive(Debug, Deserialize)]
@@ -27,87 +46,187 @@ struct RequestMeta {
   headers: Option<HashMap<String, String>>,
 }
 
+/// `contentEncoding` is set when the body frames are compressed; `headers`
+/// still describe the uncompressed body.
 #[derive(Debug, Serialize)]
 struct ResponseMeta<'a> {
   #[serde(rename = "requestId")]
   request_id: &'a str,
   status: u16,
   headers: HashMap<String, String>,
+  #[serde(rename = "contentEncoding", skip_serializing_if = "Option::is_none")]
+  content_encoding: Option<&'static str>,
 }
 
 #[derive(Clone, Debug)]
-struct TunnelResponse {
+pub(super) struct TunnelResponse {
+  pub(super) status: u16,
+  pub(super) headers: HashMap<String, String>,
+  pub(super) body: Vec<u8>,
+}
+
+/// A local response is either buffered whole, or relayed chunk by chunk
+/// while the local API is still producing it. Streaming content types and
+/// large or unsized bodies are relayed; small bodies are buffered, and cached
+/// when the request carries an idempotency key.
+enum ForwardedResponse {
+  Buffered(TunnelResponse),
+  Streaming {
+    status: u16,
+    headers: HashMap<String, String>,
+    body: Body,
+  },
+}
+
+/// A response waiting to be written to the socket. Each one is written
+/// whole, so the meta/binary/"end" frames of concurrent requests never
+/// interleave.
+enum Outgoing {
+  Frames(Vec<Message>),
+  Streaming(StreamedResponse),
+  /// A streamed body that waited behind another stream and is being copied
+  /// to disk meanwhile; it is sent from the file once the copy is done.
+  Spooled(StreamedResponse, AbortOnDropHandle<Option<SpoolFile>>),
+}
+
+/// A response relayed while the local API is still producing its body. Its
+/// meta frame is sent with the first body chunk, once it is known whether
+/// compression pays off.
+struct StreamedResponse {
+  request_id: String,
   status: u16,
   headers: HashMap<String, String>,
-  body: Vec<u8>,
+  path: String,
+  body: Body,
+  encoding: Option<ContentEncoding>,
+  /// Open-ended content such as an event stream, which cannot be spooled.
+  live: bool,
+}
+
+/// Owns the socket's write half. While a stream holds it, other responses
+/// are still taken off the queue into `backlog`, so request tasks never
+/// block on a full queue. Streams waiting there are spooled to disk, and a
+/// live stream gives up the writer after `LIVE_STREAM_HOLD` if anything is
+/// waiting behind it.
+struct ResponseWriter<'a, S> {
+  client: &'a TunnelClient,
+  write: S,
+  queue: mpsc::Receiver<Outgoing>,
+  backlog: VecDeque<Outgoing>,
+  ping: tokio::time::Interval,
+}
+
+type IncomingRequest = (RequestMeta, RequestBody);
+
+/// A request body collected from binary frames. Bodies stay in memory up to
+/// `SPOOL_THRESHOLD_BYTES`; larger uploads are spilled to a temporary file,
+/// which is streamed to the local API and removed when the body is dropped.
+#[derive(Default)]
+struct RequestBody {
+  memory: Vec<u8>,
+  spool: Option<SpoolFile>,
+  error: Option<String>,
+}
+
+struct SpoolFile {
+  path: PathBuf,
+  file: Option<tokio::fs::File>,
 }
 
 pub struct TunnelClient {
   host: String,
   port: u16,
   secret: String,
-  target_port: u16,
+  router: Router,
   config: Config,
   keepalive: Option<std::sync::Arc<KeepAliveManager>>,
   remote_keepalive_for: Option<String>,
   debug_logger: DebugLogger,
   connection_info: std::sync::Arc<Mutex<ConnectionInfo>>,
-  idempotency_cache: Mutex<HashMap<String, (Instant, TunnelResponse)>>,
+  idempotency: IdempotencyCache,
+  max_concurrent_requests: usize,
+  compression: bool,
+  proxy: ProxySettings,
+  tls: TunnelTls,
 }
 
-const IDEMPOTENCY_CACHE_TTL: Duration = Duration::from_secs(60);
-const IDEMPOTENCY_CACHE_MAX_SIZE: usize = 1000;
+const RESPONSE_CHUNK_BYTES: usize = 16 * 1024;
+/// Responses that may wait for the writer before request tasks block.
+const OUTGOING_QUEUE_SIZE: usize = 32;
+/// Responses held back while a stream has the writer; beyond this the queue
+/// fills up and request tasks wait again.
+const OUTGOING_BACKLOG_SIZE: usize = 256;
+/// How long a live stream may keep the writer while other responses wait
+/// behind it. It is then ended early, and the client has to reopen it.
+const LIVE_STREAM_HOLD: Duration = Duration::from_secs(30);
+/// Request bodies larger than this are spooled to disk.
+const SPOOL_THRESHOLD_BYTES: usize = 8 * 1024 * 1024;
+/// Responses up to this size are buffered; larger ones are relayed as they
+/// are read.
+const BUFFERED_RESPONSE_MAX_BYTES: u64 = 1024 * 1024;
+/// Content types whose bodies are relayed incrementally.
+const STREAMING_CONTENT_TYPES: &[&str] = &["text/event-stream", "application/x-ndjson"];
 
 impl TunnelClient {
   pub fn new(
     host: String,
     port: u16,
     secret: String,
-    target_port: u16,
+    router: Router,
     config: Config,
     keepalive: Option<std::sync::Arc<KeepAliveManager>>,
     remote_keepalive_for: Option<String>,
     debug_logger: DebugLogger,
     connection_info: std::sync::Arc<Mutex<ConnectionInfo>>,
+    max_concurrent_requests: usize,
+    compression: bool,
+    idempotency: IdempotencyCache,
+    proxy: ProxySettings,
+    tls: TunnelTls,
   ) -> Self {
     Self {
       host,
       port,
       secret,
-      target_port,
+      router,
       config,
       keepalive,
       remote_keepalive_for,
       debug_logger,
       connection_info,
-      idempotency_cache: Mutex::new(HashMap::new()),
+      idempotency,
+      max_concurrent_requests,
+      compression,
+      proxy,
+      tls,
     }
   }
 
-  pub async fn run(mut self, stop: CancellationToken) {
+  pub async fn run(self, stop: CancellationToken) {
+    let client = Arc::new(self);
     let mut sleep_time = 1u64;
     let mut failures_at_max = 0u8;
     loop {
       if stop.is_cancelled() {
-        let mut info = self.connection_info.lock().await;
+        let mut info = client.connection_info.lock().await;
         info.connected = false;
         break;
       }
       let connection_start = Instant::now();
-      let result = self.connect_and_run(stop.clone()).await;
+      let result = client.connect_and_run(stop.clone()).await;
       if stop.is_cancelled() {
-        let mut info = self.connection_info.lock().await;
+        let mut info = client.connection_info.lock().await;
         info.connected = false;
         break;
       }
       {
-        let mut info = self.connection_info.lock().await;
+        let mut info = client.connection_info.lock().await;
         info.connected = false;
         info.last_error = result.as_ref().err().map(|err| err.to_string());
       }
       if let Err(err) = result {
         let duration = connection_start.elapsed().as_secs_f64();
-        self.debug_logger.connection_closed(&err.to_string(), duration, None);
+        client.debug_logger.connection_closed(&err.to_string(), duration, None);
         if err.to_string().contains("AUTH_FAILURE") {
           break;
         }
@@ -128,9 +247,13 @@ impl TunnelClient {
     }
   }
 
-  async fn connect_and_run(&mut self, stop: CancellationToken) -> Result<()> {
+  /// Reads requests off the socket and runs each in its own task, keyed by
+  /// request id, up to `max_concurrent_requests` at a time. Mutating input
+  /// requests run one at a time in arrival order. Responses go through a
+  /// single writer, which also sends the keepalive pings.
+  async fn connect_and_run(self: &Arc<Self>, stop: CancellationToken) -> Result<()> {
     let host = self.host.trim_start_matches("https://").trim_start_matches("http://").trim_end_matches('/');
-    let uri = format!("wss://{host}:{}/tunnel/ws", self.port);
+    let uri = format!("{}://{host}:{}/tunnel/ws", self.tls.scheme(), self.port);
     self.debug_logger.connection_attempt(&uri, 1);
 
     {
@@ -157,6 +280,13 @@ impl TunnelClient {
       if let Some(main_id) = &self.remote_keepalive_for {
         set_header(headers, "X-Remote-Keepalive-For", main_id.clone())?;
       }
+      if self.compression {
+        set_header(
+          headers,
+          tunnel_compression::CONTENT_ENCODING_HEADER,
+          tunnel_compression::OFFERED_ENCODINGS.to_string(),
+        )?;
+      }
     }
 
     let mut config = WebSocketConfig::default();
@@ -164,9 +294,25 @@ impl TunnelClient {
     config.max_frame_size = None;
     config.accept_unmasked_frames = false;
 
-    let connect_result =
-      tokio_tungstenite::connect_async_with_config(request, Some(config), false).await;
-    let (ws_stream, _) = match connect_result {
+    // Reaching the proxy and the handshakes can hang, so a disconnect has to
+    // be able to interrupt them.
+    let connecting = async {
+      let stream = self.proxy.connect(host, self.port).await?;
+      Ok::<_, CyberdriverError>(
+        tokio_tungstenite::client_async_tls_with_config(
+          request,
+          stream,
+          Some(config),
+          self.tls.connector(),
+        )
+        .await,
+      )
+    };
+    let connect_result = tokio::select! {
+      _ = stop.cancelled() => return Ok(()),
+      result = connecting => result?,
+    };
+    let (ws_stream, handshake) = match connect_result {
       Ok(value) => value,
       Err(err) => {
         if let WsError::Http(response) = &err {
@@ -178,25 +324,51 @@ impl TunnelClient {
       }
     };
     self.debug_logger.connection_established(&uri);
+    // Responses are compressed only if the server accepted an encoding.
+    let encoding = ContentEncoding::negotiate(
+      handshake
+        .headers()
+        .get(tunnel_compression::CONTENT_ENCODING_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| self.compression),
+    );
+    if let Some(encoding) = encoding {
+      self.debug_logger.info(
+        "TUNNEL",
+        &format!("Compressing response bodies with {}", encoding.name()),
+      );
+    }
     {
       let mut info = self.connection_info.lock().await;
       info.connected = true;
       info.last_error = None;
     }
 
-    let (mut write, mut read) = ws_stream.split();
-    let mut ping = tokio::time::interval(Duration::from_secs(20));
+    let (write, mut read) = ws_stream.split();
+    let (outgoing, outgoing_queue) = mpsc::channel::<Outgoing>(OUTGOING_QUEUE_SIZE);
+    let writer = self.write_responses(write, outgoing_queue);
+    tokio::pin!(writer);
+    let slots = Arc::new(Semaphore::new(self.max_concurrent_requests));
+    let (serial, serial_queue) = mpsc::unbounded_channel::<IncomingRequest>();
+    // Dropping the set when the connection ends aborts requests in flight.
+    let mut requests = JoinSet::new();
+    requests.spawn(self.clone().run_serially(
+      serial_queue,
+      outgoing.clone(),
+      slots.clone(),
+      encoding,
+    ));
     let mut request_meta: Option<RequestMeta> = None;
-    let mut body_buffer: Vec<u8> = Vec::new();
+    let mut request_body = RequestBody::default();
 
     loop {
       tokio::select! {
         _ = stop.cancelled() => break,
-        _ = ping.tick() => {
-          if let Err(err) = write.send(Message::Ping(Vec::new().into())).await {
-            return Err(CyberdriverError::RuntimeError(format!("Ping failed: {err}")));
-          }
+        result = &mut writer => {
+          result?;
+          return Err(CyberdriverError::RuntimeError("Response writer stopped".into()));
         }
+        Some(_) = requests.join_next(), if !requests.is_empty() => {}
         msg = read.next() => {
           let msg = match msg {
             Some(Ok(msg)) => msg,
@@ -210,20 +382,30 @@ impl TunnelClient {
                   if let Some(k) = &self.keepalive {
                     k.record_activity().await;
                   }
-                  let response = self.forward_request(&meta, &body_buffer).await;
-                  self.send_response(&mut write, &meta, response).await?;
-                  body_buffer.clear();
+                  let body = std::mem::take(&mut request_body);
+                  if runs_serially(&meta) {
+                    let _ = serial.send((meta, body));
+                  } else {
+                    let request = self.clone().handle_request(
+                      meta,
+                      body,
+                      outgoing.clone(),
+                      slots.clone(),
+                      encoding,
+                    );
+                    requests.spawn(request);
+                  }
                 }
               } else {
                 request_meta = Some(serde_json::from_str(&text)?);
                 if let Some(k) = &self.keepalive {
                   k.record_activity().await;
                 }
-                body_buffer.clear();
+                request_body = RequestBody::default();
               }
             }
             Message::Binary(bytes) => {
-              body_buffer.extend_from_slice(&bytes);
+              request_body.push(&bytes).await;
             }
             Message::Close(frame) => {
               if let Some(frame) = frame {
@@ -241,15 +423,74 @@ impl TunnelClient {
     Ok(())
   }
 
-  async fn forward_request(&self, meta: &RequestMeta, body: &[u8]) -> TunnelResponse {
+  /// Forwards one request once a concurrency slot is free and queues its
+  /// response for the writer, compressed with `encoding` where worthwhile.
+  async fn handle_request(
+    self: Arc<Self>,
+    meta: RequestMeta,
+    body: RequestBody,
+    outgoing: mpsc::Sender<Outgoing>,
+    slots: Arc<Semaphore>,
+    encoding: Option<ContentEncoding>,
+  ) {
+    let Ok(_slot) = slots.acquire_owned().await else {
+      return;
+    };
+    let response = match self.forward_request(&meta, body).await {
+      ForwardedResponse::Buffered(response) => {
+        response_frames(&meta, response, encoding).await.map(Outgoing::Frames)
+      }
+      ForwardedResponse::Streaming { status, headers, body } => {
+        let encoding = ContentEncoding::for_response(encoding, &headers);
+        let live = is_streaming(&headers);
+        Ok(Outgoing::Streaming(StreamedResponse {
+          request_id: meta.request_id.clone(),
+          status,
+          headers,
+          path: meta.path.clone(),
+          body,
+          encoding,
+          live,
+        }))
+      }
+    };
+    match response {
+      Ok(response) => {
+        let _ = outgoing.send(response).await;
+      }
+      Err(err) => self.debug_logger.error(
+        "TUNNEL",
+        &format!("Failed to encode response for {}: {err}", meta.path),
+      ),
+    }
+  }
+
+  /// Runs mutating input requests one at a time in the order they arrived,
+  /// so clicks and keystrokes are never reordered.
+  async fn run_serially(
+    self: Arc<Self>,
+    mut queue: mpsc::UnboundedReceiver<IncomingRequest>,
+    outgoing: mpsc::Sender<Outgoing>,
+    slots: Arc<Semaphore>,
+    encoding: Option<ContentEncoding>,
+  ) {
+    while let Some((meta, body)) = queue.recv().await {
+      self
+        .clone()
+        .handle_request(meta, body, outgoing.clone(), slots.clone(), encoding)
+        .await;
+    }
+  }
+
+  async fn forward_request(&self, meta: &RequestMeta, body: RequestBody) -> ForwardedResponse {
     let start = Instant::now();
-    if let Some(idempotency_key) = get_idempotency_key(meta.headers.as_ref()) {
-      self.cleanup_idempotency_cache().await;
-      let cache = self.idempotency_cache.lock().await;
-      if let Some((ts, cached)) = cache.get(&idempotency_key) {
-        if ts.elapsed() < IDEMPOTENCY_CACHE_TTL {
-          return cached.clone();
-        }
+    let idempotency_key = get_idempotency_key(meta.headers.as_ref());
+    // Held until the response is cached; an early return releases it.
+    let mut in_flight = None;
+    if let Some(idempotency_key) = &idempotency_key {
+      match self.idempotency.claim(idempotency_key, &meta.path).await {
+        Claim::Replay(response) => return ForwardedResponse::Buffered(response),
+        Claim::Run(claim) => in_flight = Some(claim),
       }
     }
 
@@ -258,16 +499,32 @@ impl TunnelClient {
       keepalive.record_activity().await;
     }
 
-    let mut url = format!("http://127.0.0.1:{}{}", self.target_port, meta.path);
+    let mut uri = meta.path.clone();
     if let Some(query) = &meta.query {
       if !query.is_empty() {
-        url.push('?');
-        url.push_str(query);
+        uri.push('?');
+        uri.push_str(query);
       }
     }
 
-    let mut headers = HeaderMap::new();
+    let timeout = Duration::from_secs_f64(request_timeout(&meta.path, &body.memory).max(1.0));
+    let deadline = tokio::time::Instant::now() + timeout;
+    // Keeps a spooled body's file until the request is done with it.
+    let (request_body, _spool) = match body.into_body().await {
+      Ok(body) => body,
+      Err(message) => return ForwardedResponse::Buffered(error_response(message)),
+    };
+    let mut request = http::Request::new(request_body);
+    *request.method_mut() = meta.method.to_uppercase().parse().unwrap_or(http::Method::GET);
+    match uri.parse() {
+      Ok(uri) => *request.uri_mut() = uri,
+      Err(err) => {
+        let message = format!("Invalid request path {uri}: {err}");
+        return ForwardedResponse::Buffered(error_response(message));
+      }
+    }
     if let Some(raw) = &meta.headers {
+      let headers = request.headers_mut();
       for (key, value) in raw {
         if let (Ok(name), Ok(val)) = (
           http::header::HeaderName::from_bytes(key.as_bytes()),
@@ -278,107 +535,393 @@ impl TunnelClient {
       }
     }
 
-    let method = meta.method.to_uppercase();
-    let client = reqwest::Client::new();
-    let timeout = if meta.path == "/computer/shell/powershell/exec" {
-      extract_timeout(body).map(|t| t + 3.0).unwrap_or(30.0)
-    } else {
-      30.0
+    // The router is called directly rather than through the loopback
+    // listener, which does not even need to be running.
+    let dispatch = self.router.clone().oneshot(request);
+    let response = match tokio::time::timeout_at(deadline, dispatch).await {
+      Ok(Ok(response)) => response,
+      Ok(Err(never)) => match never {},
+      Err(_) => {
+        let message = format!("Local request to {} timed out after {timeout:?}", meta.path);
+        return ForwardedResponse::Buffered(error_response(message));
+      }
     };
 
-    let response = client
-      .request(method.parse().unwrap_or(reqwest::Method::GET), url)
-      .headers(headers)
-      .timeout(Duration::from_secs_f64(timeout.max(1.0)))
-      .body(body.to_vec())
-      .send()
-      .await;
+    let status = response.status().as_u16();
+    let mut headers = HashMap::new();
+    for (key, value) in response.headers().iter() {
+      if let Ok(val) = value.to_str() {
+        headers.insert(key.to_string(), val.to_string());
+      }
+    }
+    let body = response.into_body();
+    let large = body
+      .size_hint()
+      .exact()
+      .is_none_or(|length| length > BUFFERED_RESPONSE_MAX_BYTES);
+    if is_streaming(&headers) || large {
+      // Too big to cache: the claim is released, so a retry runs again.
+      drop(in_flight);
+      let elapsed_ms = start.elapsed().as_millis() as f64;
+      self
+        .debug_logger
+        .request_forwarded(&meta.method, &meta.path, status, elapsed_ms);
+      return ForwardedResponse::Streaming { status, headers, body };
+    }
+    let collect = axum::body::to_bytes(body, BUFFERED_RESPONSE_MAX_BYTES as usize);
+    let bytes = match tokio::time::timeout_at(deadline, collect).await {
+      Ok(Ok(bytes)) => bytes.to_vec(),
+      Ok(Err(err)) => {
+        let message = format!("Failed to read local response for {}: {err}", meta.path);
+        return ForwardedResponse::Buffered(error_response(message));
+      }
+      Err(_) => {
+        let message = format!("Local request to {} timed out after {timeout:?}", meta.path);
+        return ForwardedResponse::Buffered(error_response(message));
+      }
+    };
+    let mut response = TunnelResponse { status, headers, body: bytes };
+    self
+      .debug_logger
+      .request_forwarded(&meta.method, &meta.path, response.status, start.elapsed().as_millis() as f64);
+    if response.status >= 400 && response.body.is_empty() {
+      response.headers.insert("content-type".to_string(), "application/json".to_string());
+      response.body = serde_json::json!({
+        "detail": "Cyberdriver local API returned an error with an empty body",
+        "status": response.status,
+        "method": meta.method,
+        "path": meta.path,
+      })
+      .to_string()
+      .into_bytes();
+    }
+    if let Some(in_flight) = in_flight {
+      in_flight.finish(response.clone());
+    }
+    ForwardedResponse::Buffered(response)
+  }
 
-    match response {
-      Ok(resp) => {
-        let status = resp.status().as_u16();
-        let mut headers = HashMap::new();
-        for (key, value) in resp.headers().iter() {
-          if let Ok(val) = value.to_str() {
-            headers.insert(key.to_string(), val.to_string());
+  /// Writes queued responses one at a time and pings the server every 20
+  /// seconds. Returns an error once the socket can no longer be written.
+  async fn write_responses<S>(&self, write: S, queue: mpsc::Receiver<Outgoing>) -> Result<()>
+  where
+    S: Sink<Message, Error = tungstenite::Error> + Unpin,
+  {
+    ResponseWriter {
+      client: self,
+      write,
+      queue,
+      backlog: VecDeque::new(),
+      ping: tokio::time::interval(Duration::from_secs(20)),
+    }
+    .run()
+    .await
+  }
+}
+
+impl<S> ResponseWriter<'_, S>
+where
+  S: Sink<Message, Error = tungstenite::Error> + Unpin,
+{
+  async fn run(mut self) -> Result<()> {
+    loop {
+      if let Some(next) = self.backlog.pop_front() {
+        self.send(next).await?;
+        continue;
+      }
+      tokio::select! {
+        _ = self.ping.tick() => self.send_ping().await?,
+        next = self.queue.recv() => match next {
+          Some(next) => self.send(next).await?,
+          None => return Ok(()),
+        },
+      }
+    }
+  }
+
+  async fn send(&mut self, outgoing: Outgoing) -> Result<()> {
+    match outgoing {
+      Outgoing::Frames(frames) => {
+        for frame in frames {
+          self.send_frame(frame).await?;
+        }
+        Ok(())
+      }
+      Outgoing::Streaming(response) => self.send_stream(response).await,
+      Outgoing::Spooled(mut response, spooling) => {
+        // Keeps the file until it has been sent.
+        let spool = spooling.await.ok().flatten();
+        if let Some(spool) = &spool {
+          match tokio::fs::File::open(&spool.path).await {
+            Ok(file) => response.body = Body::from_stream(ReaderStream::new(file)),
+            Err(err) => self.client.debug_logger.error(
+              "TUNNEL",
+              &format!("Failed to read spooled response for {}: {err}", response.path),
+            ),
           }
         }
-        let bytes = resp.bytes().await.unwrap_or_default().to_vec();
-        let mut response = TunnelResponse { status, headers, body: bytes };
-        self
-          .debug_logger
-          .request_forwarded(&meta.method, &meta.path, response.status, start.elapsed().as_millis() as f64);
-        if response.status >= 400 && response.body.is_empty() {
-          response.headers.insert("content-type".to_string(), "application/json".to_string());
-          response.body = serde_json::json!({
-            "detail": "Cyberdriver local API returned an error with an empty body",
-            "status": response.status,
-            "method": meta.method,
-            "path": meta.path,
-          })
-          .to_string()
-          .into_bytes();
+        self.send_stream(response).await
+      }
+    }
+  }
+
+  /// Relays a streaming body as it arrives, sending keepalive pings while
+  /// the body runs. Frames cannot interleave, so other responses are held
+  /// in the backlog until the stream ends. A body that fails midway is
+  /// ended early rather than failing the tunnel. If the first chunk does
+  /// not shrink when compressed, the whole stream is sent uncompressed.
+  async fn send_stream(&mut self, response: StreamedResponse) -> Result<()> {
+    let StreamedResponse { request_id, status, headers, path, body, encoding, live } = response;
+    let mut head = Some(ResponseMeta {
+      request_id: &request_id,
+      status,
+      headers,
+      content_encoding: None,
+    });
+    let started = tokio::time::Instant::now();
+    let mut body = body.into_data_stream();
+    let mut compressor = encoding.map(Compressor::new).transpose()?;
+    let mut queue_open = true;
+    loop {
+      let hold = live && !self.backlog.is_empty();
+      tokio::select! {
+        _ = self.ping.tick() => self.send_ping().await?,
+        _ = tokio::time::sleep_until(started + LIVE_STREAM_HOLD), if hold => {
+          self.client.debug_logger.error(
+            "TUNNEL",
+            &format!("Ending stream for {path} early so waiting responses can be sent"),
+          );
+          break;
         }
-        if let Some(idempotency_key) = get_idempotency_key(meta.headers.as_ref()) {
-          let mut cache = self.idempotency_cache.lock().await;
-          cache.insert(idempotency_key, (Instant::now(), response.clone()));
+        next = self.queue.recv(), if queue_open && self.backlog.len() < OUTGOING_BACKLOG_SIZE => {
+          match next {
+            Some(next) => self.hold_back(next),
+            None => queue_open = false,
+          }
+        }
+        chunk = body.next() => {
+          let bytes = match chunk {
+            Some(Ok(bytes)) => bytes,
+            None => break,
+            Some(Err(err)) => {
+              self.client.debug_logger.error(
+                "TUNNEL",
+                &format!("Streaming response for {path} ended early: {err}"),
+              );
+              break;
+            }
+          };
+          if bytes.is_empty() {
+            continue;
+          }
+          let bytes = match compressor.take() {
+            Some(active) => {
+              let (active, compressed) = active.write_piece(bytes.clone()).await?;
+              if head.is_some() && compressed.len() >= bytes.len() {
+                bytes.to_vec()
+              } else {
+                compressor = Some(active);
+                compressed
+              }
+            }
+            None => bytes.to_vec(),
+          };
+          if let Some(mut head) = head.take() {
+            head.content_encoding = compressor.as_ref().and(encoding).map(ContentEncoding::name);
+            self.send_frame(Message::Text(serde_json::to_string(&head)?.into())).await?;
+          }
+          for piece in bytes.chunks(RESPONSE_CHUNK_BYTES) {
+            self.send_frame(Message::Binary(piece.to_vec().into())).await?;
+          }
         }
-        response
       }
-      Err(err) => TunnelResponse {
-        status: 500,
-        headers: [("content-type".to_string(), "text/plain".to_string())]
-          .into_iter()
-          .collect(),
-        body: err.to_string().into_bytes(),
-      },
     }
+    if let Some(head) = head {
+      // The body ended before its first chunk.
+      self.send_frame(Message::Text(serde_json::to_string(&head)?.into())).await?;
+      compressor = None;
+    }
+    if let Some(compressor) = compressor {
+      let tail = compressor.finish()?;
+      if !tail.is_empty() {
+        self.send_frame(Message::Binary(tail.into())).await?;
+      }
+    }
+    self.send_frame(Message::Text("end".to_string().into())).await
   }
 
-  async fn send_response<S>(
-    &self,
-    write: &mut S,
-    meta: &RequestMeta,
-    response: TunnelResponse,
-  ) -> Result<()>
-  where
-    S: Sink<Message, Error = tungstenite::Error> + Unpin,
-  {
-    let resp_meta = ResponseMeta {
-      request_id: &meta.request_id,
-      status: response.status,
-      headers: response.headers.clone(),
+  /// Queues a response behind the current stream, starting to copy its
+  /// body to disk if it is a stream that can be spooled.
+  fn hold_back(&mut self, outgoing: Outgoing) {
+    let outgoing = match outgoing {
+      Outgoing::Streaming(mut response) if !response.live => {
+        let body = std::mem::replace(&mut response.body, Body::empty());
+        let spooling = tokio::spawn(spool_response(
+          body,
+          response.path.clone(),
+          self.client.debug_logger.clone(),
+        ));
+        Outgoing::Spooled(response, AbortOnDropHandle::new(spooling))
+      }
+      outgoing => outgoing,
     };
-    let meta_text = serde_json::to_string(&resp_meta)?;
-    write
-      .send(Message::Text(meta_text.into()))
+    self.backlog.push_back(outgoing);
+  }
+
+  async fn send_frame(&mut self, frame: Message) -> Result<()> {
+    self
+      .write
+      .send(frame)
       .await
-      .map_err(|err| CyberdriverError::RuntimeError(err.to_string()))?;
-    for chunk in response.body.chunks(16 * 1024) {
-      write
-        .send(Message::Binary(chunk.to_vec().into()))
-        .await
-        .map_err(|err| CyberdriverError::RuntimeError(err.to_string()))?;
-    }
-    write
-      .send(Message::Text("end".to_string().into()))
+      .map_err(|err| CyberdriverError::RuntimeError(err.to_string()))
+  }
+
+  async fn send_ping(&mut self) -> Result<()> {
+    self
+      .write
+      .send(Message::Ping(Vec::new().into()))
       .await
-      .map_err(|err| CyberdriverError::RuntimeError(err.to_string()))?;
+      .map_err(|err| CyberdriverError::RuntimeError(format!("Ping failed: {err}")))
+  }
+}
+
+/// Copies a response body to a temporary file, so the local handler can
+/// finish while the response waits for the writer. A body that fails midway
+/// is kept up to that point, as when it is relayed directly.
+async fn spool_response(body: Body, path: String, logger: DebugLogger) -> Option<SpoolFile> {
+  let mut spool = match SpoolFile::create("response").await {
+    Ok(spool) => spool,
+    Err(err) => {
+      logger.error("TUNNEL", &format!("Failed to spool response for {path}: {err}"));
+      return None;
+    }
+  };
+  let mut body = body.into_data_stream();
+  let file = spool.file.as_mut()?;
+  while let Some(chunk) = body.next().await {
+    let written = match chunk {
+      Ok(bytes) => file.write_all(&bytes).await.map_err(|err| err.to_string()),
+      Err(err) => Err(err.to_string()),
+    };
+    if let Err(err) = written {
+      logger.error("TUNNEL", &format!("Streaming response for {path} ended early: {err}"));
+      break;
+    }
+  }
+  let _ = file.flush().await;
+  spool.file = None;
+  Some(spool)
+}
+
+impl RequestBody {
+  async fn push(&mut self, bytes: &[u8]) {
+    if self.error.is_some() {
+      return;
+    }
+    if let Err(err) = self.write(bytes).await {
+      self.error = Some(format!("Failed to spool request body: {err}"));
+    }
+  }
+
+  async fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+    if self.spool.is_none() && self.memory.len() + bytes.len() <= SPOOL_THRESHOLD_BYTES {
+      self.memory.extend_from_slice(bytes);
+      return Ok(());
+    }
+    if self.spool.is_none() {
+      self.spool = Some(SpoolFile::create("upload").await?);
+    }
+    let buffered = std::mem::take(&mut self.memory);
+    if let Some(file) = self.spool.as_mut().and_then(|spool| spool.file.as_mut()) {
+      file.write_all(&buffered).await?;
+      file.write_all(bytes).await?;
+    }
     Ok(())
   }
 
-  async fn cleanup_idempotency_cache(&self) {
-    let mut cache = self.idempotency_cache.lock().await;
-    let now = Instant::now();
-    cache.retain(|_, (ts, _)| now.duration_since(*ts) <= IDEMPOTENCY_CACHE_TTL);
-    if cache.len() > IDEMPOTENCY_CACHE_MAX_SIZE {
-      let mut keys = cache.keys().cloned().collect::<Vec<_>>();
-      keys.sort_by_key(|k| cache.get(k).map(|(ts, _)| *ts));
-      for key in keys.into_iter().take(cache.len() / 5) {
-        cache.remove(&key);
-      }
+  /// Turns the body into a request body, streaming a spooled one from disk.
+  /// The returned spool file must outlive the request.
+  async fn into_body(self) -> std::result::Result<(Body, Option<SpoolFile>), String> {
+    if let Some(error) = self.error {
+      return Err(error);
     }
+    let Some(mut spool) = self.spool else {
+      return Ok((Body::from(self.memory), None));
+    };
+    if let Some(mut file) = spool.file.take() {
+      file
+        .flush()
+        .await
+        .map_err(|err| format!("Failed to spool request body: {err}"))?;
+    }
+    let file = tokio::fs::File::open(&spool.path)
+      .await
+      .map_err(|err| format!("Failed to read spooled request body: {err}"))?;
+    Ok((Body::from_stream(ReaderStream::new(file)), Some(spool)))
+  }
+}
+
+impl SpoolFile {
+  async fn create(kind: &str) -> std::io::Result<Self> {
+    let path = std::env::temp_dir().join(format!(
+      "cyberdriver-{kind}-{}.part",
+      uuid::Uuid::new_v4().simple()
+    ));
+    let file = tokio::fs::File::create(&path).await?;
+    Ok(Self { path, file: Some(file) })
+  }
+}
+
+impl Drop for SpoolFile {
+  fn drop(&mut self) {
+    self.file = None;
+    let _ = std::fs::remove_file(&self.path);
+  }
+}
+
+fn error_response(message: String) -> TunnelResponse {
+  TunnelResponse {
+    status: 500,
+    headers: [("content-type".to_string(), "text/plain".to_string())]
+      .into_iter()
+      .collect(),
+    body: message.into_bytes(),
+  }
+}
+
+/// Frames for a buffered response: meta, body chunks, then "end". The body
+/// is compressed when that makes it smaller.
+async fn response_frames(
+  meta: &RequestMeta,
+  response: TunnelResponse,
+  encoding: Option<ContentEncoding>,
+) -> Result<Vec<Message>> {
+  let (content_encoding, body) = match ContentEncoding::for_response(encoding, &response.headers) {
+    Some(encoding) => tunnel_compression::compress_body(encoding, response.body).await?,
+    None => (None, response.body),
+  };
+  let content_encoding = content_encoding.map(ContentEncoding::name);
+  let resp_meta = ResponseMeta {
+    request_id: &meta.request_id,
+    status: response.status,
+    headers: response.headers,
+    content_encoding,
+  };
+  let mut frames = vec![Message::Text(serde_json::to_string(&resp_meta)?.into())];
+  for chunk in body.chunks(RESPONSE_CHUNK_BYTES) {
+    frames.push(Message::Binary(chunk.to_vec().into()));
   }
+  frames.push(Message::Text("end".to_string().into()));
+  Ok(frames)
+}
+
+/// Input that changes the machine's state must keep its order, so it never
+/// runs concurrently with other input.
+fn runs_serially(meta: &RequestMeta) -> bool {
+  let method = meta.method.to_uppercase();
+  method != "GET"
+    && method != "HEAD"
+    && (meta.path.starts_with("/computer/input/") || meta.path == "/computer/copy_to_clipboard")
 }
 
 fn get_idempotency_key(headers: Option<&HashMap<String, String>>) -> Option<String> {
@@ -390,8 +933,230 @@ fn get_idempotency_key(headers: Option<&HashMap<String, String>>) -> Option<Stri
   })
 }
 
+fn is_streaming(headers: &HashMap<String, String>) -> bool {
+  headers
+    .get("content-type")
+    .is_some_and(|value| STREAMING_CONTENT_TYPES.iter().any(|kind| value.starts_with(kind)))
+}
+
+/// Local requests time out after 30 seconds, except endpoints that take a
+/// `timeout` in their JSON body, which get that plus a few seconds.
+fn request_timeout(path: &str, body: &[u8]) -> f64 {
+  let takes_timeout = matches!(
+    path,
+    "/computer/shell/powershell/exec"
+      | "/computer/shell/powershell/exec/stream"
+      | "/computer/fs/wait"
+  ) || (path.starts_with("/computer/processes/") && path.ends_with("/wait"));
+  let timeout = if takes_timeout {
+    extract_timeout(body).map(|t| t + 3.0).unwrap_or(30.0)
+  } else {
+    30.0
+  };
+  // Commands the shell policy may hold for the local user's approval first.
+  let policy_gated = matches!(
+    path,
+    "/computer/shell/powershell/exec"
+      | "/computer/shell/powershell/exec/stream"
+      | "/computer/processes/start"
+  );
+  if policy_gated {
+    timeout + shell_policy::APPROVAL_TIMEOUT.as_secs_f64()
+  } else {
+    timeout
+  }
+}
+
 fn extract_timeout(body: &[u8]) -> Option<f64> {
   serde_json::from_slice::<serde_json::Value>(body)
     .ok()
     .and_then(|value| value.get("timeout").and_then(|v| v.as_f64()))
 }
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  use axum::routing::{get, post};
+  use http::StatusCode;
+
+  use super::*;
+  use crate::cyberdriver::{
+    mock_tunnel::{MockRequest, MockTunnelOptions, MockTunnelServer},
+    CyberdriverSettings,
+  };
+
+  const SECRET: &str = "sk_test";
+  const WAIT: Duration = Duration::from_secs(10);
+
+  struct RunningClient {
+    stop: CancellationToken,
+    task: tokio::task::JoinHandle<()>,
+    info: Arc<Mutex<ConnectionInfo>>,
+    calls: Arc<AtomicUsize>,
+  }
+
+  impl RunningClient {
+    /// Waits for the client to give up on its own, as it does after an
+    /// auth failure.
+    async fn finished(self) -> ConnectionInfo {
+      within(self.task).await.unwrap();
+      self.info.lock().await.clone()
+    }
+
+    async fn shut_down(self) {
+      self.stop.cancel();
+      within(self.task).await.unwrap();
+    }
+  }
+
+  async fn bind() -> MockTunnelServer {
+    let options = MockTunnelOptions {
+      secret: Some(SECRET.to_string()),
+      ..MockTunnelOptions::default()
+    };
+    MockTunnelServer::bind("127.0.0.1:0", options).await.unwrap()
+  }
+
+  /// `/slow` and `/fast` answer after 300ms and at once; `/count` counts how
+  /// often it actually ran.
+  fn router(calls: Arc<AtomicUsize>) -> Router {
+    Router::new()
+      .route(
+        "/slow",
+        get(|| async {
+          tokio::time::sleep(Duration::from_millis(300)).await;
+          "slow"
+        }),
+      )
+      .route("/fast", get(|| async { "fast" }))
+      .route(
+        "/count",
+        post(move || {
+          let calls = calls.clone();
+          async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            (calls.fetch_add(1, Ordering::SeqCst) + 1).to_string()
+          }
+        }),
+      )
+  }
+
+  fn start_client(server: &MockTunnelServer) -> RunningClient {
+    let settings = CyberdriverSettings {
+      host: "127.0.0.1".to_string(),
+      port: server.local_addr().port(),
+      secret: SECRET.to_string(),
+      tunnel_scheme: "ws".to_string(),
+      proxy_use_environment: false,
+      ..CyberdriverSettings::default()
+    };
+    let calls = Arc::new(AtomicUsize::new(0));
+    let info = Arc::new(Mutex::new(ConnectionInfo::default()));
+    let client = TunnelClient::new(
+      settings.host.clone(),
+      settings.port,
+      settings.secret.clone(),
+      router(calls.clone()),
+      Config {
+        version: "test".to_string(),
+        fingerprint: "test-fingerprint".to_string(),
+      },
+      None,
+      None,
+      DebugLogger::new(false).unwrap(),
+      info.clone(),
+      8,
+      false,
+      IdempotencyCache::new(Duration::from_secs(60), 100, false),
+      ProxySettings::from_settings(&settings).unwrap(),
+      TunnelTls::from_settings(&settings).unwrap(),
+    );
+    let stop = CancellationToken::new();
+    let task = tokio::spawn(client.run(stop.clone()));
+    RunningClient {
+      stop,
+      task,
+      info,
+      calls,
+    }
+  }
+
+  async fn within<F: std::future::Future>(future: F) -> F::Output {
+    tokio::time::timeout(WAIT, future)
+      .await
+      .expect("timed out waiting for the tunnel")
+  }
+
+  #[tokio::test]
+  async fn forbidden_handshake_stops_with_auth_failure() {
+    let server = bind().await;
+    server.reject_next_handshakes(1, StatusCode::FORBIDDEN).await;
+    let client = start_client(&server);
+    let info = client.finished().await;
+    assert_eq!(info.last_error.as_deref(), Some("AUTH_FAILURE"));
+    assert!(!info.connected);
+    assert_eq!(server.handshakes().await.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn policy_close_stops_with_auth_failure() {
+    let mut server = bind().await;
+    let client = start_client(&server);
+    let connection = within(server.accept()).await.unwrap();
+    connection.close_policy("revoked").await.unwrap();
+    let info = client.finished().await;
+    assert_eq!(info.last_error.as_deref(), Some("AUTH_FAILURE"));
+    assert_eq!(server.handshakes().await.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn reconnects_after_disconnect() {
+    let mut server = bind().await;
+    let client = start_client(&server);
+    let mut connection = within(server.accept()).await.unwrap();
+    assert_eq!(connection.headers()["authorization"], format!("Bearer {SECRET}"));
+    let response = within(connection.request(&MockRequest::get("/fast"))).await.unwrap();
+    assert_eq!(response.text(), "fast");
+    connection.disconnect();
+
+    let mut connection = within(server.accept()).await.unwrap();
+    let response = within(connection.request(&MockRequest::get("/fast"))).await.unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "fast");
+    client.shut_down().await;
+  }
+
+  #[tokio::test]
+  async fn coalesces_requests_with_the_same_idempotency_key() {
+    let mut server = bind().await;
+    let client = start_client(&server);
+    let mut connection = within(server.accept()).await.unwrap();
+    let request = MockRequest::post_json("/count", &serde_json::json!({}))
+      .with_header("X-Idempotency-Key", "key-1");
+    let first = connection.send_request(&request).await.unwrap();
+    let second = connection.send_request(&request).await.unwrap();
+    let first = within(connection.response_for(&first)).await.unwrap();
+    let second = within(connection.response_for(&second)).await.unwrap();
+    let replay = within(connection.request(&request)).await.unwrap();
+    assert_eq!(first.text(), "1");
+    assert_eq!(second.text(), "1");
+    assert_eq!(replay.text(), "1");
+    assert_eq!(client.calls.load(Ordering::SeqCst), 1);
+    client.shut_down().await;
+  }
+
+  #[tokio::test]
+  async fn concurrent_responses_arrive_as_they_finish() {
+    let mut server = bind().await;
+    let client = start_client(&server);
+    let mut connection = within(server.accept()).await.unwrap();
+    let slow = connection.send_request(&MockRequest::get("/slow")).await.unwrap();
+    let fast = connection.send_request(&MockRequest::get("/fast")).await.unwrap();
+    let first = within(connection.next_response()).await.unwrap();
+    let second = within(connection.next_response()).await.unwrap();
+    assert_eq!((first.request_id.as_str(), first.text().as_str()), (fast.as_str(), "fast"));
+    assert_eq!((second.request_id.as_str(), second.text().as_str()), (slow.as_str(), "slow"));
+    client.shut_down().await;
+  }
+}
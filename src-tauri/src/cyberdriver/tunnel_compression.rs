@@ -0,0 +1,229 @@
+use std::{collections::HashMap, io::Write};
+
+use bytes::Bytes;
+use flate2::{write::GzEncoder, Compression};
+
+/// Handshake header listing the encodings the client can send. The server
+/// opts in by answering with the same header naming the ones it accepts;
+/// without it responses go out uncompressed.
+pub(super) const CONTENT_ENCODING_HEADER: &str = "X-PIGLET-CONTENT-ENCODING";
+/// What the client offers, in order of preference.
+pub(super) const OFFERED_ENCODINGS: &str = "zstd, gzip";
+/// Buffered bodies smaller than this are not worth compressing.
+const MIN_COMPRESSED_BYTES: usize = 1024;
+const ZSTD_LEVEL: i32 = 3;
+/// Pieces at least this large are compressed on the blocking pool, so the
+/// async runtime is not held up.
+const BLOCKING_COMPRESS_BYTES: usize = 64 * 1024;
+/// Content types whose bodies are already compressed.
+const COMPRESSED_CONTENT_TYPES: &[&str] = &[
+  "image/jpeg",
+  "image/png",
+  "image/gif",
+  "image/webp",
+  "image/avif",
+  "video/",
+  "audio/",
+  "font/woff",
+  "application/zip",
+  "application/gzip",
+  "application/x-gzip",
+  "application/zstd",
+  "application/x-7z-compressed",
+  "application/x-xz",
+  "application/x-bzip2",
+  "application/vnd.rar",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum ContentEncoding {
+  Gzip,
+  Zstd,
+}
+
+impl ContentEncoding {
+  /// Picks the encoding to use from the server's handshake answer,
+  /// preferring zstd.
+  pub(super) fn negotiate(accepted: Option<&str>) -> Option<Self> {
+    let accepted: Vec<String> = accepted?
+      .split(',')
+      .map(|name| name.trim().to_ascii_lowercase())
+      .collect();
+    [Self::Zstd, Self::Gzip]
+      .into_iter()
+      .find(|encoding| accepted.iter().any(|name| name == encoding.name()))
+  }
+
+  pub(super) fn name(self) -> &'static str {
+    match self {
+      Self::Gzip => "gzip",
+      Self::Zstd => "zstd",
+    }
+  }
+
+  /// The negotiated encoding, unless the response is already encoded or its
+  /// content type is a compressed format.
+  pub(super) fn for_response(
+    negotiated: Option<Self>,
+    headers: &HashMap<String, String>,
+  ) -> Option<Self> {
+    let encoded = headers
+      .get("content-encoding")
+      .is_some_and(|value| !value.trim().eq_ignore_ascii_case("identity"));
+    let compressed_type = headers.get("content-type").is_some_and(|value| {
+      let value = value.to_ascii_lowercase();
+      COMPRESSED_CONTENT_TYPES.iter().any(|kind| value.starts_with(kind))
+    });
+    negotiated.filter(|_| !encoded && !compressed_type)
+  }
+}
+
+/// Compresses a buffered body, returning `None` when it is too small or
+/// compression does not make it smaller.
+pub(super) fn compress(encoding: ContentEncoding, body: &[u8]) -> Option<Vec<u8>> {
+  if body.len() < MIN_COMPRESSED_BYTES {
+    return None;
+  }
+  let mut compressor = Compressor::new(encoding).ok()?;
+  let mut compressed = compressor.write(body).ok()?;
+  compressed.extend(compressor.finish().ok()?);
+  (compressed.len() < body.len()).then_some(compressed)
+}
+
+/// Compresses a buffered body off the async runtime when it is large,
+/// returning the encoding used, or `None` with the body unchanged when
+/// compressing does not pay off.
+pub(super) async fn compress_body(
+  encoding: ContentEncoding,
+  body: Vec<u8>,
+) -> std::io::Result<(Option<ContentEncoding>, Vec<u8>)> {
+  let large = body.len() >= BLOCKING_COMPRESS_BYTES;
+  let work = move || match compress(encoding, &body) {
+    Some(compressed) => (Some(encoding), compressed),
+    None => (None, body),
+  };
+  if !large {
+    return Ok(work());
+  }
+  tokio::task::spawn_blocking(work).await.map_err(std::io::Error::other)
+}
+
+/// Compresses a streamed body piece by piece. Each piece is flushed, so the
+/// receiver can decode everything sent so far without waiting for the end.
+pub(super) enum Compressor {
+  Gzip(GzEncoder<Vec<u8>>),
+  Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Compressor {
+  pub(super) fn new(encoding: ContentEncoding) -> std::io::Result<Self> {
+    Ok(match encoding {
+      ContentEncoding::Gzip => Self::Gzip(GzEncoder::new(Vec::new(), Compression::default())),
+      ContentEncoding::Zstd => {
+        Self::Zstd(zstd::stream::write::Encoder::new(Vec::new(), ZSTD_LEVEL)?)
+      }
+    })
+  }
+
+  /// Compresses `data` and returns the output produced so far.
+  pub(super) fn write(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    match self {
+      Self::Gzip(encoder) => {
+        encoder.write_all(data)?;
+        encoder.flush()?;
+        Ok(std::mem::take(encoder.get_mut()))
+      }
+      Self::Zstd(encoder) => {
+        encoder.write_all(data)?;
+        encoder.flush()?;
+        Ok(std::mem::take(encoder.get_mut()))
+      }
+    }
+  }
+
+  /// Like [`Compressor::write`], but large pieces are compressed on the
+  /// blocking pool. The compressor is handed back with the output.
+  pub(super) async fn write_piece(self, data: Bytes) -> std::io::Result<(Self, Vec<u8>)> {
+    let large = data.len() >= BLOCKING_COMPRESS_BYTES;
+    let work = move || {
+      let mut compressor = self;
+      let output = compressor.write(&data)?;
+      Ok((compressor, output))
+    };
+    if !large {
+      return work();
+    }
+    tokio::task::spawn_blocking(work).await.map_err(std::io::Error::other)?
+  }
+
+  /// Ends the stream and returns the remaining output.
+  pub(super) fn finish(self) -> std::io::Result<Vec<u8>> {
+    match self {
+      Self::Gzip(encoder) => encoder.finish(),
+      Self::Zstd(encoder) => encoder.finish(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn negotiates_the_preferred_accepted_encoding() {
+    assert_eq!(ContentEncoding::negotiate(None), None);
+    assert_eq!(ContentEncoding::negotiate(Some("")), None);
+    assert_eq!(ContentEncoding::negotiate(Some("br, identity")), None);
+    assert_eq!(ContentEncoding::negotiate(Some("gzip")), Some(ContentEncoding::Gzip));
+    assert_eq!(ContentEncoding::negotiate(Some(" GZIP , Zstd ")), Some(ContentEncoding::Zstd));
+    assert_eq!(ContentEncoding::negotiate(Some("zstd,gzip")), Some(ContentEncoding::Zstd));
+  }
+
+  fn decode(encoding: ContentEncoding, data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    match encoding {
+      ContentEncoding::Gzip => {
+        let mut decoder = flate2::write::GzDecoder::new(&mut decoded);
+        decoder.write_all(data).unwrap();
+        decoder.flush().unwrap();
+      }
+      ContentEncoding::Zstd => {
+        let mut decoder = zstd::stream::write::Decoder::new(&mut decoded).unwrap();
+        decoder.write_all(data).unwrap();
+        decoder.flush().unwrap();
+      }
+    }
+    decoded
+  }
+
+  fn sample(lines: usize) -> Vec<u8> {
+    (0..lines).flat_map(|line| format!("line {line} of the output\n").into_bytes()).collect()
+  }
+
+  #[test]
+  fn compressed_bodies_round_trip() {
+    for encoding in [ContentEncoding::Gzip, ContentEncoding::Zstd] {
+      let body = sample(500);
+      let compressed = compress(encoding, &body).unwrap();
+      assert!(compressed.len() < body.len());
+      assert_eq!(decode(encoding, &compressed), body);
+      assert_eq!(compress(encoding, b"too small"), None);
+    }
+  }
+
+  #[test]
+  fn every_flushed_prefix_of_a_stream_decodes() {
+    for encoding in [ContentEncoding::Gzip, ContentEncoding::Zstd] {
+      let mut compressor = Compressor::new(encoding).unwrap();
+      let mut sent = Vec::new();
+      let mut written = Vec::new();
+      for piece in [sample(3), sample(200), Vec::new(), b"partial line".to_vec()] {
+        sent.extend(compressor.write(&piece).unwrap());
+        written.extend(&piece);
+        assert_eq!(decode(encoding, &sent), written);
+      }
+      sent.extend(compressor.finish().unwrap());
+      assert_eq!(decode(encoding, &sent), written);
+    }
+  }
+}
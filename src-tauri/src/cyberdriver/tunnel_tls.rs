@@ -0,0 +1,304 @@
+use std::sync::Arc;
+
+use base64::Engine;
+use rustls::{
+  client::{
+    danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    WebPkiServerVerifier,
+  },
+  pki_types::{pem::PemObject, CertificateDer, ServerName, UnixTime},
+  ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+use tokio_tungstenite::Connector;
+
+use crate::error::{CyberdriverError, Result};
+
+use super::CyberdriverSettings;
+
+/// How the tunnel secures its WebSocket: `wss://` trusting the native roots
+/// plus an optional extra CA bundle, optionally pinned to the SPKI hash of
+/// the server's leaf certificate, or plain `ws://` for a development server
+/// on this machine.
+#[derive(Clone)]
+pub struct TunnelTls {
+  plain: bool,
+  config: Option<Arc<ClientConfig>>,
+}
+
+impl TunnelTls {
+  pub fn from_settings(settings: &CyberdriverSettings) -> Result<Self> {
+    let invalid = |message: String| CyberdriverError::RuntimeError(message);
+    let plain = match settings.tunnel_scheme.trim() {
+      "" | "wss" => false,
+      "ws" => {
+        if !is_loopback(&settings.host) {
+          return Err(invalid(
+            "Plain ws:// is only allowed for localhost development endpoints".into(),
+          ));
+        }
+        true
+      }
+      other => return Err(invalid(format!("Unsupported tunnel scheme '{other}'"))),
+    };
+
+    let bundle = settings.tunnel_ca_bundle_path.trim();
+    let pins = parse_pins(&settings.tunnel_spki_pin)?;
+    if plain || (bundle.is_empty() && pins.is_empty()) {
+      return Ok(Self { plain, config: None });
+    }
+
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+    if !bundle.is_empty() {
+      let certs = CertificateDer::pem_file_iter(bundle)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|err| invalid(format!("Failed to read CA bundle '{bundle}': {err}")))?;
+      if certs.is_empty() {
+        return Err(invalid(format!("CA bundle '{bundle}' contains no certificates")));
+      }
+      for cert in certs {
+        roots.add(cert).map_err(|err| {
+          invalid(format!("Invalid certificate in CA bundle '{bundle}': {err}"))
+        })?;
+      }
+    }
+    let roots = Arc::new(roots);
+
+    let config = if pins.is_empty() {
+      ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+    } else {
+      let inner = WebPkiServerVerifier::builder(roots)
+        .build()
+        .map_err(|err| invalid(format!("Failed to set up certificate checks: {err}")))?;
+      ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedVerifier { inner, pins }))
+        .with_no_client_auth()
+    };
+    Ok(Self {
+      plain,
+      config: Some(Arc::new(config)),
+    })
+  }
+
+  pub fn scheme(&self) -> &'static str {
+    if self.plain { "ws" } else { "wss" }
+  }
+
+  /// The connector for the WebSocket handshake; `None` keeps the default
+  /// native-roots configuration.
+  pub fn connector(&self) -> Option<Connector> {
+    if self.plain {
+      return Some(Connector::Plain);
+    }
+    self.config.clone().map(Connector::Rustls)
+  }
+}
+
+/// Accepts a chain only if the normal verification passes and the server's
+/// own (leaf) certificate has a pinned SPKI hash. Intermediates are not
+/// matched: the server can send any extra certificates it likes, and they
+/// need not be part of the path that was actually verified.
+#[derive(Debug)]
+struct PinnedVerifier {
+  inner: Arc<WebPkiServerVerifier>,
+  pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+  fn verify_server_cert(
+    &self,
+    end_entity: &CertificateDer<'_>,
+    intermediates: &[CertificateDer<'_>],
+    server_name: &ServerName<'_>,
+    ocsp_response: &[u8],
+    now: UnixTime,
+  ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+    let verified = self.inner.verify_server_cert(
+      end_entity,
+      intermediates,
+      server_name,
+      ocsp_response,
+      now,
+    )?;
+    let pinned = subject_public_key_info(end_entity)
+      .is_some_and(|spki| self.pins.contains(&Sha256::digest(spki).into()));
+    if pinned {
+      Ok(verified)
+    } else {
+      Err(rustls::Error::General(
+        "server certificate does not match the configured SPKI pin".into(),
+      ))
+    }
+  }
+
+  fn verify_tls12_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+    self.inner.verify_tls12_signature(message, cert, dss)
+  }
+
+  fn verify_tls13_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+    self.inner.verify_tls13_signature(message, cert, dss)
+  }
+
+  fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+    self.inner.supported_verify_schemes()
+  }
+}
+
+/// Parses pins written as `sha256/<base64>` (or bare base64), separated by
+/// commas or whitespace so a replacement key can be pinned ahead of a
+/// rotation.
+fn parse_pins(value: &str) -> Result<Vec<[u8; 32]>> {
+  value
+    .split(|c: char| c == ',' || c.is_whitespace())
+    .filter(|pin| !pin.is_empty())
+    .map(|pin| {
+      let encoded = pin.strip_prefix("sha256/").unwrap_or(pin);
+      base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .ok()
+        .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+        .ok_or_else(|| {
+          CyberdriverError::RuntimeError(format!(
+            "Invalid SPKI pin '{pin}': expected sha256/ followed by a base64 SHA-256 hash"
+          ))
+        })
+    })
+    .collect()
+}
+
+fn is_loopback(host: &str) -> bool {
+  let host = host
+    .trim_start_matches("https://")
+    .trim_start_matches("http://")
+    .trim_end_matches('/');
+  let host = host.trim_start_matches('[').trim_end_matches(']');
+  host.eq_ignore_ascii_case("localhost")
+    || host
+      .parse::<std::net::IpAddr>()
+      .is_ok_and(|address| address.is_loopback())
+}
+
+/// The DER `SubjectPublicKeyInfo` of an X.509 certificate.
+fn subject_public_key_info(cert: &[u8]) -> Option<&[u8]> {
+  let (_, certificate, _) = der_element(cert, 0x30)?;
+  let (_, mut tbs, _) = der_element(certificate, 0x30)?;
+  // The explicitly tagged version is optional.
+  if tbs.first() == Some(&0xa0) {
+    tbs = der_element(tbs, 0xa0)?.2;
+  }
+  // serialNumber, signature, issuer, validity and subject come first.
+  for _ in 0..5 {
+    tbs = der_any(tbs)?.2;
+  }
+  Some(der_element(tbs, 0x30)?.0)
+}
+
+/// Splits the first element off `data` if it has tag `tag`, returning the
+/// whole element, its contents and what follows it.
+fn der_element(data: &[u8], tag: u8) -> Option<(&[u8], &[u8], &[u8])> {
+  if data.first() != Some(&tag) {
+    return None;
+  }
+  der_any(data)
+}
+
+fn der_any(data: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+  let first = *data.get(1)?;
+  let (header, length) = if first < 0x80 {
+    (2, first as usize)
+  } else {
+    let count = (first & 0x7f) as usize;
+    if count == 0 || count > 4 {
+      return None;
+    }
+    let length = data
+      .get(2..2 + count)?
+      .iter()
+      .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+    (2 + count, length)
+  };
+  let end = header.checked_add(length)?;
+  if end > data.len() {
+    return None;
+  }
+  Some((&data[..end], &data[header..end], &data[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A DER element with a short or long form length.
+  fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut element = vec![tag];
+    match contents.len() {
+      length @ 0..=0x7f => element.push(length as u8),
+      length @ 0x80..=0xff => element.extend([0x81, length as u8]),
+      length => element.extend([0x82, (length >> 8) as u8, length as u8]),
+    }
+    element.extend_from_slice(contents);
+    element
+  }
+
+  /// A certificate skeleton: only the structure the SPKI lookup walks.
+  fn certificate(version: bool, spki: &[u8]) -> Vec<u8> {
+    let mut tbs = Vec::new();
+    if version {
+      tbs.extend(der(0xa0, &der(0x02, &[2])));
+    }
+    tbs.extend(der(0x02, &[0x01, 0x23]));
+    tbs.extend(der(0x30, &der(0x06, &[0x2a, 0x86, 0x48])));
+    tbs.extend(der(0x30, b"issuer"));
+    tbs.extend(der(0x30, b"validity"));
+    tbs.extend(der(0x30, b"subject"));
+    tbs.extend_from_slice(spki);
+    tbs.extend(der(0xa3, b"extensions"));
+    let mut cert = der(0x30, &tbs);
+    cert.extend(der(0x30, &der(0x06, &[0x2a, 0x86, 0x48])));
+    cert.extend(der(0x03, &[0, 1, 2, 3]));
+    der(0x30, &cert)
+  }
+
+  #[test]
+  fn finds_the_subject_public_key_info() {
+    let spki = der(0x30, &der(0x03, &[0x55; 300]));
+    assert_eq!(subject_public_key_info(&certificate(true, &spki)), Some(spki.as_slice()));
+    assert_eq!(subject_public_key_info(&certificate(false, &spki)), Some(spki.as_slice()));
+  }
+
+  #[test]
+  fn rejects_malformed_certificates() {
+    let spki = der(0x30, &der(0x03, &[0x55; 16]));
+    let cert = certificate(true, &spki);
+    assert_eq!(subject_public_key_info(&cert[..cert.len() - 1]), None);
+    assert_eq!(subject_public_key_info(&[]), None);
+    assert_eq!(subject_public_key_info(&der(0x31, &cert)), None);
+    assert_eq!(subject_public_key_info(&certificate(true, &der(0x04, b"not a sequence"))), None);
+  }
+
+  #[test]
+  fn parses_pins() {
+    let hash = [7u8; 32];
+    let encoded = base64::engine::general_purpose::STANDARD.encode(hash);
+    let pins = parse_pins(&format!(" sha256/{encoded},\n{encoded} ")).unwrap();
+    assert_eq!(pins, vec![hash, hash]);
+    assert!(parse_pins("").unwrap().is_empty());
+    assert!(parse_pins("sha256/not-base64").is_err());
+    let short = base64::engine::general_purpose::STANDARD.encode([7u8; 20]);
+    assert!(parse_pins(&format!("sha256/{short}")).is_err());
+  }
+}
@@ -6,7 +6,7 @@ use tokio::sync::Mutex;
 
 use crate::error::{CyberdriverError, Result};
 
-use super::config::ConnectionInfo;
+use super::{config::ConnectionInfo, proxy::ProxySettings};
 
 const GITHUB_RELEASES_API_URL: &str = "https://api.github.com/repos/cyberdesk-hq/cyberdriver/releases";
 const GITHUB_DOWNLOAD_BASE_URL: &str =
@@ -40,6 +40,7 @@ pub async fn handle_update(
   payload: UpdateRequest,
   connection_info: &Mutex<ConnectionInfo>,
   current_version: &str,
+  proxy: &ProxySettings,
 ) -> Result<UpdateResponse> {
   if !cfg!(windows) {
     return Err(CyberdriverError::RuntimeError(
@@ -50,7 +51,7 @@ pub async fn handle_update(
     .map_err(|err| CyberdriverError::RuntimeError(err.to_string()))?;
   let mut target_version = payload.version;
   if target_version == "latest" {
-    target_version = resolve_latest_version(connection_info).await?.ok_or_else(|| {
+    target_version = resolve_latest_version(connection_info, proxy).await?.ok_or_else(|| {
       CyberdriverError::RuntimeError("Could not determine latest version".into())
     })?;
   }
@@ -71,7 +72,8 @@ pub async fn handle_update(
     .to_path_buf();
   let staging_exe = tool_dir.join("cyberdriver-update.exe");
 
-  let response = reqwest::Client::new()
+  let response = proxy
+    .http_client()?
     .get(&download_url)
     .timeout(Duration::from_secs(120))
     .send()
@@ -153,15 +155,19 @@ fn is_version_at_least(current: &str, target: &str) -> bool {
   c >= t
 }
 
-async fn resolve_latest_version(connection_info: &Mutex<ConnectionInfo>) -> Result<Option<String>> {
-  if let Some(version) = fetch_latest_version_from_api(connection_info).await? {
+async fn resolve_latest_version(
+  connection_info: &Mutex<ConnectionInfo>,
+  proxy: &ProxySettings,
+) -> Result<Option<String>> {
+  if let Some(version) = fetch_latest_version_from_api(connection_info, proxy).await? {
     return Ok(Some(version));
   }
-  fetch_latest_version_from_github().await
+  fetch_latest_version_from_github(proxy).await
 }
 
 async fn fetch_latest_version_from_api(
   connection_info: &Mutex<ConnectionInfo>,
+  proxy: &ProxySettings,
 ) -> Result<Option<String>> {
   let info = connection_info.lock().await;
   let (host, port) = match (&info.host, info.port) {
@@ -170,7 +176,8 @@ async fn fetch_latest_version_from_api(
   };
   let protocol = if port == 443 { "https" } else { "http" };
   let url = format!("{protocol}://{host}/v1/internal/cyberdriver-version");
-  let response = reqwest::Client::new()
+  let response = proxy
+    .http_client()?
     .get(url)
     .timeout(Duration::from_secs(10))
     .send()
@@ -189,8 +196,9 @@ async fn fetch_latest_version_from_api(
   Ok(None)
 }
 
-async fn fetch_latest_version_from_github() -> Result<Option<String>> {
-  let response = reqwest::Client::new()
+async fn fetch_latest_version_from_github(proxy: &ProxySettings) -> Result<Option<String>> {
+  let response = proxy
+    .http_client()?
     .get(GITHUB_RELEASES_API_URL)
     .header("Accept", "application/vnd.github.v3+json")
     .timeout(Duration::from_secs(30))
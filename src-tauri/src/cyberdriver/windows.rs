@@ -5,8 +5,9 @@ use tauri::AppHandle;
 use windows::core::w;
 #[cfg(windows)]
 use windows::Win32::UI::Input::KeyboardAndMouse::{
-  GetKeyState, SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_EXTENDEDKEY,
-  KEYBD_EVENT_FLAGS, KEYEVENTF_KEYUP, KEYEVENTF_SCANCODE, VIRTUAL_KEY, VK_CAPITAL, VK_SPACE,
+  GetKeyState, SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, INPUT_MOUSE, KEYBDINPUT,
+  KEYEVENTF_EXTENDEDKEY, KEYBD_EVENT_FLAGS, KEYEVENTF_KEYUP, KEYEVENTF_SCANCODE, MOUSEEVENTF_HWHEEL,
+  MOUSEEVENTF_WHEEL, MOUSEINPUT, VIRTUAL_KEY, VK_CAPITAL, VK_SPACE,
 };
 
 #[cfg(windows)]
@@ -165,6 +166,27 @@ pub fn send_vk_space(key_up: bool) {
   }
 }
 
+#[cfg(windows)]
+pub fn send_wheel(delta: i32, horizontal: bool) {
+  let flags = if horizontal { MOUSEEVENTF_HWHEEL } else { MOUSEEVENTF_WHEEL };
+  let input = INPUT {
+    r#type: INPUT_MOUSE,
+    Anonymous: INPUT_0 {
+      mi: MOUSEINPUT {
+        dx: 0,
+        dy: 0,
+        mouseData: delta as _,
+        dwFlags: flags,
+        time: 0,
+        dwExtraInfo: 0,
+      },
+    },
+  };
+  unsafe {
+    let _ = SendInput(&[input], std::mem::size_of::<INPUT>() as i32);
+  }
+}
+
 #[cfg(not(windows))]
 #[allow(dead_code)]
 pub fn caps_lock_is_on() -> bool {
@@ -176,3 +198,7 @@ pub fn send_scancode(_scan_code: u16, _key_up: bool) {}
 
 #[cfg(not(windows))]
 pub fn send_vk_space(_key_up: bool) {}
+
+#[cfg(not(windows))]
+#[allow(dead_code)]
+pub fn send_wheel(_delta: i32, _horizontal: bool) {}
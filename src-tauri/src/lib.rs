@@ -2,6 +2,9 @@ mod cyberdriver;
 mod commands;
 mod error;
 
+#[cfg(any(test, feature = "mock-tunnel"))]
+pub use cyberdriver::mock_tunnel;
+
 use tauri::Manager;
 use tokio::sync::Mutex;
 
@@ -22,6 +25,9 @@ pub fn run() {
     .plugin(tauri_plugin_http::init())
     .plugin(tauri_plugin_store::Builder::new().build())
     .plugin(tauri_plugin_opener::init())
+    .plugin(tauri_plugin_notification::init())
+    .plugin(tauri_plugin_dialog::init())
+    .plugin(tauri_plugin_global_shortcut::Builder::new().build())
     .invoke_handler(tauri::generate_handler![
       commands::cyberdriver::get_cyberdriver_status,
       commands::cyberdriver::start_local_api,
@@ -34,6 +40,8 @@ pub fn run() {
       commands::cyberdriver::install_persistent_display,
       commands::cyberdriver::get_cyberdriver_log_dir,
       commands::cyberdriver::get_recent_logs,
+      commands::cyberdriver::set_remote_control_frozen,
+      commands::cyberdriver::answer_shell_approval,
       commands::window::open_floating_window,
       commands::window::open_image_preview,
       commands::window::open_coord_capture,
@@ -10,6 +10,10 @@ type CyberdriverStatus = {
   keepalive_enabled: boolean;
   black_screen_recovery: boolean;
   debug_enabled: boolean;
+  remote_control_frozen: boolean;
+  local_user_active: boolean;
+  local_user_idle_seconds?: number | null;
+  remote_input_paused: boolean;
   last_error?: string | null;
   machine_uuid: string;
   version: string;
@@ -30,6 +34,29 @@ type CyberdriverSettings = {
   register_as_keepalive_for: string | null;
   experimental_space: boolean;
   driver_path: string | null;
+  emergency_stop_shortcut: string;
+  yield_to_local_user: boolean;
+  local_user_idle_seconds: number;
+  local_user_yield_mode: string;
+  fs_allowed_roots: string[];
+  fs_denied_roots: string[];
+  fs_read_only: boolean;
+  shell_allowed_commands: string[];
+  shell_denied_commands: string[];
+  shell_unlisted_action: string;
+  shell_run_as_user: string;
+  tunnel_max_concurrent_requests: number;
+  tunnel_compression: boolean;
+  idempotency_cache_ttl_seconds: number;
+  idempotency_cache_max_entries: number;
+  idempotency_cache_persist: boolean;
+  proxy_url: string;
+  proxy_username: string;
+  proxy_password: string;
+  proxy_use_environment: boolean;
+  tunnel_scheme: string;
+  tunnel_ca_bundle_path: string;
+  tunnel_spki_pin: string;
 };
 
 const defaultSettings: CyberdriverSettings = {
@@ -47,6 +74,29 @@ const defaultSettings: CyberdriverSettings = {
   register_as_keepalive_for: null,
   experimental_space: false,
   driver_path: null,
+  emergency_stop_shortcut: 'CommandOrControl+Shift+F12',
+  yield_to_local_user: false,
+  local_user_idle_seconds: 5,
+  local_user_yield_mode: 'reject',
+  fs_allowed_roots: [],
+  fs_denied_roots: [],
+  fs_read_only: false,
+  shell_allowed_commands: [],
+  shell_denied_commands: [],
+  shell_unlisted_action: 'allow',
+  shell_run_as_user: '',
+  tunnel_max_concurrent_requests: 8,
+  tunnel_compression: true,
+  idempotency_cache_ttl_seconds: 60,
+  idempotency_cache_max_entries: 1000,
+  idempotency_cache_persist: false,
+  proxy_url: '',
+  proxy_username: '',
+  proxy_password: '',
+  proxy_use_environment: true,
+  tunnel_scheme: 'wss',
+  tunnel_ca_bundle_path: '',
+  tunnel_spki_pin: '',
 };
 
 type SaveState = 'idle' | 'saving' | 'saved' | 'error';
@@ -236,6 +286,16 @@ const App: React.FC = () => {
     }
   };
 
+  const handleToggleFrozen = async () => {
+    setError('');
+    try {
+      await invoke('set_remote_control_frozen', { frozen: !status?.remote_control_frozen });
+      await refreshStatus();
+    } catch (err) {
+      setError(String(err));
+    }
+  };
+
   const handleClearConfig = async () => {
     if (!window.confirm('Clear config file and generate a new machine UUID?')) {
       return;
@@ -302,9 +362,33 @@ const App: React.FC = () => {
             >
               {action === 'stop' ? 'Stopping…' : 'Stop'}
             </button>
+            <button
+              className={`rounded-lg px-4 py-2 text-sm font-semibold ${
+                status?.remote_control_frozen
+                  ? 'border border-emerald-200 bg-emerald-50 text-emerald-700'
+                  : 'border border-red-200 bg-red-50 text-red-700'
+              }`}
+              onClick={handleToggleFrozen}
+            >
+              {status?.remote_control_frozen ? 'Resume Control' : 'Emergency Stop'}
+            </button>
           </div>
         </header>
 
+        {status?.remote_control_frozen && (
+          <div className="rounded-lg border border-amber-200 bg-amber-50 px-4 py-3 text-sm text-amber-800">
+            Remote control is frozen. Input, file and shell requests are rejected until you resume
+            {settings.emergency_stop_shortcut ? ` (${settings.emergency_stop_shortcut})` : ''}.
+          </div>
+        )}
+
+        {status?.remote_input_paused && !status?.remote_control_frozen && (
+          <div className="rounded-lg border border-accent-b-2 bg-white px-4 py-3 text-sm text-accent-b-0">
+            You are using this machine, so remote input is paused until you have been idle for{' '}
+            {settings.local_user_idle_seconds}s.
+          </div>
+        )}
+
         {error && (
           <div className="rounded-lg border border-red-200 bg-red-50 px-4 py-3 text-sm text-red-700">
             {error}
@@ -378,6 +462,114 @@ const App: React.FC = () => {
                 onChange={e => updateField('target_port', Number(e.target.value))}
               />
             </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Concurrent Tunnel Requests
+              <input
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                type="number"
+                min={1}
+                value={settings.tunnel_max_concurrent_requests}
+                onChange={e => updateField('tunnel_max_concurrent_requests', Number(e.target.value))}
+              />
+            </label>
+            <label className="flex items-center gap-2 text-sm">
+              <input
+                type="checkbox"
+                checked={settings.tunnel_compression}
+                onChange={e => updateField('tunnel_compression', e.target.checked)}
+              />
+              Compress tunnel responses when the server supports it
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Idempotency Cache TTL (seconds)
+              <input
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                type="number"
+                min={0}
+                value={settings.idempotency_cache_ttl_seconds}
+                onChange={e => updateField('idempotency_cache_ttl_seconds', Number(e.target.value))}
+              />
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Idempotency Cache Size (entries)
+              <input
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                type="number"
+                min={1}
+                value={settings.idempotency_cache_max_entries}
+                onChange={e => updateField('idempotency_cache_max_entries', Number(e.target.value))}
+              />
+            </label>
+            <label className="flex items-center gap-2 text-sm">
+              <input
+                type="checkbox"
+                checked={settings.idempotency_cache_persist}
+                onChange={e => updateField('idempotency_cache_persist', e.target.checked)}
+              />
+              Keep idempotent responses on disk across restarts
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Proxy URL
+              <input
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                value={settings.proxy_url}
+                onChange={e => updateField('proxy_url', e.target.value)}
+                placeholder="http://proxy:8080, socks5://proxy:1080 or socks5h://proxy:1080"
+              />
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Proxy Username
+              <input
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                value={settings.proxy_username}
+                onChange={e => updateField('proxy_username', e.target.value)}
+              />
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Proxy Password
+              <input
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                type="password"
+                value={settings.proxy_password}
+                onChange={e => updateField('proxy_password', e.target.value)}
+              />
+            </label>
+            <label className="flex items-center gap-2 text-sm">
+              <input
+                type="checkbox"
+                checked={settings.proxy_use_environment}
+                onChange={e => updateField('proxy_use_environment', e.target.checked)}
+              />
+              Use HTTPS_PROXY from the environment when no proxy is set
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Tunnel Scheme
+              <select
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                value={settings.tunnel_scheme}
+                onChange={e => updateField('tunnel_scheme', e.target.value)}
+              >
+                <option value="wss">wss:// (TLS)</option>
+                <option value="ws">ws:// (localhost development only)</option>
+              </select>
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Extra CA Bundle (PEM file path)
+              <input
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                value={settings.tunnel_ca_bundle_path}
+                onChange={e => updateField('tunnel_ca_bundle_path', e.target.value)}
+              />
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              SPKI Pin
+              <input
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                value={settings.tunnel_spki_pin}
+                onChange={e => updateField('tunnel_spki_pin', e.target.value)}
+                placeholder="sha256/base64hash"
+              />
+            </label>
           </div>
 
           <div className="text-xs text-accent-b-0">
@@ -486,6 +678,110 @@ const App: React.FC = () => {
               />
               Experimental Space Key (Windows)
             </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Emergency Stop Shortcut
+              <input
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                value={settings.emergency_stop_shortcut}
+                onChange={e => updateField('emergency_stop_shortcut', e.target.value)}
+                placeholder="Leave empty to disable"
+              />
+            </label>
+            <label className="flex items-center gap-2 text-sm">
+              <input
+                type="checkbox"
+                checked={settings.yield_to_local_user}
+                onChange={e => updateField('yield_to_local_user', e.target.checked)}
+              />
+              Yield to Local User
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Local Idle Threshold (seconds)
+              <input
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                type="number"
+                value={settings.local_user_idle_seconds}
+                onChange={e => updateField('local_user_idle_seconds', Number(e.target.value))}
+              />
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              While Local User Is Active
+              <select
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                value={settings.local_user_yield_mode}
+                onChange={e => updateField('local_user_yield_mode', e.target.value)}
+              >
+                <option value="reject">Reject remote input (retry later)</option>
+                <option value="queue">Queue remote input until idle</option>
+              </select>
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Allowed Filesystem Roots (one per line)
+              <textarea
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                rows={3}
+                value={settings.fs_allowed_roots.join('\n')}
+                onChange={e => updateField('fs_allowed_roots', e.target.value.split('\n'))}
+                placeholder="Leave empty to allow all paths"
+              />
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Denied Filesystem Roots (one per line)
+              <textarea
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                rows={3}
+                value={settings.fs_denied_roots.join('\n')}
+                onChange={e => updateField('fs_denied_roots', e.target.value.split('\n'))}
+              />
+            </label>
+            <label className="flex items-center gap-2 text-sm">
+              <input
+                type="checkbox"
+                checked={settings.fs_read_only}
+                onChange={e => updateField('fs_read_only', e.target.checked)}
+              />
+              Read-only Filesystem Access
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Allowed Shell Commands (one per line, /regex/ or executable)
+              <textarea
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                rows={3}
+                value={settings.shell_allowed_commands.join('\n')}
+                onChange={e => updateField('shell_allowed_commands', e.target.value.split('\n'))}
+                placeholder="e.g. git or /^ls( |$)/"
+              />
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Denied Shell Commands (one per line, /regex/ or executable)
+              <textarea
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                rows={3}
+                value={settings.shell_denied_commands.join('\n')}
+                onChange={e => updateField('shell_denied_commands', e.target.value.split('\n'))}
+              />
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Other Shell Commands
+              <select
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                value={settings.shell_unlisted_action}
+                onChange={e => updateField('shell_unlisted_action', e.target.value)}
+              >
+                <option value="allow">Allow</option>
+                <option value="ask">Ask me to approve each one</option>
+                <option value="deny">Deny</option>
+              </select>
+            </label>
+            <label className="flex flex-col gap-1 text-sm">
+              Run Shell Commands As User (macOS/Linux, needs root)
+              <input
+                className="rounded-lg border border-accent-b-2 px-3 py-2"
+                value={settings.shell_run_as_user}
+                onChange={e => updateField('shell_run_as_user', e.target.value)}
+                placeholder="Current user"
+              />
+            </label>
           </div>
           <div className="mt-4 flex flex-wrap gap-2">
             <button
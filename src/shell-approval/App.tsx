@@ -0,0 +1,41 @@
+import React, { useEffect, useState } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+
+const params = new URLSearchParams(location.search);
+const id = params.get('id') ?? '';
+const command = params.get('command') ?? '';
+const timeoutSeconds = Number(params.get('seconds')) || 0;
+
+const answer = (approved: boolean) => invoke('answer_shell_approval', { id, approved });
+
+const App: React.FC = () => {
+  const [remaining, setRemaining] = useState(timeoutSeconds);
+
+  useEffect(() => {
+    const timer = setInterval(() => setRemaining(value => Math.max(0, value - 1)), 1000);
+    return () => clearInterval(timer);
+  }, []);
+
+  return (
+    <div className="h-screen w-screen flex flex-col gap-3 p-4 text-sm">
+      <div className="text-base font-semibold">A remote agent wants to run this command</div>
+      <pre className="flex-1 overflow-auto whitespace-pre-wrap break-all rounded-lg bg-black/5 p-3 font-mono text-xs">
+        {command}
+      </pre>
+      <div className="opacity-70">It is denied automatically in {remaining} seconds.</div>
+      <div className="flex justify-end gap-2">
+        <button className="rounded-lg border px-4 py-2" onClick={() => answer(false)}>
+          Deny
+        </button>
+        <button
+          className="rounded-lg bg-red-600 px-4 py-2 text-white"
+          onClick={() => answer(true)}
+        >
+          Allow
+        </button>
+      </div>
+    </div>
+  );
+};
+
+export default App;
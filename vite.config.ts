@@ -37,6 +37,7 @@ export default defineConfig({
         floating: path.resolve(__dirname, 'windows/floating.html'),
         image: path.resolve(__dirname, 'windows/image-preview.html'),
         coordCapture: path.resolve(__dirname, 'windows/coord-capture.html'),
+        shellApproval: path.resolve(__dirname, 'windows/shell-approval.html'),
       },
     },
   },